@@ -1,105 +1,133 @@
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use validator::poh_handler::PohEntry;
-use validator::transaction::Transaction;
-use validator::registration::Validator;
-use crate::network::Stake;
-use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
-use tokio::io::AsyncWriteExt;
-use std::time::{SystemTime, UNIX_EPOCH};
-use sha2::{Sha256, Digest}; 
+use validator::block::Message;
+use validator::clock::{sleep_until, Clock};
+use validator::handlers::BlockProducer;
+use crate::PoHGenerator;
+use crate::pubsub::Event;
+use validator::metrics::metrics;
+use tracing::{debug, info, info_span, warn, Instrument};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block {
-    pub parent_hash: String,
-    pub block_hash: String,
-    pub block_height: u64,
-    pub timestamp: u64,
-    pub transactions: Vec<Transaction>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub enum Message {
-    PoHEntries(Vec<PohEntry>),
-    BlockProposal(Block),
-    ConsensusVote(Block),
-    RegisterValidator(Validator),
-    StakeTokens(Stake),
-    Transaction(Transaction),
-}
+/// Produces a block every slot on `clock`, starting one slot from now.
+pub async fn propose_block(node: Arc<PoHGenerator>, clock: impl Clock) {
+    let slot_duration_ms = node.poh_timing.slot_duration_ms();
+    let mut next_slot_ms = clock.now_ms() + slot_duration_ms;
 
-pub async fn propose_block(
-    poh: Arc<Mutex<Vec<PohEntry>>>,
-    validators: Arc<Mutex<HashMap<String, usize>>>,
-    _votes: Arc<Mutex<HashMap<String, bool>>>,
-    transactions: Arc<Mutex<Vec<Transaction>>>,
-    parent_hash: Arc<Mutex<[u8; 32]>>,
-    block_height: Arc<Mutex<u64>>,
-) {
     loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-
-        let block_transactions;
-        {
-            let txs_guard = transactions.lock().await;
-            block_transactions = txs_guard.clone();
-        }
-
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        sleep_until(&clock, next_slot_ms).await;
+        let slot = *node.block_height.lock().await + 1;
+        produce_block(&node, &clock).instrument(info_span!("slot", slot)).await;
+        // A slot that overran starts the next one late rather than in a burst
+        next_slot_ms = (next_slot_ms + slot_duration_ms).max(clock.now_ms());
+    }
+}
 
-        let current_parent_hash = *parent_hash.lock().await;
-        let current_block_height = *block_height.lock().await;
+/// Builds, stores and broadcasts the block for the next slot.
+pub(crate) async fn produce_block(node: &PoHGenerator, clock: &impl Clock) {
+    let epoch_schedule = &node.genesis.epoch_schedule;
 
-        let block_hash = generate_block_hash(&current_parent_hash, current_block_height, timestamp, &block_transactions);
+    let pending = std::mem::take(&mut *node.transactions.lock().await);
+    metrics().mempool_size.set(0);
 
-        let block = Block {
-            parent_hash: hex::encode(current_parent_hash),
-            block_hash: hex::encode(block_hash),
-            block_height: current_block_height + 1,
-            timestamp,
-            transactions: block_transactions,
-        };
+    let current_parent_hash = *node.parent_hash.lock().await;
+    let current_block_height = *node.block_height.lock().await;
 
-        {
-            let mut parent_hash_lock = parent_hash.lock().await;
-            *parent_hash_lock = block_hash;
-            let mut block_height_lock = block_height.lock().await;
-            *block_height_lock += 1;
+    let block_evidence = node.evidence_pool.lock().await.take_pending();
+    let pending_stake = std::mem::take(&mut *node.stake_instructions.lock().await);
+    // The bank changes, the block recording them and any epoch rewards land
+    // under the blockstore lock, which snapshots also hold while reading the
+    // bank, so a snapshot at a slot always has exactly that slot's bank
+    let produced = {
+        let mut blockstore = node.blockstore.lock().await;
+        let mut balances = node.balances.lock().await;
+        let mut stakes = node.stakes.lock().await;
+        let mut vote_credits = node.vote_credits.lock().await;
+        BlockProducer {
+            identity: &node.identity,
+            genesis: &node.genesis,
+            blockstore: &mut blockstore,
+            balances: &mut balances,
+            stakes: &mut stakes,
+            vote_credits: &mut vote_credits,
         }
+        .produce_block(clock, current_parent_hash, current_block_height, pending, block_evidence, pending_stake)
+    };
+    if let Some(rewards) = produced.rewards {
+        *node.epoch_rewards.lock().await = rewards;
+    }
+    let block = produced.block;
+    node.status_cache.lock().await.push_blockhash(block.block_hash.clone());
+
+    {
+        let mut parent_hash_lock = node.parent_hash.lock().await;
+        parent_hash_lock.copy_from_slice(&hex::decode(&block.block_hash).expect("block hash is hex"));
+        let mut block_height_lock = node.block_height.lock().await;
+        *block_height_lock += 1;
+    }
 
-        println!("Proposing new block: {:?}", block);
+    let horizon = current_block_height.saturating_sub(epoch_schedule.slots_per_epoch);
+    node.conflict_detector.lock().await.purge_below(horizon);
+    node.evidence_pool.lock().await.purge_below(horizon);
 
-        let serialized_block = serde_json::to_string(&Message::BlockProposal(block.clone())).unwrap();
-        let message_length = (serialized_block.len() as u32).to_be_bytes();
+    metrics().blocks_produced.inc();
+    info!(
+        "Proposed block with {} transactions and {} stake instructions",
+        block.transactions.len(),
+        block.stake_instructions.len()
+    );
+    debug!("Proposed block: {:?}", block);
+    // Nobody may be subscribed, in which case the event is just dropped
+    let _ = node.events.send(Event::Block(block.clone()));
 
-        for validator in validators.lock().await.keys() {
-            if let Ok(mut stream) = tokio::net::TcpStream::connect(validator).await {
-                if stream.write_all(&message_length).await.is_ok() {
-                    if stream.write_all(serialized_block.as_bytes()).await.is_ok() {
-                        println!("Sent block proposal to {}", validator);
-                    }
+    // Validators are reached at the tvu address they publish in gossip
+    let validators: Vec<String> = node.validators.lock().await.keys().cloned().collect();
+    for validator in validators {
+        match node.cluster.lookup(&validator).await {
+            Some(info) => {
+                if node.connections.send(&validator, &info.tvu_addr, Message::BlockProposal(block.clone())).await {
+                    debug!("Sent block proposal to {}", validator);
                 }
             }
+            None => warn!("No contact info for validator {}, skipping block proposal", validator),
         }
     }
 }
 
-fn generate_block_hash(
-    parent_hash: &[u8],
-    block_height: u64,
-    timestamp: u64,
-    transactions: &[Transaction],
-) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(parent_hash);
-    hasher.update(&block_height.to_be_bytes());
-    hasher.update(&timestamp.to_be_bytes());
-    for tx in transactions {
-        hasher.update(&serde_json::to_vec(tx).unwrap());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::block::verify_block_hash;
+    use validator::clock::SystemClock;
+    use validator::keypair::Keypair;
+    use validator::stake::Stake;
+    use crate::submit;
+
+    #[tokio::test]
+    async fn stake_instructions_apply_in_blocks_and_replay() {
+        let alice = Keypair::from_seed([2; 32]);
+        let accounts = [(alice.pubkey(), 100)];
+        let node = PoHGenerator::for_tests(&accounts);
+        let blockhash = hex::encode(*node.parent_hash.lock().await);
+
+        let stake = Stake::new(&alice, "validator_a".to_string(), 30, blockhash.clone());
+        submit::delegate_stake(&node, &stake).await.unwrap();
+        // Checked against the bank as it was, so both are queued, but only one fits
+        let overdraft = Stake::new(&alice, "validator_a".to_string(), 80, blockhash);
+        submit::delegate_stake(&node, &overdraft).await.unwrap();
+        produce_block(&node, &SystemClock).await;
+
+        let block = node.blockstore.lock().await.get(1).cloned().unwrap();
+        assert_eq!(block.stake_instructions.len(), 1);
+        assert!(verify_block_hash(&block));
+        assert!(node.stake_instructions.lock().await.is_empty());
+        assert_eq!(node.balances.lock().await[&alice.pubkey()], 70);
+        assert_eq!(node.stakes.lock().await.get(&stake.stake_account()).unwrap().amount, 30);
+        assert!(node.stakes.lock().await.get(&overdraft.stake_account()).is_none());
+
+        // A node restarting from genesis gets the same bank from the ledger
+        let restarted = PoHGenerator::for_tests(&accounts);
+        restarted.blockstore.lock().await.insert(block).unwrap();
+        assert_eq!(crate::snapshot::replay_blocks(&restarted, 0).await.unwrap(), 1);
+        assert_eq!(restarted.balances.lock().await[&alice.pubkey()], 70);
+        assert_eq!(restarted.stakes.lock().await.get(&stake.stake_account()).unwrap().amount, 30);
     }
-    let result = hasher.finalize();
-    let mut hash = [0u8; 32];
-    hash.copy_from_slice(&result);
-    hash
 }
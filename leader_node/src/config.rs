@@ -0,0 +1,257 @@
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use validator::blockstore::DEFAULT_LEDGER_DIR;
+use validator::config::{check_distinct_addrs, load_toml};
+use validator::genesis::{PohConfig, DEFAULT_GENESIS_PATH};
+use validator::gossip::DEFAULT_GOSSIP_FANOUT;
+use validator::logging::LogFormat;
+use validator::rpc_client::DEFAULT_RPC_ADDR;
+use validator::snapshot::DEFAULT_SNAPSHOT_INTERVAL_SLOTS;
+use crate::genesis::GenesisArgs;
+
+pub const DEFAULT_TPU_ADDR: &str = "127.0.0.1:8080";
+pub const DEFAULT_GOSSIP_ADDR: &str = "127.0.0.1:8000";
+pub const DEFAULT_MONITOR_ADDR: &str = "127.0.0.1:3030";
+const DEFAULT_LEADER_ELECTION_INTERVAL_SECS: u64 = 30;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LeaderConfig {
+    pub genesis: PathBuf,
+    /// Keypair file holding the identity blocks are signed with.
+    pub identity: Option<PathBuf>,
+    pub ledger: PathBuf,
+    /// Where validators connect to register, vote and send transactions.
+    pub tpu_addr: SocketAddr,
+    /// Gossip listener and the cluster's entrypoint.
+    pub gossip_addr: SocketAddr,
+    pub gossip_fanout: usize,
+    /// JSON-RPC and WebSocket subscriptions.
+    pub rpc_addr: SocketAddr,
+    /// The /status dashboard and /metrics.
+    pub monitor_addr: SocketAddr,
+    pub snapshot_interval_slots: u64,
+    pub leader_election_interval_secs: u64,
+    /// Overrides the genesis tick duration for this node, for local testing.
+    pub tick_duration_ms: Option<u64>,
+    /// Overrides the genesis ticks per slot for this node, for local testing.
+    pub ticks_per_slot: Option<u64>,
+    /// Log filter in `RUST_LOG` syntax; falls back to `RUST_LOG`, then info.
+    pub log: Option<String>,
+    pub log_format: LogFormat,
+}
+
+impl Default for LeaderConfig {
+    fn default() -> Self {
+        LeaderConfig {
+            genesis: PathBuf::from(DEFAULT_GENESIS_PATH),
+            identity: None,
+            ledger: PathBuf::from(DEFAULT_LEDGER_DIR),
+            tpu_addr: DEFAULT_TPU_ADDR.parse().unwrap(),
+            gossip_addr: DEFAULT_GOSSIP_ADDR.parse().unwrap(),
+            gossip_fanout: DEFAULT_GOSSIP_FANOUT,
+            rpc_addr: DEFAULT_RPC_ADDR.parse().unwrap(),
+            monitor_addr: DEFAULT_MONITOR_ADDR.parse().unwrap(),
+            snapshot_interval_slots: DEFAULT_SNAPSHOT_INTERVAL_SLOTS,
+            leader_election_interval_secs: DEFAULT_LEADER_ELECTION_INTERVAL_SECS,
+            tick_duration_ms: None,
+            ticks_per_slot: None,
+            log: None,
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+impl LeaderConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        if self.gossip_fanout == 0 {
+            return Err(invalid("gossip_fanout must be at least 1"));
+        }
+        if self.snapshot_interval_slots == 0 {
+            return Err(invalid("snapshot_interval_slots must be at least 1"));
+        }
+        if self.leader_election_interval_secs == 0 {
+            return Err(invalid("leader_election_interval_secs must be at least 1"));
+        }
+        if self.tick_duration_ms == Some(0) || self.ticks_per_slot == Some(0) {
+            return Err(invalid("tick_duration_ms and ticks_per_slot must be at least 1"));
+        }
+        check_distinct_addrs(&[
+            ("tpu_addr", Some(self.tpu_addr)),
+            ("gossip_addr", Some(self.gossip_addr)),
+            ("rpc_addr", Some(self.rpc_addr)),
+            ("monitor_addr", Some(self.monitor_addr)),
+        ])
+    }
+
+    /// PoH timing from genesis with this node's overrides applied.
+    pub fn poh_timing(&self, genesis: &PohConfig) -> PohConfig {
+        PohConfig {
+            tick_duration_ms: self.tick_duration_ms.unwrap_or(genesis.tick_duration_ms),
+            ticks_per_slot: self.ticks_per_slot.unwrap_or(genesis.ticks_per_slot),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "leader_node", version, about = "Runs the leader, which produces PoH and blocks")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub args: LeaderArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Write a genesis file
+    Genesis(GenesisArgs),
+}
+
+/// Flags override the config file, which overrides the defaults.
+#[derive(clap::Args, Debug)]
+pub struct LeaderArgs {
+    /// TOML file with any of the settings below
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Print the resolved configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+    /// Genesis file to boot from
+    #[arg(long)]
+    pub genesis: Option<PathBuf>,
+    /// Keypair file holding the leader identity (see `keygen new`)
+    #[arg(long)]
+    pub identity: Option<PathBuf>,
+    /// Ledger directory for blocks, PoH and snapshots
+    #[arg(long)]
+    pub ledger: Option<PathBuf>,
+    /// Where validators connect to register, vote and send transactions
+    #[arg(long)]
+    pub tpu_addr: Option<SocketAddr>,
+    /// Gossip listener and the cluster's entrypoint
+    #[arg(long)]
+    pub gossip_addr: Option<SocketAddr>,
+    /// Peers each gossip message is pushed to
+    #[arg(long)]
+    pub gossip_fanout: Option<usize>,
+    /// JSON-RPC and WebSocket address
+    #[arg(long)]
+    pub rpc_addr: Option<SocketAddr>,
+    /// Address of the /status dashboard and /metrics
+    #[arg(long)]
+    pub monitor_addr: Option<SocketAddr>,
+    /// Slots between accounts snapshots
+    #[arg(long = "snapshot-interval")]
+    pub snapshot_interval_slots: Option<u64>,
+    /// Seconds between leader elections
+    #[arg(long)]
+    pub leader_election_interval_secs: Option<u64>,
+    /// Override the genesis tick duration
+    #[arg(long)]
+    pub tick_duration_ms: Option<u64>,
+    /// Override the genesis ticks per slot
+    #[arg(long)]
+    pub ticks_per_slot: Option<u64>,
+    /// Log filter, e.g. `info,leader_node::network=debug`
+    #[arg(long)]
+    pub log: Option<String>,
+    /// `text` or `json`
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+}
+
+impl LeaderArgs {
+    pub fn resolve(&self) -> io::Result<LeaderConfig> {
+        let mut config = match &self.config {
+            Some(path) => load_toml(path)?,
+            None => LeaderConfig::default(),
+        };
+        if let Some(genesis) = &self.genesis {
+            config.genesis = genesis.clone();
+        }
+        if let Some(identity) = &self.identity {
+            config.identity = Some(identity.clone());
+        }
+        if let Some(ledger) = &self.ledger {
+            config.ledger = ledger.clone();
+        }
+        config.tpu_addr = self.tpu_addr.unwrap_or(config.tpu_addr);
+        config.gossip_addr = self.gossip_addr.unwrap_or(config.gossip_addr);
+        config.gossip_fanout = self.gossip_fanout.unwrap_or(config.gossip_fanout);
+        config.rpc_addr = self.rpc_addr.unwrap_or(config.rpc_addr);
+        config.monitor_addr = self.monitor_addr.unwrap_or(config.monitor_addr);
+        config.snapshot_interval_slots = self.snapshot_interval_slots.unwrap_or(config.snapshot_interval_slots);
+        config.leader_election_interval_secs =
+            self.leader_election_interval_secs.unwrap_or(config.leader_election_interval_secs);
+        config.tick_duration_ms = self.tick_duration_ms.or(config.tick_duration_ms);
+        config.ticks_per_slot = self.ticks_per_slot.or(config.ticks_per_slot);
+        config.log = self.log.clone().or(config.log);
+        config.log_format = self.log_format.unwrap_or(config.log_format);
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(flags: &[&str], toml: Option<&str>) -> io::Result<LeaderConfig> {
+        let path = std::env::temp_dir().join(format!("leader-config-{}-{}.toml", std::process::id(), flags.join("")));
+        let mut args = vec!["leader_node".to_string()];
+        if let Some(toml) = toml {
+            std::fs::write(&path, toml).unwrap();
+            args.extend(["--config".to_string(), path.display().to_string()]);
+        }
+        args.extend(flags.iter().map(|flag| flag.to_string()));
+        let cli = Cli::try_parse_from(args).unwrap();
+        let config = cli.args.resolve();
+        let _ = std::fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn flags_override_the_file_which_overrides_defaults() {
+        let toml = "rpc_addr = \"127.0.0.1:9899\"\nsnapshot_interval_slots = 10\nticks_per_slot = 4\n";
+        let config = resolve(&["--snapshot-interval", "20", "--tick-duration-ms", "5"], Some(toml)).unwrap();
+        assert_eq!(config.snapshot_interval_slots, 20);
+        assert_eq!(config.rpc_addr, "127.0.0.1:9899".parse().unwrap());
+        assert_eq!(config.ticks_per_slot, Some(4));
+        assert_eq!(config.tick_duration_ms, Some(5));
+        assert_eq!(config.tpu_addr, DEFAULT_TPU_ADDR.parse().unwrap());
+        assert_eq!(config.leader_election_interval_secs, DEFAULT_LEADER_ELECTION_INTERVAL_SECS);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let error = resolve(&[], Some("snapshot_interval = 10\n")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("snapshot_interval"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_zero_intervals_and_shared_addrs() {
+        assert!(resolve(&["--snapshot-interval", "0"], None).is_err());
+        assert!(resolve(&["--ticks-per-slot", "0"], None).is_err());
+        assert!(resolve(&[], Some("leader_election_interval_secs = 0\n")).is_err());
+        let error = resolve(&["--rpc-addr", DEFAULT_MONITOR_ADDR], None).unwrap_err();
+        assert!(error.to_string().contains("rpc_addr and monitor_addr"), "{}", error);
+    }
+
+    #[test]
+    fn overrides_apply_to_genesis_timing() {
+        let genesis = PohConfig { tick_duration_ms: 100, ticks_per_slot: 8 };
+        let config = resolve(&["--ticks-per-slot", "2"], None).unwrap();
+        let timing = config.poh_timing(&genesis);
+        assert_eq!((timing.tick_duration_ms, timing.ticks_per_slot), (100, 2));
+    }
+}
@@ -1,41 +1,60 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use std::collections::HashMap;
+use validator::consensus::elect_leader;
+use validator::genesis::EpochSchedule;
+use validator::stake::StakeAccounts;
 
 #[derive(Debug)]
 pub struct LeaderElection {
-    stakes: Arc<Mutex<HashMap<String, u64>>>,
+    stakes: Arc<Mutex<StakeAccounts>>,
+    block_height: Arc<Mutex<u64>>,
+    epoch_schedule: EpochSchedule,
+    rng: Mutex<StdRng>,
 }
 
 impl LeaderElection {
-    pub fn new(stakes: Arc<Mutex<HashMap<String, u64>>>) -> Self {
-        LeaderElection { stakes }
+    /// Elections draw from an RNG seeded with `seed`, so a run can be
+    /// replayed from it.
+    pub fn new(stakes: Arc<Mutex<StakeAccounts>>, block_height: Arc<Mutex<u64>>, epoch_schedule: EpochSchedule, seed: [u8; 32]) -> Self {
+        LeaderElection { stakes, block_height, epoch_schedule, rng: Mutex::new(StdRng::from_seed(seed)) }
     }
 
     pub async fn elect_leader(&self) -> Option<String> {
+        let height = *self.block_height.lock().await;
         let stakes = self.stakes.lock().await;
-        if stakes.is_empty() {
-            return None;
-        }
-
-        let mut rng = rand::thread_rng();
-        let weighted_choices: Vec<(&String, u64)> = stakes.iter().map(|(k, &v)| (k, v)).collect();
-        let weights: Vec<u64> = weighted_choices.iter().map(|(_, weight)| *weight).collect();
+        elect_leader(&stakes, &self.epoch_schedule, height, &mut *self.rng.lock().await)
+    }
+}
 
-        let total_weight: u64 = weights.iter().sum();
-        if total_weight == 0 {
-            return None;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::genesis::{GenesisConfig, GenesisValidator};
 
-        let mut choice = rng.gen_range(0..total_weight);
-        for (validator, weight) in weighted_choices {
-            if choice < weight {
-                return Some(validator.clone());
-            }
-            choice -= weight;
+    #[tokio::test]
+    async fn the_same_seed_elects_the_same_leaders() {
+        let stakes = StakeAccounts::from_genesis(&GenesisConfig {
+            creation_time: 0,
+            accounts: Vec::new(),
+            validators: ["a", "b", "c"]
+                .iter()
+                .map(|identity| GenesisValidator { identity: identity.to_string(), stake: 10, commission: 0 })
+                .collect(),
+            poh: Default::default(),
+            epoch_schedule: Default::default(),
+            inflation: Default::default(),
+            slashing: Default::default(),
+        });
+        let election = |seed| {
+            LeaderElection::new(Arc::new(Mutex::new(stakes.clone())), Arc::new(Mutex::new(0)), EpochSchedule::default(), seed)
+        };
+        let (first, second) = (election([1; 32]), election([1; 32]));
+        for _ in 0..10 {
+            let leader = first.elect_leader().await;
+            assert!(leader.is_some());
+            assert_eq!(leader, second.elect_leader().await);
         }
-
-        None
     }
 }
@@ -0,0 +1,118 @@
+use clap::Args;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use validator::genesis::{
+    EpochSchedule, GenesisAccount, GenesisConfig, GenesisValidator, PohConfig, DEFAULT_GENESIS_PATH,
+};
+use validator::inflation::Inflation;
+use validator::slashing::SlashingConfig;
+
+const DEFAULT_BOOTSTRAP_STAKE: u64 = 5000;
+
+fn parse_u64(value: &str, what: &str) -> io::Result<u64> {
+    value.parse().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {}: {}", what, value))
+    })
+}
+
+/// Splits `<pubkey>[:<amount>]`, falling back to `default` when no amount is given.
+fn parse_pubkey_amount(value: &str, default: Option<u64>, what: &str) -> io::Result<(String, u64)> {
+    match value.split_once(':') {
+        Some((pubkey, amount)) => Ok((pubkey.to_string(), parse_u64(amount, what)?)),
+        None => match default {
+            Some(amount) => Ok((value.to_string(), amount)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} must be <pubkey>:<amount>, got {}", what, value),
+            )),
+        },
+    }
+}
+
+/// Options for `leader_node genesis`, which writes a new genesis file.
+#[derive(Args, Debug)]
+pub struct GenesisArgs {
+    /// `<pubkey>[:<stake>]`, repeated for each validator staked at genesis
+    #[arg(long = "bootstrap-validator")]
+    bootstrap_validators: Vec<String>,
+    /// `<pubkey>:<balance>`, repeated for each funded account
+    #[arg(long = "account")]
+    accounts: Vec<String>,
+    /// Commission percentage of the bootstrap validators
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100))]
+    commission: u8,
+    /// Milliseconds per PoH tick
+    #[arg(long)]
+    tick_duration_ms: Option<u64>,
+    /// PoH ticks per slot
+    #[arg(long)]
+    ticks_per_slot: Option<u64>,
+    /// Slots per epoch
+    #[arg(long)]
+    slots_per_epoch: Option<u64>,
+    /// Initial yearly inflation rate
+    #[arg(long)]
+    inflation_initial: Option<f64>,
+    /// Yearly reduction of the inflation rate
+    #[arg(long)]
+    inflation_taper: Option<f64>,
+    /// Long-term inflation rate
+    #[arg(long)]
+    inflation_terminal: Option<f64>,
+    /// Fraction of stake burned for a slashable offense
+    #[arg(long)]
+    slash_fraction: Option<f64>,
+    /// Where to write the genesis file
+    #[arg(long, default_value = DEFAULT_GENESIS_PATH)]
+    out: PathBuf,
+}
+
+pub fn run_genesis_command(args: &GenesisArgs) -> io::Result<()> {
+    let mut poh = PohConfig::default();
+    poh.tick_duration_ms = args.tick_duration_ms.unwrap_or(poh.tick_duration_ms);
+    poh.ticks_per_slot = args.ticks_per_slot.unwrap_or(poh.ticks_per_slot);
+
+    let mut epoch_schedule = EpochSchedule::default();
+    epoch_schedule.slots_per_epoch = args.slots_per_epoch.unwrap_or(epoch_schedule.slots_per_epoch);
+
+    let mut inflation = Inflation::default();
+    inflation.initial = args.inflation_initial.unwrap_or(inflation.initial);
+    inflation.taper = args.inflation_taper.unwrap_or(inflation.taper);
+    inflation.terminal = args.inflation_terminal.unwrap_or(inflation.terminal);
+
+    let mut slashing = SlashingConfig::default();
+    slashing.slash_fraction = args.slash_fraction.unwrap_or(slashing.slash_fraction);
+
+    let mut validators = Vec::new();
+    for value in &args.bootstrap_validators {
+        let (identity, stake) = parse_pubkey_amount(value, Some(DEFAULT_BOOTSTRAP_STAKE), "bootstrap validator")?;
+        validators.push(GenesisValidator { identity, stake, commission: args.commission });
+    }
+
+    let mut accounts = Vec::new();
+    for value in &args.accounts {
+        let (pubkey, balance) = parse_pubkey_amount(value, None, "account")?;
+        accounts.push(GenesisAccount { pubkey, balance });
+    }
+
+    let genesis = GenesisConfig {
+        creation_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        accounts,
+        validators,
+        poh,
+        epoch_schedule,
+        inflation,
+        slashing,
+    };
+
+    let out = &args.out;
+    genesis.write(out)?;
+    println!(
+        "Wrote genesis {} to {} (total supply {})",
+        bs58::encode(genesis.hash()).into_string(),
+        out.display(),
+        genesis.total_supply()
+    );
+    Ok(())
+}
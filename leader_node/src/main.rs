@@ -1,25 +1,70 @@
 use std::collections::HashMap;
+use std::io;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use validator::poh_handler::PohEntry;
+use tokio::sync::{broadcast, Mutex};
+use validator::poh_handler::PohHistory;
 use validator::transaction::Transaction;
-use sha2::{Sha256, Digest};
-use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::net::TcpListener;
+use validator::blockstore::Blockstore;
+use validator::clock::{sleep_until, Clock, SystemClock};
+use validator::consensus::ConfirmationTracker;
+use validator::genesis::{GenesisConfig, PohConfig};
+use validator::stake::{StakeAccounts, StakeInstruction};
+use validator::inflation::EpochRewards;
+use validator::keypair::{load_identity, Keypair};
+use validator::connection::ConnectionManager;
+use validator::crds::{self, ClusterInfo, ContactInfo};
+use validator::repair::RepairService;
+use validator::slashing::{ConflictDetector, EvidencePool};
+use validator::snapshot::SnapshotArchive;
+use crate::config::{Cli, Command, LeaderConfig};
 use crate::election::LeaderElection;
-use crate::network::GossipActivity;
+use crate::pubsub::{Event, EVENT_CHANNEL_SIZE};
+use crate::status_cache::{StatusCache, MAX_RECENT_BLOCKHASHES};
+use validator::gossip::GossipActivity;
+use validator::config::to_toml;
+use validator::logging;
+use clap::Parser;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 
+mod config;
 mod election;
+mod genesis;
 mod network;
+mod pubsub;
 mod block;
+mod rewards;
+mod rpc;
+mod snapshot;
+mod status_cache;
+mod submit;
 mod tester;
 
 struct PoHGenerator {
-    poh: Arc<Mutex<Vec<PohEntry>>>,
+    genesis: GenesisConfig,
+    config: LeaderConfig,
+    /// Genesis PoH timing with the config's overrides applied.
+    poh_timing: PohConfig,
+    identity: Arc<Keypair>,
+    cluster: Arc<ClusterInfo>,
+    connections: Arc<ConnectionManager>,
+    blockstore: Arc<Mutex<Blockstore>>,
+    repair_service: Arc<Mutex<RepairService>>,
+    poh: Arc<Mutex<PohHistory>>,
+    latest_snapshot: Arc<Mutex<Option<SnapshotArchive>>>,
     validators: Arc<Mutex<HashMap<String, usize>>>,
     votes: Arc<Mutex<HashMap<String, bool>>>,
     transactions: Arc<Mutex<Vec<Transaction>>>,
-    stakes: Arc<Mutex<HashMap<String, u64>>>,
+    stake_instructions: Arc<Mutex<Vec<StakeInstruction>>>,
+    status_cache: Arc<Mutex<StatusCache>>,
+    stakes: Arc<Mutex<StakeAccounts>>,
+    balances: Arc<Mutex<HashMap<String, u64>>>,
+    vote_credits: Arc<Mutex<HashMap<String, u64>>>,
+    epoch_rewards: Arc<Mutex<EpochRewards>>,
+    conflict_detector: Arc<Mutex<ConflictDetector>>,
+    evidence_pool: Arc<Mutex<EvidencePool>>,
+    confirmations: Arc<Mutex<ConfirmationTracker>>,
+    events: broadcast::Sender<Event>,
     leader_election: LeaderElection,
     current_leader: Arc<Mutex<Option<String>>>,
     parent_hash: Arc<Mutex<[u8; 32]>>,
@@ -27,110 +72,301 @@ struct PoHGenerator {
 }
 
 impl PoHGenerator {
-    fn new() -> Self {
-        let stakes = Arc::new(Mutex::new(HashMap::new()));
-        PoHGenerator {
-            poh: Arc::new(Mutex::new(Vec::new())),
+    fn new(
+        genesis: GenesisConfig,
+        config: LeaderConfig,
+        identity: Keypair,
+        blockstore: Blockstore,
+        poh: PohHistory,
+    ) -> io::Result<Self> {
+        let balances: HashMap<String, u64> = genesis
+            .accounts
+            .iter()
+            .map(|account| (account.pubkey.clone(), account.balance))
+            .collect();
+        let stakes = Arc::new(Mutex::new(StakeAccounts::from_genesis(&genesis)));
+        let genesis_hash = genesis.hash();
+        // Resume the chain from the ledger after a restart
+        let (tip_height, tip_hash) = match blockstore.latest() {
+            Some(tip) => {
+                let hash: [u8; 32] = hex::decode(&tip.block_hash)
+                    .ok()
+                    .and_then(|hash| hash.try_into().ok())
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("block {} in the ledger has an invalid hash {:?}", tip.block_height, tip.block_hash),
+                        )
+                    })?;
+                (tip.block_height, hash)
+            }
+            None => (0, genesis_hash),
+        };
+        let block_height = Arc::new(Mutex::new(tip_height));
+        let recent_blocks = blockstore.range(
+            tip_height.saturating_sub(MAX_RECENT_BLOCKHASHES as u64 - 1).max(1),
+            tip_height,
+            MAX_RECENT_BLOCKHASHES,
+        );
+        let oldest_blockhash = match recent_blocks.first() {
+            Some(block) => block.parent_hash.clone(),
+            None => hex::encode(tip_hash),
+        };
+        let status_cache = StatusCache::new(oldest_blockhash, &recent_blocks);
+        let identity = Arc::new(identity);
+        // The server on the tpu address also answers repair requests, the
+        // only thing peers send to a tvu address, so it serves as both
+        let cluster = Arc::new(ClusterInfo::new(
+            Arc::clone(&identity),
+            ContactInfo {
+                identity: identity.pubkey(),
+                gossip_addr: config.gossip_addr.to_string(),
+                tpu_addr: config.tpu_addr.to_string(),
+                tvu_addr: config.tpu_addr.to_string(),
+                shred_version: crds::shred_version(&genesis_hash),
+                wallclock: crds::wallclock(),
+            },
+        ));
+        Ok(PoHGenerator {
+            poh_timing: config.poh_timing(&genesis.poh),
+            config,
+            identity,
+            cluster,
+            connections: Arc::new(ConnectionManager::new()),
+            blockstore: Arc::new(Mutex::new(blockstore)),
+            repair_service: Arc::new(Mutex::new(RepairService::default())),
+            poh: Arc::new(Mutex::new(poh)),
+            latest_snapshot: Arc::new(Mutex::new(None)),
             validators: Arc::new(Mutex::new(HashMap::new())),
             votes: Arc::new(Mutex::new(HashMap::new())),
             transactions: Arc::new(Mutex::new(Vec::new())),
+            stake_instructions: Arc::new(Mutex::new(Vec::new())),
+            status_cache: Arc::new(Mutex::new(status_cache)),
             stakes: Arc::clone(&stakes),
-            leader_election: LeaderElection::new(Arc::clone(&stakes)),
+            balances: Arc::new(Mutex::new(balances)),
+            vote_credits: Arc::new(Mutex::new(HashMap::new())),
+            epoch_rewards: Arc::new(Mutex::new(EpochRewards::default())),
+            conflict_detector: Arc::new(Mutex::new(ConflictDetector::default())),
+            evidence_pool: Arc::new(Mutex::new(EvidencePool::default())),
+            confirmations: Arc::new(Mutex::new(ConfirmationTracker::default())),
+            events: broadcast::channel(EVENT_CHANNEL_SIZE).0,
+            // Seeded from genesis so every run of a cluster elects the same way
+            leader_election: LeaderElection::new(
+                Arc::clone(&stakes),
+                Arc::clone(&block_height),
+                genesis.epoch_schedule.clone(),
+                genesis_hash,
+            ),
             current_leader: Arc::new(Mutex::new(None)),
-            parent_hash: Arc::new(Mutex::new([0u8; 32])),
-            block_height: Arc::new(Mutex::new(0)),
-        }
+            parent_hash: Arc::new(Mutex::new(tip_hash)),
+            block_height,
+            genesis,
+        })
     }
 
-    pub async fn generate_poh_entry(self: Arc<Self>) {
-        let mut prev_hash = vec![0; 32];
-
-        loop {
-            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-
-            let mut hasher = Sha256::new();
-            let timestamp_bytes = timestamp.to_be_bytes();
+    /// A node on an in-memory ledger, starting from a genesis that funds `accounts`.
+    #[cfg(test)]
+    fn for_tests(accounts: &[(String, u64)]) -> Self {
+        use validator::genesis::GenesisAccount;
+        let genesis = GenesisConfig {
+            creation_time: 0,
+            accounts: accounts
+                .iter()
+                .map(|(pubkey, balance)| GenesisAccount { pubkey: pubkey.clone(), balance: *balance })
+                .collect(),
+            validators: Vec::new(),
+            poh: PohConfig::default(),
+            epoch_schedule: Default::default(),
+            inflation: Default::default(),
+            slashing: Default::default(),
+        };
+        let poh = PohHistory::new(genesis.hash());
+        PoHGenerator::new(genesis, LeaderConfig::default(), Keypair::from_seed([1; 32]), Blockstore::in_memory(), poh).unwrap()
+    }
 
-            hasher.update(&prev_hash);
-            hasher.update(&timestamp_bytes);
-            let result = hasher.finalize_reset().to_vec();
+    async fn current_epoch(&self) -> u64 {
+        let block_height = *self.block_height.lock().await;
+        self.genesis.epoch_schedule.epoch_for_slot(block_height)
+    }
 
-            let entry = PohEntry {
-                timestamp,
-                hash: result.clone(),
-            };
+    /// Appends a PoH entry every tick on `clock`.
+    pub async fn generate_poh_entry(self: Arc<Self>, clock: impl Clock) {
+        let tick_duration_ms = self.poh_timing.tick_duration_ms;
+        let mut next_tick_ms = clock.now_ms();
 
-            {
-                let mut poh = self.poh.lock().await;
-                poh.push(entry);
-                prev_hash = result.clone();
-                println!("Generated entry at timestamp {}", timestamp);
+        loop {
+            if let Err(e) = self.poh.lock().await.tick(&clock) {
+                error!("Failed to store PoH entry: {}", e);
             }
+            trace!("Generated PoH entry at {}", clock.now_secs());
 
-            tokio::time::sleep(tokio::time::Duration::from_millis(400)).await;
+            next_tick_ms = (next_tick_ms + tick_duration_ms).max(clock.now_ms());
+            sleep_until(&clock, next_tick_ms).await;
         }
     }
 
-    async fn start_leader_election(&self) {
+    /// Elects a leader every election interval on `clock`.
+    async fn start_leader_election(&self, clock: impl Clock) {
+        let interval_ms = self.config.leader_election_interval_secs * 1000;
+        let mut next_election_ms = clock.now_ms() + interval_ms;
         loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+            sleep_until(&clock, next_election_ms).await;
+            next_election_ms += interval_ms;
             if let Some(leader) = self.leader_election.elect_leader().await {
                 let mut current_leader = self.current_leader.lock().await;
                 *current_leader = Some(leader.clone());
-                println!("New leader elected: {}", leader);
+                info!("New leader elected: {}", leader);
             } else {
-                println!("No leader elected.");
+                debug!("No leader elected.");
             }
         }
     }
 
-    pub async fn start_server(self: Arc<Self>, gossip_activity: Arc<Mutex<GossipActivity>>) {
-        let listener = TcpListener::bind("127.0.0.1:8080").await.unwrap();
-        println!("Server running on 127.0.0.1:8080");
+    pub async fn start_server(self: Arc<Self>) {
+        let listener = TcpListener::bind(self.config.tpu_addr).await.unwrap();
+        info!("Server running on {}", self.config.tpu_addr);
 
         loop {
-            let (socket, _) = listener.accept().await.unwrap();
-            let poh_clone = Arc::clone(&self.poh);
-            let validators_clone = Arc::clone(&self.validators);
-            let votes_clone = Arc::clone(&self.votes);
-            let transactions_clone = Arc::clone(&self.transactions);
-            let stakes_clone = Arc::clone(&self.stakes);
-            let gossip_activity_clone = Arc::clone(&gossip_activity);
+            let (socket, addr) = listener.accept().await.unwrap();
+            let node = Arc::clone(&self);
+            // The validator's identity is filled in once it registers
+            let span = info_span!("connection", peer = %addr, validator = tracing::field::Empty);
             tokio::spawn(async move {
-                crate::network::handle_connection(socket, poh_clone, validators_clone, votes_clone, transactions_clone, stakes_clone, gossip_activity_clone).await;
-            });
-
-            tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
+                crate::network::handle_connection(socket, node).await;
+            }.instrument(span));
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let poh_generator = Arc::new(PoHGenerator::new());
+    let cli = Cli::parse();
+    if let Some(Command::Genesis(args)) = &cli.command {
+        if let Err(e) = genesis::run_genesis_command(args) {
+            eprintln!("Failed to create genesis: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let config = match cli.args.resolve() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if cli.args.print_config {
+        print!("{}", to_toml(&config));
+        return;
+    }
+    if let Err(e) = logging::init(config.log.as_deref(), config.log_format) {
+        eprintln!("Failed to set up logging: {}", e);
+        std::process::exit(1);
+    }
+
+    let genesis = match GenesisConfig::load(&config.genesis) {
+        Ok(genesis) => genesis,
+        Err(e) => {
+            error!("Failed to load genesis from {}: {} (create one with `leader_node genesis`)", config.genesis.display(), e);
+            std::process::exit(1);
+        }
+    };
+    info!("Booting from genesis {}", bs58::encode(genesis.hash()).into_string());
+
+    let ledger_dir = config.ledger.clone();
+    let blockstore = match Blockstore::open(&ledger_dir) {
+        Ok(blockstore) => blockstore,
+        Err(e) => {
+            error!("Failed to open ledger at {}: {}", ledger_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    if !blockstore.is_empty() {
+        info!("Resuming from ledger {} at block height {}", ledger_dir.display(), blockstore.height());
+    }
+    let poh = match PohHistory::open(&ledger_dir, genesis.hash()) {
+        Ok(poh) => poh,
+        Err(e) => {
+            error!("Failed to open PoH history at {}: {}", ledger_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    if !poh.is_empty() {
+        info!("Resuming PoH at entry {} from checkpoint {}", poh.len(), poh.anchor().height);
+    }
+    let identity = match load_identity(config.identity.as_deref()) {
+        Ok(identity) => identity,
+        Err(e) => {
+            error!("Failed to load identity keypair: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let poh_generator = match PoHGenerator::new(genesis, config, identity, blockstore, poh) {
+        Ok(poh_generator) => Arc::new(poh_generator),
+        Err(e) => {
+            error!("Failed to resume from ledger at {}: {}", ledger_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    info!("Leader identity {}", poh_generator.identity.pubkey());
+    let restored_slot = match SnapshotArchive::latest(&ledger_dir) {
+        Ok(Some(archive)) => match snapshot::restore(&poh_generator, &archive).await {
+            Ok(()) => {
+                info!("Restored accounts from snapshot at slot {}", archive.slot);
+                let slot = archive.slot;
+                *poh_generator.latest_snapshot.lock().await = Some(archive);
+                slot
+            }
+            Err(e) => {
+                warn!("Ignoring snapshot at slot {}: {}", archive.slot, e);
+                0
+            }
+        },
+        Ok(None) => 0,
+        Err(e) => {
+            warn!("Failed to read snapshots from {}: {}", ledger_dir.display(), e);
+            0
+        }
+    };
+    match snapshot::replay_blocks(&poh_generator, restored_slot).await {
+        Ok(0) => {}
+        Ok(replayed) => info!("Replayed {} blocks after slot {} into the bank", replayed, restored_slot),
+        Err(e) => {
+            error!("Failed to replay the ledger at {}: {}", ledger_dir.display(), e);
+            std::process::exit(1);
+        }
+    }
     let gossip_activity = Arc::new(Mutex::new(GossipActivity::default()));
 
     tokio::spawn({
         let poh_generator = Arc::clone(&poh_generator);
+        let gossip_activity = Arc::clone(&gossip_activity);
         async move {
-            block::propose_block(
-                Arc::clone(&poh_generator.poh),
-                Arc::clone(&poh_generator.validators),
-                Arc::clone(&poh_generator.votes),
-                Arc::clone(&poh_generator.transactions),
-                Arc::clone(&poh_generator.parent_hash),
-                Arc::clone(&poh_generator.block_height),
-            ).await;
+            let (gossip_addr, fanout) = (poh_generator.config.gossip_addr.to_string(), poh_generator.config.gossip_fanout);
+            network::start_gossip(poh_generator, gossip_addr, fanout, gossip_activity).await;
         }
     });
 
     tokio::spawn({
         let poh_generator = Arc::clone(&poh_generator);
         async move {
-            poh_generator.start_leader_election().await;
+            block::propose_block(poh_generator, SystemClock).await;
         }
     });
 
+    tokio::spawn({
+        let poh_generator = Arc::clone(&poh_generator);
+        async move {
+            poh_generator.start_leader_election(SystemClock).await;
+        }
+    });
+
+    tokio::spawn(rpc::start_rpc(Arc::clone(&poh_generator), poh_generator.config.rpc_addr));
+
+    let snapshot_interval = poh_generator.config.snapshot_interval_slots;
+    tokio::spawn(snapshot::run_snapshots(Arc::clone(&poh_generator), ledger_dir, snapshot_interval));
+
     tokio::spawn({
         let poh_generator = Arc::clone(&poh_generator);
         let gossip_activity = Arc::clone(&gossip_activity);
@@ -142,9 +378,9 @@ async fn main() {
     tokio::spawn({
         let poh_generator = Arc::clone(&poh_generator);
         async move {
-            poh_generator.generate_poh_entry().await;
+            poh_generator.generate_poh_entry(SystemClock).await;
         }
     });
 
-    poh_generator.start_server(Arc::clone(&gossip_activity)).await;
+    poh_generator.start_server().await;
 }
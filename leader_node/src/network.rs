@@ -1,170 +1,374 @@
-use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::task::JoinHandle;
+use tokio::io;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use std::collections::HashMap;
+use tokio::sync::{mpsc, Mutex};
 use serde::{Serialize, Deserialize};
-use validator::poh_handler::PohEntry;
+use validator::poh_handler::{MAX_POH_BATCH_ENTRIES, POH_BATCH_INTERVAL_MS};
 use validator::transaction::Transaction;
 use validator::registration::Validator;
-use crate::block::Block;
-use rand::Rng;
-use rand::rngs::StdRng;
-use rand::SeedableRng;
-use bs58;
+use validator::block::{Message as WireMessage, Vote};
+use validator::clock::SystemClock;
+use validator::catchup::ChainTip;
+use validator::repair::{RepairRequest, SignedRepairRequest};
+use validator::handlers::{VoteCounter, VoteOutcome};
+use validator::slashing::Evidence;
+use validator::stake::{Stake, StakeAccountAction};
+use validator::gossip::{GossipActivity, PushGossip};
+use validator::metrics::metrics;
+use validator::network::{read_message, run_gossip_listener, write_message};
+use crate::PoHGenerator;
+use crate::pubsub::Event;
+use crate::submit;
+use tracing::{debug, error, info, warn, Instrument, Span};
 
 #[derive(Serialize, Deserialize, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Message {
-    ConsensusVote(Block),
-    PoHEntries(Vec<PohEntry>),
+    ConsensusVote(Vote),
+    Evidence(Evidence),
+    /// Index to stream from and the hash of the entry just before it.
+    PohSubscribe(usize, Vec<u8>),
     StakeTokens(Stake),
+    DeactivateStake(StakeAccountAction),
+    WithdrawStake(StakeAccountAction),
     RegisterValidator(Validator),
     Transaction(Transaction),
-    GossipMessage(String), 
+    Repair(SignedRepairRequest),
+    SnapshotRequest,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Stake {
-    pub validator_id: String,
-    pub amount: u64,
+impl Message {
+    /// What clients may send without registering as a validator.
+    fn is_submission(&self) -> bool {
+        matches!(
+            self,
+            Message::Transaction(_) | Message::StakeTokens(_) | Message::DeactivateStake(_) | Message::WithdrawStake(_)
+        )
+    }
 }
 
-pub async fn handle_connection(
-    mut stream: TcpStream,
-    poh: Arc<Mutex<Vec<PohEntry>>>,
-    validators: Arc<Mutex<HashMap<String, usize>>>,
-    votes: Arc<Mutex<HashMap<String, bool>>>,
-    transactions: Arc<Mutex<Vec<Transaction>>>,
-    stakes: Arc<Mutex<HashMap<String, u64>>>,
-    gossip_activity: Arc<Mutex<GossipActivity>>, 
-) {
-    let mut length_buffer = [0; 4];
-    if let Err(_e) = stream.read_exact(&mut length_buffer).await {
-        println!("Failed to read length buffer");
-        return;
-    }
-    let message_length = u32::from_be_bytes(length_buffer) as usize;
-    let mut buffer = vec![0; message_length];
-    if let Err(_e) = stream.read_exact(&mut buffer).await {
-        println!("Failed to read buffer");
-        return;
-    }
+pub async fn handle_connection(stream: TcpStream, node: Arc<PoHGenerator>) {
+    let genesis_hash = bs58::encode(node.genesis.hash()).into_string();
+
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    let mut poh_subscription: Option<JoinHandle<()>> = None;
 
-    let validator_id = match serde_json::from_slice::<Message>(&buffer) {
+    let validator_id = match read_message::<_, Message>(&mut reader).await {
         Ok(Message::RegisterValidator(validator)) => {
-            let mut validators = validators.lock().await;
             let validator_id_str = bs58::encode(validator.public_key.clone()).into_string();
+            if validator.genesis_hash != genesis_hash {
+                warn!(
+                    "Rejecting validator {}: genesis hash {} does not match {}",
+                    validator_id_str, validator.genesis_hash, genesis_hash
+                );
+                return;
+            }
+            let mut validators = node.validators.lock().await;
             validators.insert(validator_id_str.clone(), 0);
-            println!("Registered validator: {}", validator_id_str);
+            Span::current().record("validator", validator_id_str.as_str());
+            info!("Registered validator: {}", validator_id_str);
             validator_id_str
         }
-        _ => {
-            println!("First message must be RegisterValidator");
+        // Wallets submit transactions and stake instructions without registering
+        Ok(message) if message.is_submission() => {
+            serve_client(&node, reader, message).await;
+            return;
+        }
+        Ok(_) => {
+            warn!("First message must be RegisterValidator or a transaction");
+            return;
+        }
+        Err(e) => {
+            debug!("Failed to read first message: {}", e);
             return;
         }
     };
 
-    // Mint tokens for the new validator
-    let mut rng = StdRng::from_entropy();
-    let random_tokens: u64 = rng.gen_range(1000..10000); 
-    {
-        let mut stakes = stakes.lock().await;
-        stakes.insert(validator_id.clone(), random_tokens);
-        println!("Minted {} tokens for validator {}", random_tokens, validator_id);
+    let connections = metrics().connections.with_label_values(&["inbound"]);
+    connections.inc();
+
+    // Stake only comes from genesis or explicit delegation; registration mints nothing
+    let epoch = node.current_epoch().await;
+    match node.stakes.lock().await.active_stakes(epoch).get(&validator_id) {
+        Some(stake) => info!("Validator {} has {} active stake", validator_id, stake),
+        None => info!("Validator {} has no active stake", validator_id),
+    }
+
+    // Tell the validator how far the chain is so it can catch up
+    let tip = {
+        let blockstore = node.blockstore.lock().await;
+        let poh = node.poh.lock().await;
+        ChainTip {
+            leader: node.identity.pubkey(),
+            block_height: blockstore.height(),
+            block_hash: blockstore.tip_hash().unwrap_or_default(),
+            poh_entries: poh.len(),
+            poh_hash: poh.last_hash(),
+        }
+    };
+    if write_message(&mut *writer.lock().await, &WireMessage::ChainTip(tip)).await.is_err() {
+        warn!("Failed to send chain tip to {}", validator_id);
     }
 
     loop {
-        let mut length_buffer = [0; 4];
-        if let Err(_e) = stream.read_exact(&mut length_buffer).await {
-            println!("Failed to read length buffer in loop");
-            break;
-        }
-        let message_length = u32::from_be_bytes(length_buffer) as usize;
-        let mut buffer = vec![0; message_length];
-        if let Err(_e) = stream.read_exact(&mut buffer).await {
-            println!("Failed to read buffer in loop");
-            break;
-        }
-
-        match serde_json::from_slice::<Message>(&buffer) {
-            Ok(Message::ConsensusVote(block)) => {
-                let mut votes = votes.lock().await;
-                votes.insert(validator_id.clone(), true);
-                println!("Received consensus vote for block {:?}", block);
+        match read_message::<_, Message>(&mut reader).await {
+            Ok(Message::ConsensusVote(vote)) if vote.voter != validator_id || !vote.verify_signature() => {
+                warn!("Ignoring vote from {} with an invalid signature", validator_id);
             }
-            Ok(Message::PoHEntries(_)) => {
-                let poh = poh.lock().await;
-                let serialized = serde_json::to_string(&Message::PoHEntries(poh.clone())).unwrap();
-                let message_length = (serialized.len() as u32).to_be_bytes();
-                if let Err(_e) = stream.write_all(&message_length).await {
-                    println!("Failed to send length buffer in loop");
-                    break;
+            Ok(Message::ConsensusVote(vote)) => count_vote(&node, &validator_id, vote).await,
+            Ok(Message::Evidence(evidence)) => {
+                let offender = evidence.offense.offender().to_string();
+                if node.evidence_pool.lock().await.add(evidence) {
+                    info!("Accepted evidence against {} reported by {}", offender, validator_id);
+                } else {
+                    warn!("Ignoring invalid or duplicate evidence from {}", validator_id);
+                }
+            }
+            Ok(Message::PohSubscribe(start, last_hash)) => {
+                if let Some(previous) = poh_subscription.take() {
+                    previous.abort();
                 }
-                if let Err(_e) = stream.write_all(serialized.as_bytes()).await {
-                    println!("Failed to send buffer in loop");
+                // A subscriber we can't vouch for restarts from our oldest checkpoint
+                let start = if node.poh.lock().await.hash_at(start) == Some(last_hash) {
+                    start
+                } else {
+                    warn!("PoH subscription from {} at {} does not match our history, resyncing from checkpoint", validator_id, start);
+                    0
+                };
+                info!("Streaming PoH entries to {} from index {}", validator_id, start);
+                poh_subscription = Some(tokio::spawn(stream_poh(Arc::clone(&node), start, Arc::clone(&writer)).in_current_span()));
+            }
+            // Repairs are rate limited by the identity registered on this
+            // connection and answered on it, so the signature isn't needed
+            Ok(Message::Repair(repair)) => {
+                if !serve_repair(&node, &validator_id, &repair.request, &writer).await {
                     break;
                 }
-                println!("Sent PoH entries to {}", validator_id);
             }
-            Ok(Message::StakeTokens(stake)) => {
-                let mut stakes = stakes.lock().await;
-                let entry = stakes.entry(stake.validator_id.clone()).or_insert(0);
-                *entry += stake.amount;
-                println!("Staked {} tokens for validator {}", stake.amount, stake.validator_id);
+            Ok(Message::SnapshotRequest) => {
+                let archive = node.latest_snapshot.lock().await.clone();
+                match &archive {
+                    Some(archive) => info!("Sending snapshot at slot {} to {}", archive.slot, validator_id),
+                    None => info!("No snapshot yet for {}", validator_id),
+                }
+                if write_message(&mut *writer.lock().await, &WireMessage::Snapshot(archive)).await.is_err() {
+                    break;
+                }
             }
-            Ok(Message::Transaction(transaction)) => {
-                println!("Received Transaction message: {:?}", transaction);
-                let mut txs = transactions.lock().await;
-                txs.push(transaction);
+            Ok(message @ (Message::Transaction(_)
+            | Message::StakeTokens(_)
+            | Message::DeactivateStake(_)
+            | Message::WithdrawStake(_))) => submit_message(&node, message).await,
+            Ok(Message::RegisterValidator(_validator)) => {
+                warn!("Validator {} is already registered", validator_id);
             }
-            Ok(Message::GossipMessage(_msg)) => {
-                // Update gossip activity
-                let mut gossip_activity = gossip_activity.lock().await;
-                gossip_activity.messages_received += 1;
-                println!("Received gossip message from {}", validator_id);
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                metrics().frame_decode_errors.inc();
+                warn!("Failed to parse message: {}", e);
             }
-            Ok(Message::RegisterValidator(_validator)) => {
-                println!("Validator {} is already registered", validator_id);
+            Err(e) => {
+                debug!("Validator {} connection closed: {}", validator_id, e);
+                break;
             }
-            Err(e) => println!("Failed to parse message: {}", e),
         }
     }
 
+    connections.dec();
+    if let Some(subscription) = poh_subscription {
+        subscription.abort();
+    }
     {
-        let mut validators = validators.lock().await;
+        let mut validators = node.validators.lock().await;
         validators.remove(&validator_id);
-        println!("Validator disconnected: {}", validator_id);
+        node.connections.remove(&validator_id).await;
+        info!("Validator disconnected: {}", validator_id);
     }
 }
 
-pub async fn start_server(
-    poh: Arc<Mutex<Vec<PohEntry>>>,
-    validators: Arc<Mutex<HashMap<String, usize>>>,
-    votes: Arc<Mutex<HashMap<String, bool>>>,
-    transactions: Arc<Mutex<Vec<Transaction>>>,
-    stakes: Arc<Mutex<HashMap<String, u64>>>,
-    gossip_activity: Arc<Mutex<GossipActivity>>,
-) {
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await.unwrap();
-    println!("Server listening on port 8080");
+/// Counts a vote whose signature was checked against the validator
+/// registered on the connection it came in on.
+async fn count_vote(node: &PoHGenerator, validator_id: &str, vote: Vote) {
+    debug!("Received consensus vote for block {} from {}", vote.block_height, validator_id);
+    metrics().votes_received.with_label_values(&[validator_id]).inc();
+    let slot = vote.block_height;
+    let epoch = node.current_epoch().await;
+    let active_stakes = node.stakes.lock().await.active_stakes(epoch);
+    let outcome = {
+        let blockstore = node.blockstore.lock().await;
+        let mut conflict_detector = node.conflict_detector.lock().await;
+        let mut evidence_pool = node.evidence_pool.lock().await;
+        let mut confirmations = node.confirmations.lock().await;
+        let mut vote_credits = node.vote_credits.lock().await;
+        VoteCounter {
+            identity: &node.identity,
+            blockstore: &blockstore,
+            conflict_detector: &mut conflict_detector,
+            evidence_pool: &mut evidence_pool,
+            confirmations: &mut confirmations,
+            vote_credits: &mut vote_credits,
+        }
+        .handle_vote(&vote, &active_stakes)
+    };
+    let confirmed = match outcome {
+        VoteOutcome::Counted { confirmed } => confirmed,
+        VoteOutcome::Repeated => {
+            debug!("Validator {} already voted for slot {}", validator_id, slot);
+            return;
+        }
+        VoteOutcome::DoubleVote => {
+            warn!("Validator {} voted twice for slot {}", validator_id, slot);
+            return;
+        }
+        VoteOutcome::Unknown => {
+            warn!("Ignoring vote from {} for slot {}, which is not in our ledger", validator_id, slot);
+            return;
+        }
+    };
+    node.votes.lock().await.insert(validator_id.to_string(), true);
+    if confirmed {
+        info!("Slot {} confirmed by a supermajority of stake", slot);
+        metrics().blocks_confirmed.inc();
+        let _ = node.events.send(Event::Confirmed(slot));
+    }
+}
+
+/// Queues a transaction or applies a stake instruction. Nothing is sent
+/// back; clients learn the outcome over RPC.
+async fn submit_message(node: &PoHGenerator, message: Message) {
+    let (what, result) = match message {
+        Message::Transaction(transaction) => ("transaction", submit::submit_transaction(node, transaction).await.map(drop)),
+        Message::StakeTokens(stake) => ("delegation", submit::delegate_stake(node, &stake).await.map(drop)),
+        Message::DeactivateStake(action) => ("deactivation", submit::deactivate_stake(node, &action).await),
+        Message::WithdrawStake(action) => ("withdrawal", submit::withdraw_stake(node, &action).await.map(drop)),
+        _ => return,
+    };
+    if let Err(e) = result {
+        warn!("Rejected {}: {}", what, e);
+    }
+}
+
+/// A connection that never registered may only submit transactions and
+/// stake instructions, starting with `first`.
+async fn serve_client(node: &PoHGenerator, mut reader: OwnedReadHalf, first: Message) {
+    debug!("Serving client submissions");
+    submit_message(node, first).await;
+    loop {
+        match read_message::<_, Message>(&mut reader).await {
+            Ok(message) if message.is_submission() => submit_message(node, message).await,
+            Ok(_) => warn!("Clients may only submit transactions and stake instructions"),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                metrics().frame_decode_errors.inc();
+                warn!("Failed to parse message: {}", e);
+            }
+            Err(_) => break,
+        }
+    }
+}
 
+/// Pushes PoH entries from `next` onwards to a subscribed validator in
+/// batches, catching it up first and then following the generator. If
+/// `next` has been evicted, the validator is moved to our oldest checkpoint.
+async fn stream_poh(node: Arc<PoHGenerator>, mut next: usize, writer: Arc<Mutex<OwnedWriteHalf>>) {
+    let mut checkpoint_sent = false;
     loop {
-        let (socket, _) = listener.accept().await.unwrap();
-        let poh = Arc::clone(&poh);
-        let validators = Arc::clone(&validators);
-        let votes = Arc::clone(&votes);
-        let transactions = Arc::clone(&transactions);
-        let stakes = Arc::clone(&stakes);
-        let gossip_activity = Arc::clone(&gossip_activity);
-
-        tokio::spawn(async move {
-            handle_connection(socket, poh, validators, votes, transactions, stakes, gossip_activity).await;
-        });
+        let (checkpoint, entries) = {
+            let poh = node.poh.lock().await;
+            let anchor = poh.anchor();
+            let checkpoint = if next < anchor.height || (next == 0 && !checkpoint_sent) {
+                next = anchor.height;
+                Some(anchor.clone())
+            } else {
+                None
+            };
+            (checkpoint, poh.range(next, next + MAX_POH_BATCH_ENTRIES))
+        };
+        if let Some(checkpoint) = checkpoint {
+            checkpoint_sent = true;
+            if write_message(&mut *writer.lock().await, &WireMessage::PohCheckpoint(checkpoint)).await.is_err() {
+                return;
+            }
+        }
+        let count = entries.len();
+        if count > 0 {
+            if write_message(&mut *writer.lock().await, &WireMessage::PohBatch(next, entries)).await.is_err() {
+                return;
+            }
+            next += count;
+        }
+        if count < MAX_POH_BATCH_ENTRIES {
+            tokio::time::sleep(tokio::time::Duration::from_millis(POH_BATCH_INTERVAL_MS)).await;
+        }
     }
 }
 
-#[derive(Default)]
-pub struct GossipActivity {
-    pub messages_sent: usize,
-    pub messages_received: usize,
+/// Answers a repair request on the validator's connection. Returns false
+/// if the connection is gone.
+async fn serve_repair(node: &PoHGenerator, requester: &str, request: &RepairRequest, writer: &Mutex<OwnedWriteHalf>) -> bool {
+    let response = {
+        let blockstore = node.blockstore.lock().await;
+        let poh = node.poh.lock().await;
+        node.repair_service.lock().await.serve(&SystemClock, requester, request, &blockstore, &poh)
+    };
+    match response {
+        Some(response) => write_message(&mut *writer.lock().await, &WireMessage::RepairResponse(response)).await.is_ok(),
+        None => {
+            debug!("Dropping repair request from {}", requester);
+            true
+        }
+    }
+}
+
+/// Serves cluster info to validators (the leader is their entrypoint) and
+/// relays the gossip that reaches us, counting it in `gossip_activity`.
+pub async fn start_gossip(
+    node: Arc<PoHGenerator>,
+    gossip_addr: String,
+    fanout: usize,
+    gossip_activity: Arc<Mutex<GossipActivity>>,
+) {
+    let listener = match TcpListener::bind(&gossip_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind gossip listener on {}: {}", gossip_addr, e);
+            return;
+        }
+    };
+    info!("Gossip running on {}", gossip_addr);
+
+    let (inbound_tx, mut inbound_rx) = mpsc::channel(1024);
+    let gossip = Arc::new(PushGossip::new(Arc::clone(&node.identity), fanout, Arc::clone(&node.connections), gossip_activity));
+    tokio::spawn(run_gossip_listener(listener, Arc::clone(&node.cluster), gossip, inbound_tx));
+    tokio::spawn(Arc::clone(&node.cluster).run_gossip(None));
+
+    // The leader receives transactions and votes over its own connections;
+    // gossip copies are only relayed and counted by the push engine.
+    while inbound_rx.recv().await.is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::keypair::Keypair;
+
+    #[tokio::test]
+    async fn a_repeated_vote_earns_one_credit() {
+        let node = PoHGenerator::for_tests(&[]);
+        crate::block::produce_block(&node, &SystemClock).await;
+        crate::block::produce_block(&node, &SystemClock).await;
+        let voter = Keypair::from_seed([3; 32]);
+        let (first, second) = {
+            let blockstore = node.blockstore.lock().await;
+            (blockstore.get(1).cloned().unwrap(), blockstore.get(2).cloned().unwrap())
+        };
+
+        for _ in 0..5 {
+            count_vote(&node, &voter.pubkey(), Vote::new(&first, &voter)).await;
+        }
+        assert_eq!(node.vote_credits.lock().await[&voter.pubkey()], 1);
+        count_vote(&node, &voter.pubkey(), Vote::new(&second, &voter)).await;
+        assert_eq!(node.vote_credits.lock().await[&voter.pubkey()], 2);
+    }
 }
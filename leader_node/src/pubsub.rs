@@ -0,0 +1,334 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use warp::ws::{Message as WsMessage, WebSocket};
+use validator::block::Block;
+use validator::rpc_client::Commitment;
+use crate::PoHGenerator;
+use tracing::warn;
+
+pub const EVENT_CHANNEL_SIZE: usize = 1024;
+/// Subscriptions one WebSocket may hold; further subscribe requests are
+/// refused until some end.
+const MAX_SUBSCRIPTIONS_PER_SESSION: usize = 100;
+
+/// What block production and voting publish to WebSocket subscribers.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A block was produced at its height.
+    Block(Block),
+    /// Votes from a supermajority of stake confirmed this slot.
+    Confirmed(u64),
+}
+
+#[derive(Debug)]
+enum Subscription {
+    Slot,
+    /// Every block, or only those with a transaction touching the account.
+    Block { mentions: Option<String> },
+    Signature { signature: Vec<u8>, commitment: Commitment },
+    Account { pubkey: String, balance: u64 },
+}
+
+impl Subscription {
+    fn notification_method(&self) -> &'static str {
+        match self {
+            Subscription::Slot => "slotNotification",
+            Subscription::Block { .. } => "blockNotification",
+            Subscription::Signature { .. } => "signatureNotification",
+            Subscription::Account { .. } => "accountNotification",
+        }
+    }
+}
+
+fn notification(method: &str, subscription: u64, result: Value) -> WsMessage {
+    let message = json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": { "result": result, "subscription": subscription },
+    });
+    WsMessage::text(message.to_string())
+}
+
+fn response(id: Value, result: Result<Value, String>) -> WsMessage {
+    let message = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(message) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32602, "message": message } }),
+    };
+    WsMessage::text(message.to_string())
+}
+
+fn signature_result(slot: u64) -> Value {
+    json!({ "context": { "slot": slot }, "value": { "err": null } })
+}
+
+fn mentions(block: &Block, account: &str) -> bool {
+    block
+        .transactions
+        .iter()
+        .any(|transaction| transaction.sender == account || transaction.receiver == account)
+}
+
+fn contains_signature(block: &Block, signature: &[u8]) -> bool {
+    block.transactions.iter().any(|transaction| transaction.signature == signature)
+}
+
+/// One WebSocket client and its subscriptions.
+struct Session {
+    node: Arc<PoHGenerator>,
+    subscriptions: HashMap<u64, Subscription>,
+    next_id: u64,
+}
+
+impl Session {
+    /// Parses a subscribe request into a subscription, plus a notification
+    /// to send right away if the request is already satisfied.
+    async fn subscribe(&self, method: &str, params: &Value) -> Result<(Subscription, Option<Value>), String> {
+        match method {
+            "slotSubscribe" => Ok((Subscription::Slot, None)),
+            "blockSubscribe" => {
+                let mentions = match params.get(0) {
+                    None => None,
+                    Some(Value::String(filter)) if filter == "all" => None,
+                    Some(filter) => match filter.get("mentionsAccountOrProgram").and_then(Value::as_str) {
+                        Some(account) => Some(account.to_string()),
+                        None => return Err("filter must be \"all\" or {\"mentionsAccountOrProgram\": <pubkey>}".to_string()),
+                    },
+                };
+                Ok((Subscription::Block { mentions }, None))
+            }
+            "signatureSubscribe" => {
+                let signature = params
+                    .get(0)
+                    .and_then(Value::as_str)
+                    .and_then(|signature| bs58::decode(signature).into_vec().ok())
+                    .ok_or("expected a base58 transaction signature")?;
+                let commitment = match params.get(1).and_then(|config| config.get("commitment")).and_then(Value::as_str) {
+                    None | Some("confirmed") => Commitment::Confirmed,
+                    Some("processed") => Commitment::Processed,
+                    Some(other) => return Err(format!("unsupported commitment {}", other)),
+                };
+                // The transaction may already have landed
+                let immediate = self.signature_reached(&signature, commitment).await.map(signature_result);
+                Ok((Subscription::Signature { signature, commitment }, immediate))
+            }
+            "accountSubscribe" => {
+                let pubkey = params.get(0).and_then(Value::as_str).ok_or("expected an account pubkey")?.to_string();
+                let balance = self.node.balances.lock().await.get(&pubkey).copied().unwrap_or(0);
+                Ok((Subscription::Account { pubkey, balance }, None))
+            }
+            _ => Err(format!("method {} not found", method)),
+        }
+    }
+
+    /// The slot holding the transaction, once it has reached `commitment`.
+    async fn signature_reached(&self, signature: &[u8], commitment: Commitment) -> Option<u64> {
+        let slot = self.node.blockstore.lock().await.transaction_slot(signature)?;
+        match commitment {
+            Commitment::Processed => Some(slot),
+            Commitment::Confirmed => self.node.confirmations.lock().await.is_confirmed(slot).then_some(slot),
+        }
+    }
+
+    /// Finishes the signature subscriptions whose notification was among
+    /// the events this session missed by falling behind.
+    async fn catch_up(&mut self) -> Vec<WsMessage> {
+        let mut messages = Vec::new();
+        let mut finished = Vec::new();
+        for (&id, subscription) in &self.subscriptions {
+            if let Subscription::Signature { signature, commitment } = subscription {
+                if let Some(slot) = self.signature_reached(signature, *commitment).await {
+                    messages.push(notification(subscription.notification_method(), id, signature_result(slot)));
+                    finished.push(id);
+                }
+            }
+        }
+        for id in finished {
+            self.subscriptions.remove(&id);
+        }
+        messages
+    }
+
+    async fn handle_request(&mut self, text: &str) -> Vec<WsMessage> {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return vec![response(Value::Null, Err(e.to_string()))],
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let method = request.get("method").and_then(Value::as_str).unwrap_or_default();
+        let params = request.get("params").cloned().unwrap_or_else(|| json!([]));
+
+        if method.ends_with("Unsubscribe") {
+            let removed = params
+                .get(0)
+                .and_then(Value::as_u64)
+                .is_some_and(|subscription| self.subscriptions.remove(&subscription).is_some());
+            return vec![response(id, Ok(json!(removed)))];
+        }
+
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS_PER_SESSION {
+            return vec![response(id, Err(format!("at most {} subscriptions per connection", MAX_SUBSCRIPTIONS_PER_SESSION)))];
+        }
+        match self.subscribe(method, &params).await {
+            Ok((subscription, immediate)) => {
+                let subscription_id = self.next_id;
+                self.next_id += 1;
+                let mut messages = vec![response(id, Ok(json!(subscription_id)))];
+                match immediate {
+                    // Signature subscriptions end with their one notification
+                    Some(result) => messages.push(notification(subscription.notification_method(), subscription_id, result)),
+                    None => {
+                        self.subscriptions.insert(subscription_id, subscription);
+                    }
+                }
+                messages
+            }
+            Err(message) => vec![response(id, Err(message))],
+        }
+    }
+
+    async fn handle_event(&mut self, event: &Event) -> Vec<WsMessage> {
+        let mut messages = Vec::new();
+        let mut finished = Vec::new();
+        let latest_confirmed = self.node.confirmations.lock().await.latest_confirmed();
+
+        for (&id, subscription) in self.subscriptions.iter_mut() {
+            let method = subscription.notification_method();
+            match (subscription, event) {
+                (Subscription::Slot, Event::Block(block)) => {
+                    let result = json!({
+                        "slot": block.block_height,
+                        "parent": block.block_height.saturating_sub(1),
+                        "root": latest_confirmed.unwrap_or(0),
+                    });
+                    messages.push(notification(method, id, result));
+                }
+                (Subscription::Block { mentions: filter }, Event::Block(block))
+                    if filter.as_deref().is_none_or(|account| mentions(block, account)) =>
+                {
+                    let result = json!({
+                        "context": { "slot": block.block_height },
+                        "value": { "slot": block.block_height, "block": block, "err": null },
+                    });
+                    messages.push(notification(method, id, result));
+                }
+                (Subscription::Signature { signature, commitment: Commitment::Processed }, Event::Block(block))
+                    if contains_signature(block, signature) =>
+                {
+                    messages.push(notification(method, id, signature_result(block.block_height)));
+                    finished.push(id);
+                }
+                (Subscription::Signature { signature, commitment: Commitment::Confirmed }, Event::Confirmed(slot)) => {
+                    let included = self.node.blockstore.lock().await.get(*slot).is_some_and(|block| contains_signature(block, signature));
+                    if included {
+                        messages.push(notification(method, id, signature_result(*slot)));
+                        finished.push(id);
+                    }
+                }
+                (Subscription::Account { pubkey, balance }, Event::Block(block)) => {
+                    let current = self.node.balances.lock().await.get(pubkey.as_str()).copied().unwrap_or(0);
+                    if current != *balance {
+                        *balance = current;
+                        let result = json!({ "context": { "slot": block.block_height }, "value": { "lamports": current } });
+                        messages.push(notification(method, id, result));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for id in finished {
+            self.subscriptions.remove(&id);
+        }
+        messages
+    }
+}
+
+/// Serves subscriptions on one WebSocket until the client goes away.
+pub async fn handle_socket(node: Arc<PoHGenerator>, socket: WebSocket) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut events = node.events.subscribe();
+    let mut session = Session {
+        node,
+        subscriptions: HashMap::new(),
+        next_id: 0,
+    };
+
+    loop {
+        let messages = tokio::select! {
+            message = receiver.next() => match message {
+                Some(Ok(message)) if message.is_text() => session.handle_request(message.to_str().unwrap_or_default()).await,
+                Some(Ok(message)) if message.is_close() => break,
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => session.handle_event(&event).await,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket subscriber fell behind, skipped {} events", skipped);
+                    session.catch_up().await
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        for message in messages {
+            if sender.send(message).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::clock::SystemClock;
+    use validator::keypair::Keypair;
+    use validator::transaction::Transaction;
+    use crate::submit;
+
+    fn session(node: PoHGenerator) -> Session {
+        Session { node: Arc::new(node), subscriptions: HashMap::new(), next_id: 0 }
+    }
+
+    fn request(method: &str, params: Value) -> String {
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string()
+    }
+
+    #[tokio::test]
+    async fn refuses_subscriptions_past_the_limit() {
+        let mut session = session(PoHGenerator::for_tests(&[]));
+        for _ in 0..MAX_SUBSCRIPTIONS_PER_SESSION {
+            session.handle_request(&request("slotSubscribe", json!([]))).await;
+        }
+        let refused = session.handle_request(&request("slotSubscribe", json!([]))).await;
+        assert!(refused[0].to_str().unwrap().contains("error"));
+        assert_eq!(session.subscriptions.len(), MAX_SUBSCRIPTIONS_PER_SESSION);
+
+        session.handle_request(&request("slotUnsubscribe", json!([0]))).await;
+        let accepted = session.handle_request(&request("slotSubscribe", json!([]))).await;
+        assert!(accepted[0].to_str().unwrap().contains("result"));
+    }
+
+    #[tokio::test]
+    async fn catching_up_finishes_signatures_that_landed_while_behind() {
+        let alice = Keypair::from_seed([2; 32]);
+        let mut session = session(PoHGenerator::for_tests(&[(alice.pubkey(), 100)]));
+        let node = Arc::clone(&session.node);
+        let blockhash = hex::encode(*node.parent_hash.lock().await);
+        let transaction = Transaction::new(&alice, "bob".to_string(), 1, blockhash);
+        let signature = submit::submit_transaction(&node, transaction).await.unwrap();
+        let params = json!([signature, { "commitment": "processed" }]);
+        session.handle_request(&request("signatureSubscribe", params)).await;
+        assert!(session.catch_up().await.is_empty());
+
+        // The block event is never seen, as if it was skipped
+        crate::block::produce_block(&node, &SystemClock).await;
+        let messages = session.catch_up().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].to_str().unwrap().contains("signatureNotification"));
+        assert!(session.subscriptions.is_empty());
+    }
+}
@@ -0,0 +1,13 @@
+use validator::inflation::pay_epoch_rewards;
+use crate::PoHGenerator;
+
+/// Pays the rewards for the epoch that just finished out of the node's bank.
+pub async fn distribute_epoch_rewards(node: &PoHGenerator, epoch: u64) {
+    let rewards = {
+        let mut balances = node.balances.lock().await;
+        let mut stakes = node.stakes.lock().await;
+        let mut vote_credits = node.vote_credits.lock().await;
+        pay_epoch_rewards(&node.genesis, &mut stakes, &mut balances, &mut vote_credits, epoch)
+    };
+    *node.epoch_rewards.lock().await = rewards;
+}
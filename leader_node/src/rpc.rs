@@ -0,0 +1,387 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+use validator::rpc_client::{Commitment, RpcStakeAccount, SignatureStatus};
+use validator::stake::{Stake, StakeAccountAction};
+use validator::transaction::Transaction;
+use crate::pubsub;
+use crate::submit::{self, SubmitError};
+use crate::PoHGenerator;
+use tracing::{debug, info};
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Server errors
+const TRANSACTION_REJECTED: i64 = -32002;
+
+/// Larger request bodies are refused before they are read.
+const MAX_REQUEST_BODY_BYTES: u64 = 1024 * 1024;
+/// Longer batches get a single error instead of being run.
+const MAX_BATCH_REQUESTS: usize = 100;
+/// Most signatures one getSignatureStatuses call may ask about, as in Solana.
+const MAX_SIGNATURE_STATUSES: usize = 256;
+
+#[derive(Serialize, Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl From<SubmitError> for RpcError {
+    fn from(e: SubmitError) -> Self {
+        RpcError::new(TRANSACTION_REJECTED, e.to_string())
+    }
+}
+
+/// Positional parameter `index`, deserialized as `T`.
+fn param<T: DeserializeOwned>(params: &Value, index: usize) -> Result<T, RpcError> {
+    let value = params
+        .get(index)
+        .cloned()
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing parameter {}", index)))?;
+    serde_json::from_value(value).map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid parameter {}: {}", index, e)))
+}
+
+/// Solana wraps account and blockhash results with the slot they were read at.
+fn with_context(slot: u64, value: Value) -> Value {
+    json!({ "context": { "slot": slot }, "value": value })
+}
+
+async fn call(node: &PoHGenerator, method: &str, params: &Value) -> Result<Value, RpcError> {
+    let slot = *node.block_height.lock().await;
+    let epoch_schedule = &node.genesis.epoch_schedule;
+
+    match method {
+        "getBalance" => {
+            let pubkey: String = param(params, 0)?;
+            let balance = node.balances.lock().await.get(&pubkey).copied().unwrap_or(0);
+            Ok(with_context(slot, json!(balance)))
+        }
+        "getBlock" => {
+            let height: u64 = param(params, 0)?;
+            let block = node.blockstore.lock().await.get(height).cloned();
+            Ok(json!(block))
+        }
+        "getBlockHeight" => Ok(json!(node.blockstore.lock().await.height())),
+        "getTransaction" => {
+            let signature: String = param(params, 0)?;
+            let signature = bs58::decode(&signature)
+                .into_vec()
+                .map_err(|_| RpcError::new(INVALID_PARAMS, "signature is not base58"))?;
+            let blockstore = node.blockstore.lock().await;
+            Ok(match blockstore.find_transaction(&signature) {
+                Some((block, transaction)) => json!({
+                    "slot": block.block_height,
+                    "blockTime": block.timestamp,
+                    "transaction": transaction,
+                }),
+                None => Value::Null,
+            })
+        }
+        "sendTransaction" => {
+            let transaction: Transaction = param(params, 0)?;
+            let signature = submit::submit_transaction(node, transaction).await?;
+            debug!("Received transaction {} over RPC", signature);
+            Ok(json!(signature))
+        }
+        "getSignatureStatuses" => {
+            let signatures: Vec<String> = param(params, 0)?;
+            if signatures.len() > MAX_SIGNATURE_STATUSES {
+                let message = format!("{} signatures is over the limit of {}", signatures.len(), MAX_SIGNATURE_STATUSES);
+                return Err(RpcError::new(INVALID_PARAMS, message));
+            }
+            let signatures = signatures
+                .iter()
+                .map(|signature| bs58::decode(signature).into_vec())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| RpcError::new(INVALID_PARAMS, "signature is not base58"))?;
+            let blockstore = node.blockstore.lock().await;
+            // The chain has no forks, so confirming a slot confirms its ancestors
+            let latest_confirmed = node.confirmations.lock().await.latest_confirmed();
+            let statuses: Vec<Option<SignatureStatus>> = signatures
+                .iter()
+                .map(|signature| {
+                    blockstore.transaction_slot(signature).map(|slot| SignatureStatus {
+                        slot,
+                        confirmation_status: match latest_confirmed {
+                            Some(confirmed) if confirmed >= slot => Commitment::Confirmed,
+                            _ => Commitment::Processed,
+                        },
+                    })
+                })
+                .collect();
+            Ok(with_context(slot, json!(statuses)))
+        }
+        "delegateStake" => {
+            let stake: Stake = param(params, 0)?;
+            Ok(json!(submit::delegate_stake(node, &stake).await?))
+        }
+        "deactivateStake" => {
+            let action: StakeAccountAction = param(params, 0)?;
+            submit::deactivate_stake(node, &action).await?;
+            Ok(Value::Null)
+        }
+        "withdrawStake" => {
+            let action: StakeAccountAction = param(params, 0)?;
+            Ok(json!(submit::withdraw_stake(node, &action).await?))
+        }
+        "getStakeAccounts" => {
+            let staker: String = param(params, 0)?;
+            let stakes = node.stakes.lock().await;
+            let mut accounts: Vec<RpcStakeAccount> = stakes
+                .accounts()
+                .filter(|(_, account)| account.staker == staker)
+                .map(|(address, account)| RpcStakeAccount {
+                    address: address.clone(),
+                    validator_id: account.validator_id.clone(),
+                    amount: account.amount,
+                    activation_epoch: account.activation_epoch,
+                    deactivation_epoch: account.deactivation_epoch,
+                })
+                .collect();
+            accounts.sort_by(|a, b| a.address.cmp(&b.address));
+            Ok(json!(accounts))
+        }
+        "getLatestBlockhash" => {
+            let blockstore = node.blockstore.lock().await;
+            let blockhash = blockstore.tip_hash().unwrap_or_else(|| hex::encode(node.genesis.hash()));
+            Ok(with_context(slot, json!({ "blockhash": blockhash, "blockHeight": blockstore.height() })))
+        }
+        "getLeaderSchedule" => {
+            // This node produces every block, so it leads every slot of the epoch
+            let slots: Vec<u64> = (0..epoch_schedule.slots_per_epoch).collect();
+            Ok(json!({ node.identity.pubkey(): slots }))
+        }
+        "getVoteAccounts" => {
+            let epoch = epoch_schedule.epoch_for_slot(slot);
+            let active_stakes = node.stakes.lock().await.active_stakes(epoch);
+            let vote_credits = node.vote_credits.lock().await.clone();
+            let connected = node.validators.lock().await.clone();
+            let commissions: BTreeMap<&str, u8> = node
+                .genesis
+                .validators
+                .iter()
+                .map(|validator| (validator.identity.as_str(), validator.commission))
+                .collect();
+
+            let mut current = Vec::new();
+            let mut delinquent = Vec::new();
+            for (identity, stake) in active_stakes.into_iter().collect::<BTreeMap<_, _>>() {
+                let account = json!({
+                    "nodePubkey": identity,
+                    "activatedStake": stake,
+                    "commission": commissions.get(identity.as_str()).copied().unwrap_or(0),
+                    "epochCredits": vote_credits.get(&identity).copied().unwrap_or(0),
+                });
+                if connected.contains_key(&identity) {
+                    current.push(account);
+                } else {
+                    delinquent.push(account);
+                }
+            }
+            Ok(json!({ "current": current, "delinquent": delinquent }))
+        }
+        "getEpochInfo" => Ok(json!({
+            "absoluteSlot": slot,
+            "blockHeight": node.blockstore.lock().await.height(),
+            "epoch": epoch_schedule.epoch_for_slot(slot),
+            "slotIndex": slot % epoch_schedule.slots_per_epoch.max(1),
+            "slotsInEpoch": epoch_schedule.slots_per_epoch,
+        })),
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("method {} not found", method))),
+    }
+}
+
+/// Answers one request object. Notifications (no `id`) get no response.
+async fn handle_request(node: &PoHGenerator, request: &Value) -> Option<Value> {
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str);
+    let result = match method {
+        Some(method) if request.get("jsonrpc").and_then(Value::as_str) == Some("2.0") => {
+            let params = request.get("params").cloned().unwrap_or_else(|| json!([]));
+            call(node, method, &params).await
+        }
+        _ => Err(RpcError::new(INVALID_REQUEST, "invalid request")),
+    };
+
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    })
+}
+
+/// A single request or a batch, per the JSON-RPC 2.0 spec. None when there
+/// is nothing to send back because every request was a notification.
+async fn handle_body(node: &PoHGenerator, body: &[u8]) -> Option<Value> {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(json!({ "jsonrpc": "2.0", "id": null, "error": RpcError::new(PARSE_ERROR, e.to_string()) }));
+        }
+    };
+    match request {
+        Value::Array(requests) if requests.len() > MAX_BATCH_REQUESTS => {
+            let message = format!("batch of {} requests is over the limit of {}", requests.len(), MAX_BATCH_REQUESTS);
+            Some(json!({ "jsonrpc": "2.0", "id": null, "error": RpcError::new(INVALID_REQUEST, message) }))
+        }
+        Value::Array(requests) if !requests.is_empty() => {
+            let mut responses = Vec::new();
+            for request in &requests {
+                responses.extend(handle_request(node, request).await);
+            }
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        Value::Object(_) => handle_request(node, &request).await,
+        _ => Some(json!({ "jsonrpc": "2.0", "id": null, "error": RpcError::new(INVALID_REQUEST, "invalid request") })),
+    }
+}
+
+/// JSON-RPC over HTTP POST and WebSocket subscriptions on the same address.
+pub async fn start_rpc(node: Arc<PoHGenerator>, addr: SocketAddr) {
+    let ws_node = Arc::clone(&node);
+    let rpc = warp::post()
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(MAX_REQUEST_BODY_BYTES))
+        .and(warp::body::bytes())
+        .and_then(move |body: warp::hyper::body::Bytes| {
+            let node = Arc::clone(&node);
+            async move {
+                let reply = match handle_body(&node, &body).await {
+                    Some(response) => warp::reply::json(&response).into_response(),
+                    None => StatusCode::NO_CONTENT.into_response(),
+                };
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+
+    let pubsub = warp::path::end()
+        .and(warp::ws())
+        .map(move |ws: warp::ws::Ws| {
+            let node = Arc::clone(&ws_node);
+            ws.on_upgrade(move |socket| pubsub::handle_socket(node, socket))
+        });
+
+    info!("Starting JSON-RPC server on http://{} (subscriptions on ws://{})", addr, addr);
+    warp::serve(pubsub.or(rpc)).run(addr).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::clock::SystemClock;
+    use validator::keypair::Keypair;
+
+    fn node() -> PoHGenerator {
+        PoHGenerator::for_tests(&[("alice".to_string(), 100)])
+    }
+
+    async fn handle(node: &PoHGenerator, body: Value) -> Option<Value> {
+        handle_body(node, &serde_json::to_vec(&body).unwrap()).await
+    }
+
+    async fn respond(node: &PoHGenerator, body: Value) -> Value {
+        handle(node, body).await.expect("a response")
+    }
+
+    #[tokio::test]
+    async fn dispatches_by_method() {
+        let node = node();
+        let response = respond(&node, json!({ "jsonrpc": "2.0", "id": 1, "method": "getBalance", "params": ["alice"] })).await;
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "id": 1, "result": { "context": { "slot": 0 }, "value": 100 } }));
+    }
+
+    #[tokio::test]
+    async fn reports_json_rpc_errors() {
+        let node = node();
+        let error_code = |response: Value| response["error"]["code"].as_i64().unwrap();
+
+        let parse_error = handle_body(&node, b"{not json").await.unwrap();
+        assert_eq!(parse_error["id"], Value::Null);
+        assert_eq!(error_code(parse_error), PARSE_ERROR);
+        let unknown = respond(&node, json!({ "jsonrpc": "2.0", "id": 2, "method": "getNothing" })).await;
+        assert_eq!(unknown["id"], 2);
+        assert_eq!(error_code(unknown), METHOD_NOT_FOUND);
+        let missing_param = respond(&node, json!({ "jsonrpc": "2.0", "id": 3, "method": "getBalance" })).await;
+        assert_eq!(error_code(missing_param), INVALID_PARAMS);
+        let wrong_version = respond(&node, json!({ "jsonrpc": "1.0", "id": 4, "method": "getBlockHeight" })).await;
+        assert_eq!(error_code(wrong_version), INVALID_REQUEST);
+        assert_eq!(error_code(respond(&node, json!([])).await), INVALID_REQUEST);
+        assert_eq!(error_code(respond(&node, json!(7)).await), INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn answers_batches_in_order_without_notifications() {
+        let node = node();
+        let response = respond(
+            &node,
+            json!([
+                { "jsonrpc": "2.0", "id": 1, "method": "getBlockHeight" },
+                { "jsonrpc": "2.0", "method": "getBlockHeight" },
+                { "jsonrpc": "2.0", "id": 2, "method": "getNothing" },
+                { "id": 3 },
+            ]),
+        )
+        .await;
+        let responses = response.as_array().unwrap();
+        let ids: Vec<_> = responses.iter().map(|response| response["id"].clone()).collect();
+        assert_eq!(ids, vec![json!(1), json!(2), json!(3)]);
+        assert_eq!(responses[0]["result"], 0);
+        assert_eq!(responses[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[2]["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn notifications_get_no_response() {
+        let node = node();
+        assert_eq!(handle(&node, json!({ "jsonrpc": "2.0", "method": "getBlockHeight" })).await, None);
+        let notifications = json!([{ "jsonrpc": "2.0", "method": "getBlockHeight" }, { "jsonrpc": "2.0", "method": "getEpochInfo" }]);
+        assert_eq!(handle(&node, notifications).await, None);
+    }
+
+    #[tokio::test]
+    async fn refuses_oversized_batches() {
+        let node = node();
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "getBlockHeight" });
+        let full = respond(&node, Value::Array(vec![request.clone(); MAX_BATCH_REQUESTS])).await;
+        assert_eq!(full.as_array().unwrap().len(), MAX_BATCH_REQUESTS);
+
+        let over = respond(&node, Value::Array(vec![request; MAX_BATCH_REQUESTS + 1])).await;
+        assert_eq!(over["id"], Value::Null);
+        assert_eq!(over["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn limits_and_answers_signature_statuses() {
+        let alice = Keypair::from_seed([2; 32]);
+        let node = PoHGenerator::for_tests(&[(alice.pubkey(), 100)]);
+        let blockhash = hex::encode(*node.parent_hash.lock().await);
+        let transaction = Transaction::new(&alice, "bob".to_string(), 1, blockhash);
+        let signature = submit::submit_transaction(&node, transaction).await.unwrap();
+        crate::block::produce_block(&node, &SystemClock).await;
+
+        let request = |signatures: Vec<String>| {
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "getSignatureStatuses", "params": [signatures] })
+        };
+        let unknown = bs58::encode([9; 64]).into_string();
+        let response = respond(&node, request(vec![signature.clone(), unknown])).await;
+        assert_eq!(response["result"]["value"], json!([{ "slot": 1, "confirmationStatus": "processed" }, null]));
+
+        let too_many = respond(&node, request(vec![signature; MAX_SIGNATURE_STATUSES + 1])).await;
+        assert_eq!(too_many["error"]["code"], INVALID_PARAMS);
+    }
+}
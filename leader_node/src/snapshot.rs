@@ -0,0 +1,176 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use validator::poh_handler::PohCheckpoint;
+use validator::snapshot::{Snapshot, SnapshotArchive};
+use validator::transaction::execute_transaction;
+use crate::PoHGenerator;
+use tracing::{error, info};
+
+/// Captures the bank, stakes and chain position at the newest block, or
+/// None before the first block. The blockstore stays locked throughout, and
+/// `produce_block` only changes the bank while holding it, so everything
+/// read belongs to the same slot.
+async fn take_snapshot(node: &PoHGenerator) -> Option<Snapshot> {
+    let blockstore = node.blockstore.lock().await;
+    let (slot, block_hash) = (blockstore.height(), blockstore.tip_hash()?);
+    let poh = {
+        let poh = node.poh.lock().await;
+        PohCheckpoint {
+            height: poh.len(),
+            hash: poh.last_hash(),
+        }
+    };
+    let snapshot = Snapshot {
+        genesis_hash: bs58::encode(node.genesis.hash()).into_string(),
+        slot,
+        block_hash,
+        poh,
+        balances: node.balances.lock().await.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        stakes: node.stakes.lock().await.clone(),
+        vote_credits: node.vote_credits.lock().await.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+    };
+    drop(blockstore);
+    Some(snapshot)
+}
+
+/// Every `interval_slots` blocks, archives a snapshot into the ledger and
+/// makes it the one served to joining validators.
+pub async fn run_snapshots(node: Arc<PoHGenerator>, ledger_dir: PathBuf, interval_slots: u64) {
+    let slot_duration = tokio::time::Duration::from_millis(node.poh_timing.slot_duration_ms());
+    let mut last_slot = node.latest_snapshot.lock().await.as_ref().map_or(0, |archive| archive.slot);
+
+    loop {
+        tokio::time::sleep(slot_duration).await;
+        if *node.block_height.lock().await < last_slot + interval_slots.max(1) {
+            continue;
+        }
+        let snapshot = match take_snapshot(&node).await {
+            Some(snapshot) => snapshot,
+            None => continue,
+        };
+
+        // Compressing and writing can take a while for a large bank
+        let dir = ledger_dir.clone();
+        let archived = tokio::task::spawn_blocking(move || {
+            let archive = snapshot.archive()?;
+            archive.save(&dir)?;
+            Ok::<_, std::io::Error>(archive)
+        })
+        .await
+        .unwrap();
+        match archived {
+            Ok(archive) => {
+                info!("Wrote snapshot at slot {} ({} bytes, hash {})", archive.slot, archive.data.len(), archive.hash);
+                last_slot = archive.slot;
+                *node.latest_snapshot.lock().await = Some(archive);
+            }
+            Err(e) => error!("Failed to write snapshot: {}", e),
+        }
+    }
+}
+
+/// Reloads the bank from our newest snapshot after a restart. The snapshot
+/// must be a point on our own chain; the blocks after it are then replayed
+/// with `replay_blocks`.
+pub async fn restore(node: &PoHGenerator, archive: &SnapshotArchive) -> io::Result<()> {
+    let snapshot = archive.unpack()?;
+    if snapshot.genesis_hash != bs58::encode(node.genesis.hash()).into_string() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot is from a different genesis"));
+    }
+    {
+        let blockstore = node.blockstore.lock().await;
+        if snapshot.slot > blockstore.height() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("snapshot is past the ledger tip at {}", blockstore.height()),
+            ));
+        }
+        if blockstore.get(snapshot.slot).is_some_and(|block| block.block_hash != snapshot.block_hash) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot is not on our chain"));
+        }
+    }
+    *node.balances.lock().await = snapshot.balances.into_iter().collect();
+    *node.stakes.lock().await = snapshot.stakes;
+    *node.vote_credits.lock().await = snapshot.vote_credits.into_iter().collect();
+    Ok(())
+}
+
+/// Brings the bank from `slot` up to the ledger tip by re-applying the
+/// stored blocks after it the way producing them did: slashing for their
+/// evidence, their stake instructions, their transfers and rewards at each
+/// epoch boundary. Votes are not stored in blocks, so those rewards only
+/// count the credits held at `slot`. Every instruction and transfer executed
+/// when its block was built, so one that fails now means the bank and the
+/// chain disagree.
+pub async fn replay_blocks(node: &PoHGenerator, slot: u64) -> io::Result<usize> {
+    let blocks = node.blockstore.lock().await.range(slot + 1, u64::MAX, usize::MAX);
+    let epoch_schedule = &node.genesis.epoch_schedule;
+    for block in &blocks {
+        {
+            let mut balances = node.balances.lock().await;
+            let mut stakes = node.stakes.lock().await;
+            for evidence in &block.evidence {
+                stakes.slash(evidence.offense.offender(), node.genesis.slashing.slash_fraction);
+            }
+            let epoch = epoch_schedule.epoch_for_slot(block.block_height);
+            for instruction in &block.stake_instructions {
+                stakes.apply(&mut balances, instruction, epoch).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("stake instruction {} in block {} fails on replay: {}", bs58::encode(instruction.signature()).into_string(), block.block_height, e),
+                    )
+                })?;
+            }
+            for transaction in &block.transactions {
+                execute_transaction(&mut balances, transaction).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("transaction {} in block {} fails on replay: {}", bs58::encode(&transaction.signature).into_string(), block.block_height, e),
+                    )
+                })?;
+            }
+        }
+        let finished_epoch = epoch_schedule.epoch_for_slot(block.block_height - 1);
+        if epoch_schedule.epoch_for_slot(block.block_height) > finished_epoch {
+            crate::rewards::distribute_epoch_rewards(node, finished_epoch).await;
+        }
+    }
+    Ok(blocks.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::clock::SystemClock;
+    use validator::keypair::Keypair;
+    use validator::transaction::Transaction;
+    use crate::submit;
+
+    async fn transfer_in_a_block(node: &PoHGenerator, sender: &Keypair, amount: u64) {
+        let blockhash = hex::encode(*node.parent_hash.lock().await);
+        submit::submit_transaction(node, Transaction::new(sender, "bob".to_string(), amount, blockhash)).await.unwrap();
+        crate::block::produce_block(node, &SystemClock).await;
+    }
+
+    #[tokio::test]
+    async fn restoring_a_snapshot_and_replaying_reaches_the_same_bank() {
+        let alice = Keypair::from_seed([2; 32]);
+        let accounts = [(alice.pubkey(), 100)];
+        let node = PoHGenerator::for_tests(&accounts);
+        transfer_in_a_block(&node, &alice, 10).await;
+        let snapshot = take_snapshot(&node).await.unwrap();
+        assert_eq!(snapshot.slot, 1);
+        assert_eq!(snapshot.balances["bob"], 10);
+        transfer_in_a_block(&node, &alice, 20).await;
+
+        let restarted = PoHGenerator::for_tests(&accounts);
+        for block in node.blockstore.lock().await.range(1, u64::MAX, usize::MAX) {
+            restarted.blockstore.lock().await.insert(block).unwrap();
+        }
+        restore(&restarted, &snapshot.archive().unwrap()).await.unwrap();
+        assert_eq!(replay_blocks(&restarted, snapshot.slot).await.unwrap(), 1);
+        assert_eq!(*restarted.balances.lock().await, *node.balances.lock().await);
+        assert_eq!(restarted.balances.lock().await["bob"], 30);
+    }
+}
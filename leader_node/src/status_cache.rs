@@ -0,0 +1,80 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use validator::block::Block;
+use crate::submit::SubmitError;
+
+/// Blocks a blockhash stays usable for new transactions, as in Solana.
+pub const MAX_RECENT_BLOCKHASHES: usize = 150;
+
+/// Recent blockhashes and the signatures submitted against each. Every
+/// transaction and stake instruction names a recent blockhash, so a replay
+/// is caught here until its blockhash expires, and rejected as too old after.
+#[derive(Debug, Default)]
+pub struct StatusCache {
+    blockhashes: VecDeque<String>,
+    signatures: HashMap<String, HashSet<Vec<u8>>>,
+}
+
+impl StatusCache {
+    /// Seeds the cache after a restart from the blockhash before `recent`
+    /// and the blocks themselves, oldest first.
+    pub fn new(parent_blockhash: String, recent: &[Block]) -> Self {
+        let mut cache = StatusCache::default();
+        cache.push_blockhash(parent_blockhash);
+        for block in recent {
+            cache.push_blockhash(block.block_hash.clone());
+            for transaction in &block.transactions {
+                let _ = cache.insert(&transaction.recent_blockhash, &transaction.signature);
+            }
+            for instruction in &block.stake_instructions {
+                let _ = cache.insert(instruction.recent_blockhash(), instruction.signature());
+            }
+        }
+        cache
+    }
+
+    pub fn push_blockhash(&mut self, blockhash: String) {
+        self.signatures.entry(blockhash.clone()).or_default();
+        self.blockhashes.push_back(blockhash);
+        while self.blockhashes.len() > MAX_RECENT_BLOCKHASHES {
+            if let Some(expired) = self.blockhashes.pop_front() {
+                self.signatures.remove(&expired);
+            }
+        }
+    }
+
+    /// Records a signature, failing if its blockhash has expired or the
+    /// signature was seen before.
+    pub fn insert(&mut self, blockhash: &str, signature: &[u8]) -> Result<(), SubmitError> {
+        let signatures = self
+            .signatures
+            .get_mut(blockhash)
+            .ok_or_else(|| SubmitError::BlockhashNotFound(blockhash.to_string()))?;
+        if !signatures.insert(signature.to_vec()) {
+            return Err(SubmitError::AlreadyProcessed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::clock::SystemClock;
+    use validator::keypair::Keypair;
+    use validator::stake::Stake;
+    use crate::{submit, PoHGenerator};
+
+    #[tokio::test]
+    async fn a_restarted_cache_still_refuses_replayed_stake_instructions() {
+        let alice = Keypair::from_seed([2; 32]);
+        let node = PoHGenerator::for_tests(&[(alice.pubkey(), 100)]);
+        let genesis_blockhash = hex::encode(*node.parent_hash.lock().await);
+        let stake = Stake::new(&alice, "validator_a".to_string(), 30, genesis_blockhash.clone());
+        submit::delegate_stake(&node, &stake).await.unwrap();
+        crate::block::produce_block(&node, &SystemClock).await;
+
+        let block = node.blockstore.lock().await.get(1).cloned().unwrap();
+        let mut cache = StatusCache::new(genesis_blockhash.clone(), &[block]);
+        assert_eq!(cache.insert(&genesis_blockhash, &stake.signature), Err(SubmitError::AlreadyProcessed));
+    }
+}
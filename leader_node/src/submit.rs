@@ -0,0 +1,176 @@
+use std::fmt;
+use validator::metrics::metrics;
+use validator::stake::{Stake, StakeAccountAction, StakeError, StakeInstruction};
+use validator::transaction::{Transaction, TransactionError, MAX_PENDING_TRANSACTIONS};
+use crate::PoHGenerator;
+use tracing::{debug, info};
+
+#[derive(Debug, PartialEq)]
+pub enum SubmitError {
+    InvalidSignature,
+    ZeroAmount,
+    MempoolFull,
+    BlockhashNotFound(String),
+    AlreadyProcessed,
+    Transaction(TransactionError),
+    Stake(StakeError),
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::InvalidSignature => write!(f, "invalid signature"),
+            SubmitError::ZeroAmount => write!(f, "amount must be greater than zero"),
+            SubmitError::MempoolFull => write!(f, "too many transactions are waiting for a block, try again later"),
+            SubmitError::BlockhashNotFound(blockhash) => write!(f, "blockhash {} is unknown or too old", blockhash),
+            SubmitError::AlreadyProcessed => write!(f, "already processed"),
+            SubmitError::Transaction(e) => write!(f, "{}", e),
+            SubmitError::Stake(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Queues a signed transfer for the next block, returning its base58
+/// signature. Balances only change once the block is produced, so a
+/// transfer that passes the balance check here can still be dropped then.
+pub async fn submit_transaction(node: &PoHGenerator, transaction: Transaction) -> Result<String, SubmitError> {
+    if !transaction.validate() {
+        return Err(SubmitError::InvalidSignature);
+    }
+    if transaction.amount == 0 {
+        return Err(SubmitError::ZeroAmount);
+    }
+    let balance = node.balances.lock().await.get(&transaction.sender).copied().unwrap_or(0);
+    if balance < transaction.amount {
+        return Err(SubmitError::Transaction(TransactionError::InsufficientFunds { balance, amount: transaction.amount }));
+    }
+    if node.transactions.lock().await.len() >= MAX_PENDING_TRANSACTIONS {
+        return Err(SubmitError::MempoolFull);
+    }
+    node.status_cache.lock().await.insert(&transaction.recent_blockhash, &transaction.signature)?;
+    let signature = bs58::encode(&transaction.signature).into_string();
+    debug!("Queued transaction {}", signature);
+    let mut transactions = node.transactions.lock().await;
+    transactions.push(transaction);
+    metrics().mempool_size.set(transactions.len() as i64);
+    Ok(signature)
+}
+
+/// Queues a stake instruction for the next block. Like a transfer it is
+/// checked against the bank now and applied, or dropped, when the block is
+/// built, so the leader's ledger replays to the same stakes. Returns what
+/// the check found a withdrawal would move, or zero.
+async fn submit_stake_instruction(node: &PoHGenerator, instruction: StakeInstruction) -> Result<u64, SubmitError> {
+    if !instruction.verify_signature() {
+        return Err(SubmitError::InvalidSignature);
+    }
+    let epoch = node.genesis.epoch_schedule.epoch_for_slot(*node.block_height.lock().await + 1);
+    let amount = {
+        let balances = node.balances.lock().await;
+        node.stakes.lock().await.check(&balances, &instruction, epoch).map_err(SubmitError::Stake)?
+    };
+    let mut pending = node.stake_instructions.lock().await;
+    if pending.len() >= MAX_PENDING_TRANSACTIONS {
+        return Err(SubmitError::MempoolFull);
+    }
+    node.status_cache.lock().await.insert(instruction.recent_blockhash(), instruction.signature())?;
+    pending.push(instruction);
+    Ok(amount)
+}
+
+/// Returns the address of the stake account the delegation will create.
+pub async fn delegate_stake(node: &PoHGenerator, stake: &Stake) -> Result<String, SubmitError> {
+    submit_stake_instruction(node, StakeInstruction::Delegate(stake.clone())).await?;
+    let stake_account = stake.stake_account();
+    info!("Queued delegation of {} tokens from {} to validator {} in stake account {}", stake.amount, stake.staker, stake.validator_id, stake_account);
+    Ok(stake_account)
+}
+
+pub async fn deactivate_stake(node: &PoHGenerator, action: &StakeAccountAction) -> Result<(), SubmitError> {
+    submit_stake_instruction(node, StakeInstruction::Deactivate(action.clone())).await?;
+    info!("Queued deactivation of stake account {}", action.stake_account);
+    Ok(())
+}
+
+/// Returns the amount the withdrawal would move back to the staker's balance now.
+pub async fn withdraw_stake(node: &PoHGenerator, action: &StakeAccountAction) -> Result<u64, SubmitError> {
+    let amount = submit_stake_instruction(node, StakeInstruction::Withdraw(action.clone())).await?;
+    info!("Queued withdrawal of {} tokens from stake account {} to {}", amount, action.stake_account, action.staker);
+    Ok(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::keypair::Keypair;
+    use validator::stake::StakeAction;
+
+    fn funded(keypair: &Keypair, balance: u64) -> PoHGenerator {
+        PoHGenerator::for_tests(&[(keypair.pubkey(), balance)])
+    }
+
+    async fn blockhash(node: &PoHGenerator) -> String {
+        hex::encode(*node.parent_hash.lock().await)
+    }
+
+    #[tokio::test]
+    async fn rejects_zero_and_unfunded_transfers() {
+        let alice = Keypair::from_seed([2; 32]);
+        let node = funded(&alice, 100);
+        let blockhash = blockhash(&node).await;
+
+        let zero = Transaction::new(&alice, "bob".to_string(), 0, blockhash.clone());
+        assert_eq!(submit_transaction(&node, zero).await, Err(SubmitError::ZeroAmount));
+        let overdraft = Transaction::new(&alice, "bob".to_string(), 101, blockhash.clone());
+        assert_eq!(
+            submit_transaction(&node, overdraft).await,
+            Err(SubmitError::Transaction(TransactionError::InsufficientFunds { balance: 100, amount: 101 }))
+        );
+        let mut forged = Transaction::new(&alice, "bob".to_string(), 1, blockhash.clone());
+        forged.amount = 2;
+        assert_eq!(submit_transaction(&node, forged).await, Err(SubmitError::InvalidSignature));
+
+        let transfer = Transaction::new(&alice, "bob".to_string(), 10, blockhash);
+        assert!(submit_transaction(&node, transfer.clone()).await.is_ok());
+        assert_eq!(submit_transaction(&node, transfer).await, Err(SubmitError::AlreadyProcessed));
+        assert_eq!(node.transactions.lock().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_transfers_once_the_mempool_is_full() {
+        let alice = Keypair::from_seed([2; 32]);
+        let node = funded(&alice, 100);
+        let blockhash = blockhash(&node).await;
+        let filler = Transaction::new(&alice, "bob".to_string(), 1, blockhash.clone());
+        *node.transactions.lock().await = vec![filler; MAX_PENDING_TRANSACTIONS];
+
+        let transfer = Transaction::new(&alice, "bob".to_string(), 1, blockhash.clone());
+        assert_eq!(submit_transaction(&node, transfer).await, Err(SubmitError::MempoolFull));
+        // Refusing it didn't use up the signature
+        node.transactions.lock().await.clear();
+        let transfer = Transaction::new(&alice, "bob".to_string(), 1, blockhash);
+        assert!(submit_transaction(&node, transfer).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn stake_instructions_wait_for_a_block() {
+        let alice = Keypair::from_seed([2; 32]);
+        let node = funded(&alice, 100);
+        let blockhash = blockhash(&node).await;
+
+        let stake = Stake::new(&alice, "validator_a".to_string(), 30, blockhash.clone());
+        assert_eq!(delegate_stake(&node, &stake).await, Ok(stake.stake_account()));
+        assert_eq!(node.balances.lock().await[&alice.pubkey()], 100);
+        assert!(node.stakes.lock().await.get(&stake.stake_account()).is_none());
+        assert_eq!(node.stake_instructions.lock().await.len(), 1);
+
+        let too_much = Stake::new(&alice, "validator_a".to_string(), 101, blockhash.clone());
+        assert!(matches!(delegate_stake(&node, &too_much).await, Err(SubmitError::Stake(StakeError::InsufficientFunds { .. }))));
+        let unknown = StakeAccountAction::new(&alice, "missing".to_string(), StakeAction::Deactivate, blockhash.clone());
+        assert!(matches!(deactivate_stake(&node, &unknown).await, Err(SubmitError::Stake(StakeError::AccountNotFound(_)))));
+        let mut forged = StakeAccountAction::new(&alice, stake.stake_account(), StakeAction::Deactivate, blockhash);
+        forged.stake_account = "other".to_string();
+        assert_eq!(deactivate_stake(&node, &forged).await, Err(SubmitError::InvalidSignature));
+        assert_eq!(node.stake_instructions.lock().await.len(), 1);
+    }
+}
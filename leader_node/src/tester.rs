@@ -2,11 +2,22 @@ use serde::Serialize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use warp::Filter;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
 use crate::PoHGenerator;
-use crate::network::GossipActivity;
-use validator::poh_handler::PohEntry; 
+use tracing::{info, warn};
+use validator::connection::PeerHealth;
+use validator::gossip::GossipActivity;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::broadcast;
+use validator::block::Block;
+use validator::inflation;
+use validator::metrics::metrics;
+use crate::pubsub::Event;
+
+/// Blocks within this many seconds of the newest one count towards
+/// average block time and throughput.
+const METRICS_WINDOW_SECS: u64 = 60;
 
 #[derive(Serialize, Default)]
 struct TestStatus {
@@ -16,18 +27,71 @@ struct TestStatus {
     block_validations: usize,
     block_generation: usize,
     gossip_protocol: bool,
+    gossip: GossipActivity,
+    gossip_average_hops: f64,
+    gossip_average_latency_ms: f64,
+    connected_peers: usize,
+    peer_connections: HashMap<String, PeerHealth>,
     transactions: usize,
     proof_of_stake: bool,
     leader_election: bool,
     total_circulating_supply: u64,
+    staked_supply: u64,
+    inflation_rate: f64,
+    last_epoch_rewards: u64,
     average_block_time: f64,
-    throughput: usize,
+    throughput: f64,
     block_count: usize,
     current_epoch: u64,
 }
 
+/// Block production and confirmation as seen through node events.
+#[derive(Default)]
+struct BlockMetrics {
+    proposed: usize,
+    confirmed: usize,
+    /// Timestamp and transaction count of recent blocks, oldest first.
+    recent: VecDeque<(u64, usize)>,
+}
+
+impl BlockMetrics {
+    fn record_block(&mut self, block: &Block) {
+        self.proposed += 1;
+        self.recent.push_back((block.timestamp, block.transactions.len()));
+        while self.recent.front().is_some_and(|(timestamp, _)| timestamp.saturating_add(METRICS_WINDOW_SECS) < block.timestamp) {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Seconds between consecutive blocks in the window.
+    fn average_block_time(&self) -> f64 {
+        match (self.recent.front(), self.recent.back()) {
+            (Some((first, _)), Some((last, _))) if self.recent.len() > 1 => {
+                last.saturating_sub(*first) as f64 / (self.recent.len() - 1) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Transactions per second over the window; the oldest block only marks
+    /// where the window starts.
+    fn transactions_per_second(&self) -> f64 {
+        let span = match (self.recent.front(), self.recent.back()) {
+            // Leader clocks can step back, which counts as no time passing
+            (Some((first, _)), Some((last, _))) => last.saturating_sub(*first),
+            _ => 0,
+        };
+        if span == 0 {
+            return 0.0;
+        }
+        let transactions: usize = self.recent.iter().skip(1).map(|(_, count)| count).sum();
+        transactions as f64 / span as f64
+    }
+}
+
 struct TestMonitor {
     status: Arc<Mutex<TestStatus>>,
+    block_metrics: Arc<Mutex<BlockMetrics>>,
     poh_generator: Arc<PoHGenerator>,
     gossip_activity: Arc<Mutex<GossipActivity>>,
 }
@@ -36,91 +100,102 @@ impl TestMonitor {
     fn new(poh_generator: Arc<PoHGenerator>, gossip_activity: Arc<Mutex<GossipActivity>>) -> Self {
         TestMonitor {
             status: Arc::new(Mutex::new(TestStatus::default())),
+            block_metrics: Arc::new(Mutex::new(BlockMetrics::default())),
             poh_generator,
             gossip_activity,
         }
     }
 
+    /// Feeds block metrics from block production and vote events.
+    async fn track_blocks(block_metrics: Arc<Mutex<BlockMetrics>>, mut events: broadcast::Receiver<Event>) {
+        loop {
+            match events.recv().await {
+                Ok(Event::Block(block)) => block_metrics.lock().await.record_block(&block),
+                Ok(Event::Confirmed(_)) => block_metrics.lock().await.confirmed += 1,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Test monitor fell behind, missed {} block events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+
     async fn run_tests(&self) {
         loop {
             {
+                let poh_entries = self.poh_generator.poh.lock().await.len();
                 let mut status = self.status.lock().await;
-                let poh_guard = self.poh_generator.poh.lock().await;
 
                 // Proof of History
-                status.proof_of_history = !poh_guard.is_empty();
+                status.proof_of_history = poh_entries > 0;
 
                 // Validator Connection/Registration
                 status.validator_count = self.poh_generator.validators.lock().await.len();
 
-                // Block Proposals/Validation and Generation
-                let poh_entries = poh_guard.len();
-                status.block_proposals = poh_entries;
-                status.block_generation = poh_entries;
-
-                status.block_validations = poh_entries;
+                // Block Proposals/Validation and Generation; this node generates
+                // every block it proposes, validation is supermajority confirmation
+                let block_metrics = self.block_metrics.lock().await;
+                status.block_proposals = block_metrics.proposed;
+                status.block_generation = block_metrics.proposed;
+                status.block_validations = block_metrics.confirmed;
 
                 // Gossip Protocol
                 let gossip_activity = self.gossip_activity.lock().await;
-                status.gossip_protocol = gossip_activity.messages_received > 0;
+                status.gossip_protocol = gossip_activity.messages_received > 0 || gossip_activity.messages_sent > 0;
+                status.gossip_average_hops = gossip_activity.average_hops();
+                status.gossip_average_latency_ms = gossip_activity.average_latency_ms();
+                status.gossip = gossip_activity.clone();
+
+                // Peer Connections
+                status.peer_connections = self.poh_generator.connections.health().await;
+                status.connected_peers = status.peer_connections.values().filter(|peer| peer.connected).count();
 
                 // Transactions
                 status.transactions = self.poh_generator.transactions.lock().await.len();
 
                 // Proof of Stake
-                status.proof_of_stake = !self.poh_generator.stakes.lock().await.is_empty();
+                let epoch = self.poh_generator.current_epoch().await;
+                status.proof_of_stake = !self.poh_generator.stakes.lock().await.active_stakes(epoch).is_empty();
 
                 // Leader Election
                 let current_leader = self.poh_generator.current_leader.lock().await;
                 status.leader_election = current_leader.is_some();
 
                 // Total Circulating Supply
-                status.total_circulating_supply = self.poh_generator.stakes.lock().await.values().sum();
+                let staked = self.poh_generator.stakes.lock().await.total_staked();
+                let balances: u64 = self.poh_generator.balances.lock().await.values().sum();
+                status.total_circulating_supply = staked + balances;
+                status.staked_supply = staked;
+
+                // Inflation
+                let genesis = &self.poh_generator.genesis;
+                let epoch_years = inflation::epoch_duration_years(&genesis.poh, &genesis.epoch_schedule);
+                status.inflation_rate = genesis.inflation.rate(epoch as f64 * epoch_years);
+                status.last_epoch_rewards = self.poh_generator.epoch_rewards.lock().await.total;
 
                 // Calculate average block time and throughput
-                status.average_block_time = calculate_average_block_time(&poh_guard);
-                status.throughput = calculate_throughput(&poh_guard);
+                status.average_block_time = block_metrics.average_block_time();
+                status.throughput = block_metrics.transactions_per_second();
 
                 // Block Count
-                status.block_count = poh_entries;
+                status.block_count = self.poh_generator.blockstore.lock().await.len();
 
-                // Current Epoch simplified
-                status.current_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() / 600; 
+                // Current Epoch from the genesis epoch schedule
+                status.current_epoch = epoch;
             }
             tokio::time::sleep(Duration::from_secs(5)).await;
         }
     }
 }
 
-fn calculate_average_block_time(poh_entries: &[PohEntry]) -> f64 {
-    if poh_entries.is_empty() {
-        return 0.0;
-    }
-    let mut total_time = 0;
-    let mut count = 0;
-
-    for window in poh_entries.windows(2) {
-        if let [first, second] = &window {
-            total_time += second.timestamp - first.timestamp;
-            count += 1;
-        }
-    }
-
-    if count == 0 {
-        0.0
-    } else {
-        total_time as f64 / count as f64
-    }
-}
-
-fn calculate_throughput(poh_entries: &[PohEntry]) -> usize {
-    // Simplified example: count the number of entries per second
-    poh_entries.len() 
-}
-
 pub async fn start_test_monitor(poh_generator: Arc<PoHGenerator>, gossip_activity: Arc<Mutex<GossipActivity>>) {
+    let addr = poh_generator.config.monitor_addr;
     let test_monitor = TestMonitor::new(poh_generator, gossip_activity);
     let status = test_monitor.status.clone();
+    tokio::spawn(TestMonitor::track_blocks(
+        Arc::clone(&test_monitor.block_metrics),
+        test_monitor.poh_generator.events.subscribe(),
+    ));
 
     tokio::spawn(async move {
         test_monitor.run_tests().await;
@@ -135,10 +210,82 @@ pub async fn start_test_monitor(poh_generator: Arc<PoHGenerator>, gossip_activit
             }
         });
 
+    let metrics_route = warp::path!("metrics").map(|| {
+        warp::reply::with_header(metrics().encode(), "Content-Type", "text/plain; version=0.0.4")
+    });
+
     let static_route = warp::fs::dir("./static");
 
-    println!("Starting test monitor server on http://127.0.0.1:3030...");
-    warp::serve(status_route.or(static_route))
-        .run(([127, 0, 0, 1], 3030))
+    info!("Starting test monitor server on http://{}...", addr);
+    warp::serve(status_route.or(metrics_route).or(static_route))
+        .run(addr)
         .await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(timestamp: u64, transactions: usize) -> Block {
+        let transaction = validator::transaction::Transaction {
+            sender: "alice".to_string(),
+            receiver: "bob".to_string(),
+            amount: 1,
+            recent_blockhash: String::new(),
+            signature: Vec::new(),
+        };
+        Block {
+            parent_hash: String::new(),
+            block_hash: String::new(),
+            block_height: 1,
+            timestamp,
+            transactions: vec![transaction; transactions],
+            evidence: Vec::new(),
+            stake_instructions: Vec::new(),
+            leader: String::new(),
+            signature: Vec::new(),
+        }
+    }
+
+    fn metrics_for(blocks: &[(u64, usize)]) -> BlockMetrics {
+        let mut metrics = BlockMetrics::default();
+        for &(timestamp, transactions) in blocks {
+            metrics.record_block(&block(timestamp, transactions));
+        }
+        metrics
+    }
+
+    #[test]
+    fn averages_over_the_window() {
+        let metrics = metrics_for(&[(100, 5), (102, 4), (106, 8)]);
+        assert_eq!(metrics.proposed, 3);
+        assert_eq!(metrics.average_block_time(), 3.0);
+        // The first block only marks where the window starts
+        assert_eq!(metrics.transactions_per_second(), 2.0);
+    }
+
+    #[test]
+    fn needs_two_blocks() {
+        assert_eq!(BlockMetrics::default().average_block_time(), 0.0);
+        assert_eq!(BlockMetrics::default().transactions_per_second(), 0.0);
+        let metrics = metrics_for(&[(100, 5)]);
+        assert_eq!(metrics.average_block_time(), 0.0);
+        assert_eq!(metrics.transactions_per_second(), 0.0);
+    }
+
+    #[test]
+    fn clock_stepping_back_does_not_underflow() {
+        let metrics = metrics_for(&[(100, 1), (90, 1)]);
+        assert_eq!(metrics.average_block_time(), 0.0);
+        assert_eq!(metrics.transactions_per_second(), 0.0);
+        let metrics = metrics_for(&[(u64::MAX, 1), (0, 1)]);
+        assert_eq!(metrics.average_block_time(), 0.0);
+    }
+
+    #[test]
+    fn drops_blocks_older_than_the_window() {
+        let metrics = metrics_for(&[(0, 100), (10, 1), (10 + METRICS_WINDOW_SECS, 1)]);
+        assert_eq!(metrics.recent.len(), 2);
+        assert_eq!(metrics.average_block_time(), METRICS_WINDOW_SECS as f64);
+    }
+}
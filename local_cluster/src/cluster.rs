@@ -0,0 +1,285 @@
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use validator::genesis::{EpochSchedule, GenesisAccount, GenesisConfig, GenesisValidator, PohConfig};
+use validator::keypair::Keypair;
+use validator::rpc_client::{Commitment, RpcClient};
+use validator::transaction::Transaction;
+use tracing::info;
+use crate::node::{default_binary, http_get, Node, LEADER_BINARY, VALIDATOR_BINARY};
+
+/// How often health and confirmations are re-checked.
+const POLL_INTERVAL_MS: u64 = 200;
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    pub validators: usize,
+    /// Genesis stake of each validator.
+    pub stake: u64,
+    /// Genesis balance of the mint account, which funds tests.
+    pub mint_balance: u64,
+    pub poh: PohConfig,
+    pub slots_per_epoch: u64,
+    /// Where genesis, keypairs, ledgers and logs go; a fresh directory under
+    /// the system temp dir by default.
+    pub dir: Option<PathBuf>,
+    /// Defaults to the debug build of `../leader_node`, or `$LOCAL_CLUSTER_LEADER_BIN`.
+    pub leader_bin: PathBuf,
+    /// Defaults to the debug build of `../validator`, or `$LOCAL_CLUSTER_VALIDATOR_BIN`.
+    pub validator_bin: PathBuf,
+    /// Log filter passed to every node.
+    pub log: Option<String>,
+    /// How long `start` waits for the cluster to become healthy.
+    pub startup_timeout: Duration,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            validators: 3,
+            stake: 10_000,
+            mint_balance: 1_000_000_000,
+            poh: PohConfig {
+                tick_duration_ms: 50,
+                ticks_per_slot: 8,
+            },
+            slots_per_epoch: 32,
+            dir: None,
+            leader_bin: default_binary(LEADER_BINARY),
+            validator_bin: default_binary(VALIDATOR_BINARY),
+            log: None,
+            startup_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A leader and validators running as child processes on ephemeral local
+/// ports. Dropping the cluster kills every node; the directory is kept so
+/// logs can be read after a failure.
+pub struct LocalCluster {
+    pub dir: PathBuf,
+    pub genesis: GenesisConfig,
+    /// Funded in genesis with `mint_balance`; written to `mint.json`.
+    pub mint: Keypair,
+    pub leader: Node,
+    pub validators: Vec<Node>,
+    pub rpc_addr: SocketAddr,
+    pub tpu_addr: SocketAddr,
+    /// The leader's `/status` and `/metrics` endpoint.
+    pub monitor_addr: SocketAddr,
+    pub rpc: RpcClient,
+}
+
+/// Reserves `count` unused local addresses. Every listener is held until all
+/// are picked so the same port is not handed out twice; a port can still be
+/// taken by another process before the node binds it, which is unlikely.
+fn free_addrs(count: usize) -> io::Result<Vec<SocketAddr>> {
+    let listeners = (0..count)
+        .map(|_| TcpListener::bind("127.0.0.1:0"))
+        .collect::<io::Result<Vec<_>>>()?;
+    listeners.iter().map(|listener| listener.local_addr()).collect()
+}
+
+fn default_dir() -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+    std::env::temp_dir().join(format!("local-cluster-{}-{}", std::process::id(), now))
+}
+
+fn log_args(log: &Option<String>) -> Vec<String> {
+    match log {
+        Some(filter) => vec!["--log".to_string(), filter.clone()],
+        None => Vec::new(),
+    }
+}
+
+impl LocalCluster {
+    /// Writes genesis, starts the leader and validators, and waits until the
+    /// leader answers RPC and has every validator connected and staked (see
+    /// `unhealthy_nodes`). That doesn't mean they have voted yet.
+    pub async fn start(config: ClusterConfig) -> io::Result<Self> {
+        let dir = config.dir.clone().unwrap_or_else(default_dir);
+        fs::create_dir_all(&dir)?;
+        let genesis_path = dir.join("genesis.json");
+        let genesis_arg = genesis_path.display().to_string();
+
+        let addrs = free_addrs(4 + 2 * config.validators)?;
+        let (tpu_addr, leader_gossip, rpc_addr, monitor_addr) = (addrs[0], addrs[1], addrs[2], addrs[3]);
+        let mut leader_args = vec![
+            "--genesis".to_string(),
+            genesis_arg.clone(),
+            "--tpu-addr".to_string(),
+            tpu_addr.to_string(),
+            "--gossip-addr".to_string(),
+            leader_gossip.to_string(),
+            "--rpc-addr".to_string(),
+            rpc_addr.to_string(),
+            "--monitor-addr".to_string(),
+            monitor_addr.to_string(),
+        ];
+        leader_args.extend(log_args(&config.log));
+        let leader = Node::new(
+            "leader".to_string(),
+            dir.join("leader"),
+            config.leader_bin.clone(),
+            leader_args,
+            leader_gossip,
+            monitor_addr,
+        )?;
+
+        let mut validators = Vec::new();
+        for (i, ports) in addrs[4..].chunks(2).enumerate() {
+            let (gossip_addr, metrics_addr) = (ports[0], ports[1]);
+            let mut args = vec![
+                "--genesis".to_string(),
+                genesis_arg.clone(),
+                "--gossip-addr".to_string(),
+                gossip_addr.to_string(),
+                "--entrypoint".to_string(),
+                leader_gossip.to_string(),
+                "--leader-addr".to_string(),
+                tpu_addr.to_string(),
+                "--metrics-addr".to_string(),
+                metrics_addr.to_string(),
+            ];
+            args.extend(log_args(&config.log));
+            validators.push(Node::new(
+                format!("validator-{}", i),
+                dir.join(format!("validator-{}", i)),
+                config.validator_bin.clone(),
+                args,
+                gossip_addr,
+                metrics_addr,
+            )?);
+        }
+
+        let mint = Keypair::generate();
+        mint.write_to_file(&dir.join("mint.json"))?;
+        let genesis = GenesisConfig {
+            creation_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            accounts: vec![GenesisAccount { pubkey: mint.pubkey(), balance: config.mint_balance }],
+            validators: validators
+                .iter()
+                .map(|validator| GenesisValidator { identity: validator.pubkey(), stake: config.stake, commission: 0 })
+                .collect(),
+            poh: config.poh.clone(),
+            epoch_schedule: EpochSchedule { slots_per_epoch: config.slots_per_epoch },
+            inflation: Default::default(),
+            slashing: Default::default(),
+        };
+        genesis.write(&genesis_path)?;
+
+        let mut cluster = LocalCluster {
+            dir,
+            genesis,
+            mint,
+            leader,
+            validators,
+            rpc_addr,
+            tpu_addr,
+            monitor_addr,
+            rpc: RpcClient::new(rpc_addr),
+        };
+        info!("Starting local cluster in {}", cluster.dir.display());
+        // Validators retry with backoff, so starting the leader first saves time.
+        cluster.leader.start()?;
+        cluster.wait_for_rpc(config.startup_timeout).await?;
+        for validator in &mut cluster.validators {
+            validator.start()?;
+        }
+        cluster.wait_for_healthy(config.startup_timeout).await?;
+        Ok(cluster)
+    }
+
+    async fn wait_for_rpc(&mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        while self.rpc.call("getBlockHeight", json!([])).await.is_err() {
+            if !self.leader.is_running() {
+                return Err(io::Error::other(format!("the leader exited; see {}", self.leader.log_path().display())));
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the leader's RPC did not come up"));
+            }
+            tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MS)).await;
+        }
+        Ok(())
+    }
+
+    /// Names of running nodes that are not healthy yet: the leader must
+    /// answer RPC and each validator must be connected and staked. Killed
+    /// nodes are skipped.
+    pub async fn unhealthy_nodes(&mut self) -> Vec<String> {
+        let mut unhealthy = Vec::new();
+        let current = match self.rpc.call("getVoteAccounts", json!([])).await {
+            Ok(accounts) => accounts["current"]
+                .as_array()
+                .map(|current| {
+                    current
+                        .iter()
+                        .filter_map(|account| account["nodePubkey"].as_str().map(str::to_string))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+            Err(_) => {
+                unhealthy.push(self.leader.name.clone());
+                Vec::new()
+            }
+        };
+        for validator in &mut self.validators {
+            if validator.is_running() && !current.contains(&validator.pubkey()) {
+                unhealthy.push(validator.name.clone());
+            }
+        }
+        unhealthy
+    }
+
+    pub async fn wait_for_healthy(&mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let unhealthy = self.unhealthy_nodes().await;
+            if unhealthy.is_empty() {
+                info!("Local cluster is healthy");
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} not healthy after {:?}; logs are in {}", unhealthy.join(", "), timeout, self.dir.display()),
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(POLL_INTERVAL_MS)).await;
+        }
+    }
+
+    pub async fn balance(&self, pubkey: &str) -> io::Result<u64> {
+        self.rpc.get_balance(pubkey).await
+    }
+
+    /// Signs a transfer against the latest blockhash and submits it,
+    /// returning its signature.
+    pub async fn transfer(&self, from: &Keypair, to: &str, amount: u64) -> io::Result<String> {
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        self.rpc.send_transaction(&Transaction::new(from, to.to_string(), amount, blockhash)).await
+    }
+
+    /// Waits for a signature to reach `commitment`, returning its slot.
+    pub async fn confirm(&self, signature: &str, commitment: Commitment, timeout: Duration) -> io::Result<u64> {
+        self.rpc.confirm_transaction(signature, commitment, timeout).await
+    }
+
+    /// The leader's `/status` report.
+    pub async fn status(&self) -> io::Result<Value> {
+        let body = http_get(self.monitor_addr, "/status").await?;
+        serde_json::from_str(&body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Kills every node.
+    pub async fn shutdown(mut self) -> io::Result<()> {
+        for validator in &mut self.validators {
+            validator.kill().await?;
+        }
+        self.leader.kill().await
+    }
+}
@@ -0,0 +1,5 @@
+pub mod cluster;
+pub mod node;
+
+pub use cluster::{ClusterConfig, LocalCluster};
+pub use node::{build_node_binaries, Node};
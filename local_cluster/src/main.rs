@@ -0,0 +1,99 @@
+use clap::Parser;
+use local_cluster::{ClusterConfig, LocalCluster};
+use std::path::PathBuf;
+use std::time::Duration;
+use validator::genesis::PohConfig;
+use validator::logging::{self, LogFormat};
+
+#[derive(Parser, Debug)]
+#[command(name = "local-cluster", version, about = "Runs a leader and validators on ephemeral local ports")]
+struct Args {
+    /// Number of validators, each staked in genesis
+    #[arg(long, default_value_t = 3)]
+    validators: usize,
+    /// Genesis stake of each validator
+    #[arg(long, default_value_t = 10_000)]
+    stake: u64,
+    /// Genesis balance of the mint account
+    #[arg(long, default_value_t = 1_000_000_000)]
+    mint_balance: u64,
+    #[arg(long, default_value_t = 50)]
+    tick_duration_ms: u64,
+    #[arg(long, default_value_t = 8)]
+    ticks_per_slot: u64,
+    #[arg(long, default_value_t = 32)]
+    slots_per_epoch: u64,
+    /// Directory for genesis, keypairs, ledgers and logs; a fresh temp dir by default
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    #[arg(long)]
+    leader_bin: Option<PathBuf>,
+    #[arg(long)]
+    validator_bin: Option<PathBuf>,
+    /// Log filter for the nodes, written to their log files
+    #[arg(long)]
+    node_log: Option<String>,
+    /// Log filter for the launcher itself
+    #[arg(long)]
+    log: Option<String>,
+    /// Seconds to wait for the cluster to become healthy
+    #[arg(long, default_value_t = 60)]
+    startup_timeout_secs: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = logging::init(args.log.as_deref(), LogFormat::Text) {
+        eprintln!("Failed to set up logging: {}", e);
+        std::process::exit(1);
+    }
+    let defaults = ClusterConfig::default();
+    let config = ClusterConfig {
+        validators: args.validators,
+        stake: args.stake,
+        mint_balance: args.mint_balance,
+        poh: PohConfig {
+            tick_duration_ms: args.tick_duration_ms,
+            ticks_per_slot: args.ticks_per_slot,
+        },
+        slots_per_epoch: args.slots_per_epoch,
+        dir: args.dir,
+        leader_bin: args.leader_bin.unwrap_or(defaults.leader_bin),
+        validator_bin: args.validator_bin.unwrap_or(defaults.validator_bin),
+        log: args.node_log,
+        startup_timeout: Duration::from_secs(args.startup_timeout_secs),
+    };
+    let cluster = match LocalCluster::start(config).await {
+        Ok(cluster) => cluster,
+        Err(e) => {
+            eprintln!("local-cluster: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Local cluster running in {}", cluster.dir.display());
+    println!("Genesis:  {}", cluster.dir.join("genesis.json").display());
+    println!("Mint:     {} ({})", cluster.mint.pubkey(), cluster.dir.join("mint.json").display());
+    println!("RPC:      {}", cluster.rpc_addr);
+    println!("TPU:      {}", cluster.tpu_addr);
+    println!("Monitor:  {}", cluster.monitor_addr);
+    println!("Leader:   {} gossip {}, log {}", cluster.leader.pubkey(), cluster.leader.gossip_addr, cluster.leader.log_path().display());
+    for validator in &cluster.validators {
+        println!(
+            "{}: {} gossip {}, metrics {}, log {}",
+            validator.name,
+            validator.pubkey(),
+            validator.gossip_addr,
+            validator.metrics_addr,
+            validator.log_path().display()
+        );
+    }
+    println!("Press Ctrl-C to stop");
+
+    let _ = tokio::signal::ctrl_c().await;
+    if let Err(e) = cluster.shutdown().await {
+        eprintln!("local-cluster: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use validator::keypair::Keypair;
+use tracing::info;
+
+/// A leader or validator running as a child process. Its identity, ledger
+/// and addresses stay the same across restarts, so a restarted node rejoins
+/// as itself and resumes from its ledger.
+pub struct Node {
+    pub name: String,
+    pub identity: Keypair,
+    pub dir: PathBuf,
+    pub gossip_addr: SocketAddr,
+    /// Where the node serves Prometheus metrics.
+    pub metrics_addr: SocketAddr,
+    program: PathBuf,
+    args: Vec<String>,
+    child: Option<Child>,
+}
+
+impl Node {
+    /// Sets up the node's directory and identity file; `args` come after
+    /// `--identity` and `--ledger`.
+    pub(crate) fn new(
+        name: String,
+        dir: PathBuf,
+        program: PathBuf,
+        args: Vec<String>,
+        gossip_addr: SocketAddr,
+        metrics_addr: SocketAddr,
+    ) -> io::Result<Self> {
+        if !program.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no binary at {}; build it with `cargo build` first", program.display()),
+            ));
+        }
+        fs::create_dir_all(&dir)?;
+        let identity = Keypair::generate();
+        identity.write_to_file(&dir.join("identity.json"))?;
+        let mut node_args = vec![
+            "--identity".to_string(),
+            dir.join("identity.json").display().to_string(),
+            "--ledger".to_string(),
+            dir.join("ledger").display().to_string(),
+        ];
+        node_args.extend(args);
+        Ok(Node {
+            name,
+            identity,
+            dir,
+            gossip_addr,
+            metrics_addr,
+            program,
+            args: node_args,
+            child: None,
+        })
+    }
+
+    pub fn pubkey(&self) -> String {
+        self.identity.pubkey()
+    }
+
+    /// Everything the node prints, across restarts.
+    pub fn log_path(&self) -> PathBuf {
+        self.dir.join("node.log")
+    }
+
+    pub fn ledger_dir(&self) -> PathBuf {
+        self.dir.join("ledger")
+    }
+
+    /// Spawns the process unless it is already running.
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.is_running() {
+            return Ok(());
+        }
+        let log = OpenOptions::new().create(true).append(true).open(self.log_path())?;
+        let child = Command::new(&self.program)
+            .args(&self.args)
+            .current_dir(&self.dir)
+            // Logs go to a file, so leave out terminal colours
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .kill_on_drop(true)
+            .spawn()?;
+        info!("Started {} ({}) as pid {}", self.name, self.pubkey(), child.id().unwrap_or_default());
+        self.child = Some(child);
+        Ok(())
+    }
+
+    /// Kills the process and waits for it to exit.
+    pub async fn kill(&mut self) -> io::Result<()> {
+        if let Some(mut child) = self.child.take() {
+            child.kill().await?;
+            info!("Killed {}", self.name);
+        }
+        Ok(())
+    }
+
+    pub async fn restart(&mut self) -> io::Result<()> {
+        self.kill().await?;
+        self.start()
+    }
+
+    /// False once the node was killed or has exited on its own.
+    pub fn is_running(&mut self) -> bool {
+        match &mut self.child {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    /// The node's metrics in the Prometheus text format.
+    pub async fn metrics(&self) -> io::Result<String> {
+        http_get(self.metrics_addr, "/metrics").await
+    }
+
+    /// Sum of every series of the named metric, or 0 if the node has not
+    /// recorded it yet.
+    pub async fn metric(&self, name: &str) -> io::Result<f64> {
+        Ok(sum_metric(&self.metrics().await?, name))
+    }
+}
+
+fn sum_metric(text: &str, name: &str) -> f64 {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit_once(' '))
+        .filter(|(series, _)| series.split('{').next() == Some(name))
+        .filter_map(|(_, value)| value.parse::<f64>().ok())
+        .sum()
+}
+
+/// Body of a successful plain HTTP GET.
+pub(crate) async fn http_get(addr: SocketAddr, path: &str) -> io::Result<String> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    match response.split_once("\r\n\r\n") {
+        Some((head, body)) if head.starts_with("HTTP/1.1 200") => Ok(body.to_string()),
+        _ => Err(io::Error::other(format!("GET {} on {} failed", path, addr))),
+    }
+}
+
+/// Node binaries as (override variable, crate directory, binary name).
+pub(crate) const LEADER_BINARY: (&str, &str, &str) = ("LOCAL_CLUSTER_LEADER_BIN", "leader_node", "leader_node");
+pub(crate) const VALIDATOR_BINARY: (&str, &str, &str) = ("LOCAL_CLUSTER_VALIDATOR_BIN", "validator", "validator");
+
+fn crate_path(crate_dir: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(crate_dir)
+}
+
+/// The debug build of a crate next to this one, in `$CARGO_TARGET_DIR` if
+/// set, unless overridden by the binary's variable.
+pub(crate) fn default_binary((env_var, crate_dir, name): (&str, &str, &str)) -> PathBuf {
+    if let Some(path) = std::env::var_os(env_var) {
+        return PathBuf::from(path);
+    }
+    let target_dir = match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => crate_path(crate_dir).join("target"),
+    };
+    target_dir.join("debug").join(name)
+}
+
+/// Builds the leader and validator binaries the default config runs, so a
+/// cluster never starts stale ones. Binaries overridden through their
+/// variable are left alone.
+pub fn build_node_binaries() -> io::Result<()> {
+    for (env_var, crate_dir, name) in [LEADER_BINARY, VALIDATOR_BINARY] {
+        if std::env::var_os(env_var).is_some() {
+            continue;
+        }
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let status = std::process::Command::new(cargo)
+            .args(["build", "--bin", name, "--manifest-path"])
+            .arg(crate_path(crate_dir).join("Cargo.toml"))
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("building {} failed with {}", name, status)));
+        }
+    }
+    Ok(())
+}
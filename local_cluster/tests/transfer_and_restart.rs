@@ -0,0 +1,33 @@
+use local_cluster::{build_node_binaries, ClusterConfig, LocalCluster};
+use std::time::Duration;
+use validator::keypair::Keypair;
+use validator::rpc_client::Commitment;
+
+#[tokio::test]
+async fn transfer_then_restart_validator() {
+    // Runs the node binaries, so they are rebuilt first rather than trusting whatever is on disk
+    tokio::task::spawn_blocking(build_node_binaries).await.unwrap().unwrap();
+    let mut cluster = LocalCluster::start(ClusterConfig { validators: 2, ..Default::default() })
+        .await
+        .unwrap();
+
+    let alice = Keypair::generate();
+    let signature = cluster.transfer(&cluster.mint, &alice.pubkey(), 500).await.unwrap();
+    cluster.confirm(&signature, Commitment::Confirmed, Duration::from_secs(30)).await.unwrap();
+    assert_eq!(cluster.balance(&alice.pubkey()).await.unwrap(), 500);
+
+    // A killed validator is left out of health checks until it comes back
+    // with the same identity.
+    let identity = cluster.validators[0].pubkey();
+    cluster.validators[0].kill().await.unwrap();
+    assert!(!cluster.validators[0].is_running());
+    cluster.wait_for_healthy(Duration::from_secs(30)).await.unwrap();
+    cluster.validators[0].restart().await.unwrap();
+    cluster.wait_for_healthy(Duration::from_secs(30)).await.unwrap();
+    assert_eq!(cluster.validators[0].pubkey(), identity);
+    assert!(cluster.validators[0].metrics().await.is_ok());
+
+    let dir = cluster.dir.clone();
+    cluster.shutdown().await.unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,78 @@
+use clap::Parser;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use validator::faucet::{
+    run_faucet, Faucet, FaucetLimits, DEFAULT_FAUCET_ADDR, DEFAULT_PER_ADDRESS_CAP, DEFAULT_PER_REQUEST_CAP,
+    DEFAULT_PER_WINDOW_CAP, DEFAULT_WINDOW_SECS,
+};
+use validator::keypair::Keypair;
+use validator::logging::{self, LogFormat};
+use validator::rpc_client::{RpcClient, DEFAULT_RPC_ADDR};
+use tracing::{error, info};
+
+#[derive(Parser, Debug)]
+#[command(name = "faucet", version, about = "Hands out tokens from a funded keypair")]
+struct Args {
+    /// Keypair file of an account funded in genesis
+    #[arg(short, long)]
+    keypair: PathBuf,
+    /// Where to listen for airdrop requests
+    #[arg(long, default_value = DEFAULT_FAUCET_ADDR)]
+    addr: SocketAddr,
+    /// The leader's JSON-RPC address, where airdrops are sent
+    #[arg(long, default_value = DEFAULT_RPC_ADDR)]
+    rpc_addr: SocketAddr,
+    /// Most tokens a single request may ask for
+    #[arg(long, default_value_t = DEFAULT_PER_REQUEST_CAP)]
+    per_request_cap: u64,
+    /// Most tokens one address may receive per window
+    #[arg(long, default_value_t = DEFAULT_PER_ADDRESS_CAP)]
+    per_address_cap: u64,
+    /// Most tokens handed out per window in total
+    #[arg(long, default_value_t = DEFAULT_PER_WINDOW_CAP)]
+    per_window_cap: u64,
+    /// Length of the window the caps apply to
+    #[arg(long, default_value_t = DEFAULT_WINDOW_SECS)]
+    window_secs: u64,
+    /// Log filter, e.g. `info,validator::faucet=debug`
+    #[arg(long)]
+    log: Option<String>,
+    /// `text` or `json`
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if let Err(e) = logging::init(args.log.as_deref(), args.log_format) {
+        eprintln!("Failed to set up logging: {}", e);
+        std::process::exit(1);
+    }
+    let keypair = match Keypair::read_from_file(&args.keypair) {
+        Ok(keypair) => keypair,
+        Err(e) => {
+            error!("Failed to load faucet keypair: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let listener = match TcpListener::bind(args.addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind faucet on {}: {}", args.addr, e);
+            std::process::exit(1);
+        }
+    };
+    let limits = FaucetLimits {
+        per_request: args.per_request_cap,
+        per_address: args.per_address_cap,
+        per_window: args.per_window_cap,
+        window: Duration::from_secs(args.window_secs),
+    };
+    let faucet = Arc::new(Faucet::new(keypair, RpcClient::new(args.rpc_addr), limits));
+    info!("Faucet {} listening on {}, sending through RPC at {}", faucet.pubkey(), args.addr, args.rpc_addr);
+    run_faucet(faucet, listener).await;
+}
@@ -0,0 +1,71 @@
+use clap::{Parser, Subcommand};
+use std::io;
+use std::path::{Path, PathBuf};
+use validator::keypair::Keypair;
+
+#[derive(Parser, Debug)]
+#[command(name = "keygen", version, about = "Creates and inspects keypair files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a new keypair and write it to a file
+    New {
+        /// Where to write the keypair
+        #[arg(short, long)]
+        outfile: PathBuf,
+        /// Overwrite the file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the base58 public key of a keypair file
+    Pubkey { keypair: PathBuf },
+    /// Check that a keypair file holds the given public key
+    Verify { keypair: PathBuf, pubkey: String },
+}
+
+fn new_keypair(outfile: &Path, force: bool) -> io::Result<()> {
+    if outfile.exists() {
+        if !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists, use --force to overwrite it", outfile.display()),
+            ));
+        }
+        std::fs::remove_file(outfile)?;
+    }
+    let keypair = Keypair::generate();
+    keypair.write_to_file(outfile)?;
+    println!("Wrote new keypair to {}", outfile.display());
+    println!("pubkey: {}", keypair.pubkey());
+    Ok(())
+}
+
+fn run(cli: Cli) -> io::Result<bool> {
+    match cli.command {
+        Command::New { outfile, force } => new_keypair(&outfile, force).map(|()| true),
+        Command::Pubkey { keypair } => {
+            println!("{}", Keypair::read_from_file(&keypair)?.pubkey());
+            Ok(true)
+        }
+        Command::Verify { keypair, pubkey } => {
+            let matches = Keypair::read_from_file(&keypair)?.pubkey() == pubkey;
+            println!("Verification for public key {}: {}", pubkey, if matches { "Success" } else { "Failed" });
+            Ok(matches)
+        }
+    }
+}
+
+fn main() {
+    match run(Cli::parse()) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("keygen: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use validator::block::{compute_block_hash, verify_block_hash, Block};
+use validator::blockstore::{Blockstore, DEFAULT_LEDGER_DIR};
+use validator::genesis::{GenesisConfig, DEFAULT_GENESIS_PATH};
+use validator::poh_handler::{poh_hash, validate_poh_entries, PohEntry, PohHistory};
+use validator::snapshot::SnapshotArchive;
+use validator::stake::StakeAccounts;
+use validator::transaction::execute_transaction;
+
+const USAGE: &str = "usage: ledger-tool <verify|blocks|slots|balances> [--ledger <dir>] [--genesis <file>] \
+[--start <height>] [--end <height>] [--account <pubkey>]";
+
+fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1).cloned())
+}
+
+fn parse_height(args: &[String], flag: &str) -> io::Result<Option<u64>> {
+    match arg_value(args, flag) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {}: {}", flag, value))),
+        None => Ok(None),
+    }
+}
+
+/// A ledger directory opened for offline use. If the node started from a
+/// snapshot and has no blocks before it, the chain and the bank start
+/// there instead of at genesis.
+struct Ledger {
+    dir: PathBuf,
+    genesis: GenesisConfig,
+    blockstore: Blockstore,
+    balances: HashMap<String, u64>,
+    stakes: StakeAccounts,
+}
+
+impl Ledger {
+    fn open(dir: &Path, genesis_path: &Path) -> io::Result<Self> {
+        if !dir.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("no ledger at {}", dir.display())));
+        }
+        let genesis = GenesisConfig::load(genesis_path)?;
+        let mut blockstore = Blockstore::open(dir)?;
+        let mut balances: HashMap<String, u64> = genesis
+            .accounts
+            .iter()
+            .map(|account| (account.pubkey.clone(), account.balance))
+            .collect();
+        let mut stakes = StakeAccounts::from_genesis(&genesis);
+
+        let from_genesis = blockstore.get(1).is_some();
+        if let Some(archive) = SnapshotArchive::latest(dir)?.filter(|_| !from_genesis) {
+            let snapshot = archive.unpack()?;
+            if snapshot.genesis_hash != bs58::encode(genesis.hash()).into_string() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot is from a different genesis"));
+            }
+            println!("Starting from snapshot at slot {}", snapshot.slot);
+            blockstore.set_base(snapshot.slot, snapshot.block_hash);
+            balances = snapshot.balances.into_iter().collect();
+            stakes = snapshot.stakes;
+        }
+
+        Ok(Ledger {
+            dir: dir.to_path_buf(),
+            genesis,
+            blockstore,
+            balances,
+            stakes,
+        })
+    }
+
+    fn base(&self) -> (u64, String) {
+        match self.blockstore.base() {
+            Some((height, hash)) => (height, hash.to_string()),
+            None => (0, hex::encode(self.genesis.hash())),
+        }
+    }
+
+    /// The accounts at the start of the stored chain.
+    fn bank(&self) -> Bank {
+        Bank {
+            balances: self.balances.clone(),
+            stakes: self.stakes.clone(),
+        }
+    }
+
+    /// Stored blocks with `start <= height <= end`.
+    fn blocks(&self, start: u64, end: u64) -> Vec<Block> {
+        self.blockstore.range(start, end, usize::MAX)
+    }
+}
+
+/// The first point where the stored chain disagrees with a recomputation.
+struct Divergence {
+    height: u64,
+    what: &'static str,
+    expected: String,
+    actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "divergence at height {}: {} expected {}, found {}",
+            self.height, self.what, self.expected, self.actual
+        )
+    }
+}
+
+#[derive(Default)]
+struct ReplayStats {
+    blocks: usize,
+    transactions: usize,
+    stake_instructions: usize,
+}
+
+/// The accounts a replay moves forward.
+#[derive(Clone)]
+struct Bank {
+    balances: HashMap<String, u64>,
+    stakes: StakeAccounts,
+}
+
+/// Replays blocks up to `end`, checking heights, parent links, block hashes
+/// and leader signatures, then applying each block to `bank` the way the
+/// leader built it: slashing, stake instructions, transfers. Leaders leave
+/// out instructions and transfers that fail, so one that fails here is a
+/// divergence too. Epoch rewards depend on votes, which blocks don't carry,
+/// so they are not replayed.
+fn replay_blocks(ledger: &Ledger, end: u64, bank: &mut Bank) -> Result<ReplayStats, Divergence> {
+    let (base_height, mut parent_hash) = ledger.base();
+    let mut stats = ReplayStats::default();
+
+    for (expected_height, block) in (base_height + 1..).zip(ledger.blocks(base_height + 1, end)) {
+        if block.block_height != expected_height {
+            return Err(Divergence {
+                height: expected_height,
+                what: "block height",
+                expected: expected_height.to_string(),
+                actual: block.block_height.to_string(),
+            });
+        }
+        if block.parent_hash != parent_hash {
+            return Err(Divergence {
+                height: block.block_height,
+                what: "parent hash",
+                expected: parent_hash,
+                actual: block.parent_hash,
+            });
+        }
+        if !verify_block_hash(&block) {
+            return Err(Divergence {
+                height: block.block_height,
+                what: "block hash",
+                expected: compute_block_hash(&block).unwrap_or_else(|| "a hex parent hash".to_string()),
+                actual: block.block_hash,
+            });
+        }
+        if !block.header().verify_signature() {
+            return Err(Divergence {
+                height: block.block_height,
+                what: "leader signature",
+                expected: format!("a valid signature by {}", block.leader),
+                actual: hex::encode(&block.signature),
+            });
+        }
+
+        for evidence in &block.evidence {
+            bank.stakes.slash(evidence.offense.offender(), ledger.genesis.slashing.slash_fraction);
+        }
+        let epoch = ledger.genesis.epoch_schedule.epoch_for_slot(block.block_height);
+        for instruction in &block.stake_instructions {
+            let signature = bs58::encode(instruction.signature()).into_string();
+            if !instruction.verify_signature() {
+                return Err(Divergence {
+                    height: block.block_height,
+                    what: "stake instruction signature",
+                    expected: format!("{} to be signed by its staker", signature),
+                    actual: "an invalid signature".to_string(),
+                });
+            }
+            if let Err(e) = bank.stakes.apply(&mut bank.balances, instruction, epoch) {
+                return Err(Divergence {
+                    height: block.block_height,
+                    what: "stake instruction",
+                    expected: format!("{} to apply", signature),
+                    actual: e.to_string(),
+                });
+            }
+            stats.stake_instructions += 1;
+        }
+        for transaction in &block.transactions {
+            if let Err(e) = execute_transaction(&mut bank.balances, transaction) {
+                return Err(Divergence {
+                    height: block.block_height,
+                    what: "transaction",
+                    expected: format!("{} to execute", bs58::encode(&transaction.signature).into_string()),
+                    actual: e.to_string(),
+                });
+            }
+            stats.transactions += 1;
+        }
+        stats.blocks += 1;
+        parent_hash = block.block_hash;
+    }
+    Ok(stats)
+}
+
+/// Re-verifies every stored PoH entry, returning how many there are.
+fn replay_poh(ledger: &Ledger) -> io::Result<Result<usize, Divergence>> {
+    let history = PohHistory::open_with_max_entries(&ledger.dir, ledger.genesis.hash(), usize::MAX)?;
+    let anchor = history.anchor();
+    let mut chain = vec![PohEntry {
+        timestamp: 0,
+        hash: anchor.hash.clone(),
+    }];
+    chain.extend(history.entries().iter().cloned());
+
+    Ok(match validate_poh_entries(&chain) {
+        Ok(()) => Ok(history.entries().len()),
+        Err(index) => Err(Divergence {
+            height: (anchor.height + index) as u64,
+            what: "PoH hash",
+            expected: hex::encode(poh_hash(&chain[index - 1].hash, chain[index].timestamp)),
+            actual: hex::encode(&chain[index].hash),
+        }),
+    })
+}
+
+fn verify(ledger: &Ledger) -> io::Result<bool> {
+    let stats = match replay_blocks(ledger, u64::MAX, &mut ledger.bank()) {
+        Ok(stats) => stats,
+        Err(divergence) => {
+            println!("Block replay failed: {}", divergence);
+            return Ok(false);
+        }
+    };
+    println!(
+        "Replayed {} blocks to height {} ({} transactions, {} stake instructions)",
+        stats.blocks,
+        ledger.blockstore.height(),
+        stats.transactions,
+        stats.stake_instructions
+    );
+
+    match replay_poh(ledger)? {
+        Ok(entries) => println!("Verified {} PoH entries", entries),
+        Err(divergence) => {
+            println!("PoH replay failed: {}", divergence);
+            return Ok(false);
+        }
+    }
+    println!("Ledger verified, no divergence");
+    Ok(true)
+}
+
+fn print_blocks(ledger: &Ledger, start: u64, end: u64) {
+    for block in ledger.blocks(start, end) {
+        println!("{}", serde_json::to_string_pretty(&block).unwrap());
+    }
+}
+
+fn print_slots(ledger: &Ledger, start: u64, end: u64) {
+    println!("{:>8} {:>6} {:>12} {:>4} {:<64} leader", "slot", "epoch", "timestamp", "txs", "block hash");
+    for block in ledger.blocks(start, end) {
+        println!(
+            "{:>8} {:>6} {:>12} {:>4} {:<64} {}",
+            block.block_height,
+            ledger.genesis.epoch_schedule.epoch_for_slot(block.block_height),
+            block.timestamp,
+            block.transactions.len(),
+            block.block_hash,
+            block.leader
+        );
+    }
+}
+
+/// Balances after replaying blocks up to `end`.
+fn print_balances(ledger: &Ledger, end: u64, account: Option<&str>) -> bool {
+    let mut bank = ledger.bank();
+    if let Err(divergence) = replay_blocks(ledger, end, &mut bank) {
+        println!("Block replay failed: {}", divergence);
+        return false;
+    }
+    let balances: BTreeMap<String, u64> = bank.balances.into_iter().collect();
+    match account {
+        Some(account) => println!("{} {}", account, balances.get(account).copied().unwrap_or(0)),
+        None => {
+            for (account, balance) in balances {
+                println!("{} {}", account, balance);
+            }
+        }
+    }
+    true
+}
+
+fn run(args: &[String]) -> io::Result<bool> {
+    let command = match args.get(1) {
+        Some(command) => command.as_str(),
+        None => {
+            eprintln!("{}", USAGE);
+            return Ok(false);
+        }
+    };
+    let ledger_dir = PathBuf::from(arg_value(args, "--ledger").unwrap_or_else(|| DEFAULT_LEDGER_DIR.to_string()));
+    let genesis_path = PathBuf::from(arg_value(args, "--genesis").unwrap_or_else(|| DEFAULT_GENESIS_PATH.to_string()));
+    let start = parse_height(args, "--start")?.unwrap_or(0);
+    let end = parse_height(args, "--end")?.unwrap_or(u64::MAX);
+
+    let ledger = Ledger::open(&ledger_dir, &genesis_path)?;
+    match command {
+        "verify" => verify(&ledger),
+        "blocks" => {
+            print_blocks(&ledger, start, end);
+            Ok(true)
+        }
+        "slots" => {
+            print_slots(&ledger, start, end);
+            Ok(true)
+        }
+        "balances" => Ok(print_balances(&ledger, end, arg_value(args, "--account").as_deref())),
+        _ => {
+            eprintln!("unknown command {}\n{}", command, USAGE);
+            Ok(false)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("ledger-tool: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
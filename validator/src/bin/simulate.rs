@@ -0,0 +1,78 @@
+use clap::Parser;
+use validator::logging::{self, LogFormat};
+use validator::sim::{Faults, SimConfig, Simulation};
+
+#[derive(Parser, Debug)]
+#[command(name = "simulate", version, about = "Runs a leader and validators in one thread on virtual time")]
+struct Args {
+    /// Seed of the first run; a failing run is replayed exactly from its seed
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Number of runs, with consecutive seeds
+    #[arg(long, default_value_t = 1)]
+    runs: u64,
+    #[arg(long, default_value_t = 4)]
+    validators: usize,
+    /// Blocks the leader produces per run
+    #[arg(long, default_value_t = 100)]
+    slots: u64,
+    #[arg(long, default_value_t = 400)]
+    slot_duration_ms: u64,
+    #[arg(long, default_value_t = 30_000)]
+    election_interval_ms: u64,
+    #[arg(long, default_value_t = 2)]
+    transfers_per_slot: usize,
+    #[arg(long, default_value_t = 10)]
+    min_delay_ms: u64,
+    #[arg(long, default_value_t = 100)]
+    max_delay_ms: u64,
+    /// Chance that a message is lost, from 0.0 to 1.0
+    #[arg(long, default_value_t = 0.0)]
+    drop_rate: f64,
+    /// Let messages overtake earlier ones sent to the same peer
+    #[arg(long)]
+    reorder: bool,
+    /// Log filter, e.g. `validator::sim=trace` to follow every message
+    #[arg(long, default_value = "error")]
+    log: String,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = logging::init(Some(&args.log), LogFormat::Text) {
+        eprintln!("Failed to set up logging: {}", e);
+        std::process::exit(1);
+    }
+
+    let mut failed = Vec::new();
+    for seed in args.seed..args.seed + args.runs {
+        let config = SimConfig {
+            seed,
+            validators: args.validators,
+            slots: args.slots,
+            slot_duration_ms: args.slot_duration_ms,
+            election_interval_ms: args.election_interval_ms,
+            stake: SimConfig::default().stake,
+            transfers_per_slot: args.transfers_per_slot,
+            faults: Faults {
+                min_delay_ms: args.min_delay_ms,
+                max_delay_ms: args.max_delay_ms,
+                drop_rate: args.drop_rate,
+                reorder: args.reorder,
+            },
+        };
+        let report = Simulation::new(config).run();
+        println!("{}", report);
+        for violation in &report.violations {
+            println!("  violation: {}", violation);
+        }
+        if !report.violations.is_empty() {
+            failed.push(seed);
+        }
+    }
+
+    if !failed.is_empty() {
+        println!("{} of {} runs failed, seeds {:?}; rerun one with --seed <seed> --runs 1", failed.len(), args.runs, failed);
+        std::process::exit(1);
+    }
+}
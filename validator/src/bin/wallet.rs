@@ -0,0 +1,254 @@
+use clap::{Parser, Subcommand};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use validator::block::Message;
+use validator::faucet::{request_airdrop, DEFAULT_FAUCET_ADDR};
+use validator::keypair::Keypair;
+use validator::network::write_message;
+use validator::rpc_client::{Commitment, RpcClient, RpcStakeAccount, DEFAULT_RPC_ADDR};
+use validator::stake::{Stake, StakeAccountAction, StakeAction};
+use validator::transaction::Transaction;
+
+/// How often stake accounts are re-read while waiting for an instruction
+/// sent over TCP to take effect.
+const STAKE_POLL_INTERVAL_MS: u64 = 400;
+
+#[derive(Parser, Debug)]
+#[command(name = "wallet", version, about = "Sends transfers and manages stake through the leader")]
+struct Cli {
+    /// Keypair file that signs and pays (see `keygen new`)
+    #[arg(short, long, global = true)]
+    keypair: Option<PathBuf>,
+    /// The leader's JSON-RPC address, used for queries and confirmations
+    #[arg(long, global = true, default_value = DEFAULT_RPC_ADDR)]
+    rpc_addr: SocketAddr,
+    /// Faucet to request airdrops from
+    #[arg(long, global = true, default_value = DEFAULT_FAUCET_ADDR)]
+    faucet_addr: SocketAddr,
+    /// Send transactions and stake instructions straight to the leader's
+    /// tpu address instead of over RPC
+    #[arg(long, global = true)]
+    tpu_addr: Option<SocketAddr>,
+    /// `processed` or `confirmed`
+    #[arg(long, global = true, default_value = "confirmed")]
+    commitment: Commitment,
+    /// How long to wait for a transaction or stake instruction to land
+    #[arg(long, global = true, default_value_t = 30)]
+    timeout_secs: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the keypair's public key
+    Address,
+    /// Print the balance of an account, the keypair's by default
+    Balance { pubkey: Option<String> },
+    /// Ask the faucet for tokens, for the keypair's account by default
+    Airdrop { amount: u64, pubkey: Option<String> },
+    /// Send tokens and wait for the transfer to land
+    Transfer {
+        to: String,
+        amount: u64,
+        /// Print the signature without waiting
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Delegate tokens to a validator from a new stake account
+    Delegate { validator: String, amount: u64 },
+    /// Stop a stake account counting from the next epoch
+    Deactivate { stake_account: String },
+    /// Return a deactivated stake account's tokens once it has cooled down
+    Withdraw { stake_account: String },
+    /// List the stake accounts of a staker, the keypair's by default
+    Stakes { pubkey: Option<String> },
+    /// Wait for a transaction signature to land
+    Confirm { signature: String },
+}
+
+struct Wallet {
+    keypair: Option<PathBuf>,
+    rpc: RpcClient,
+    faucet_addr: SocketAddr,
+    tpu_addr: Option<SocketAddr>,
+    commitment: Commitment,
+    timeout: Duration,
+}
+
+impl Wallet {
+    fn keypair(&self) -> io::Result<Keypair> {
+        match &self.keypair {
+            Some(path) => Keypair::read_from_file(path),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "this command needs --keypair")),
+        }
+    }
+
+    /// The account to look at: the one given, or the keypair's.
+    fn pubkey_or_own(&self, pubkey: Option<String>) -> io::Result<String> {
+        match pubkey {
+            Some(pubkey) => Ok(pubkey),
+            None => Ok(self.keypair()?.pubkey()),
+        }
+    }
+
+    async fn send_to_tpu(&self, tpu_addr: SocketAddr, message: &Message) -> io::Result<()> {
+        let mut stream = TcpStream::connect(tpu_addr)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to connect to {}: {}", tpu_addr, e)))?;
+        write_message(&mut stream, message).await?;
+        stream.shutdown().await
+    }
+
+    /// Re-reads the staker's stake accounts until `done` holds for them.
+    async fn wait_for_stakes<T>(&self, staker: &str, done: impl Fn(&[RpcStakeAccount]) -> Option<T>) -> io::Result<T> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(result) = done(&self.rpc.get_stake_accounts(staker).await?) {
+                return Ok(result);
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("the leader did not apply the instruction within {:?}; check its log", self.timeout),
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(STAKE_POLL_INTERVAL_MS)).await;
+        }
+    }
+
+    async fn confirm(&self, signature: &str) -> io::Result<()> {
+        let slot = self.rpc.confirm_transaction(signature, self.commitment, self.timeout).await?;
+        println!("{:?} in slot {}", self.commitment, slot);
+        Ok(())
+    }
+
+    async fn transfer(&self, to: String, amount: u64, no_wait: bool) -> io::Result<()> {
+        let keypair = self.keypair()?;
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        let transaction = Transaction::new(&keypair, to, amount, blockhash);
+        let signature = match self.tpu_addr {
+            Some(tpu_addr) => {
+                self.send_to_tpu(tpu_addr, &Message::Transaction(transaction.clone())).await?;
+                bs58::encode(&transaction.signature).into_string()
+            }
+            None => self.rpc.send_transaction(&transaction).await?,
+        };
+        println!("Signature: {}", signature);
+        if no_wait {
+            return Ok(());
+        }
+        self.confirm(&signature).await
+    }
+
+    async fn delegate(&self, validator_id: String, amount: u64) -> io::Result<()> {
+        let keypair = self.keypair()?;
+        let staker = keypair.pubkey();
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        let stake = Stake::new(&keypair, validator_id.clone(), amount, blockhash);
+        let stake_account = stake.stake_account();
+        match self.tpu_addr {
+            Some(tpu_addr) => self.send_to_tpu(tpu_addr, &Message::StakeTokens(stake)).await?,
+            None => drop(self.rpc.delegate_stake(&stake).await?),
+        }
+        // Stake instructions take effect in the next block either way
+        self.wait_for_stakes(&staker, |accounts| accounts.iter().find(|account| account.address == stake_account).map(drop))
+            .await?;
+        println!("Delegated {} to {} from stake account {}", amount, validator_id, stake_account);
+        Ok(())
+    }
+
+    async fn deactivate(&self, stake_account: String) -> io::Result<()> {
+        let keypair = self.keypair()?;
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        let action = StakeAccountAction::new(&keypair, stake_account.clone(), StakeAction::Deactivate, blockhash);
+        match self.tpu_addr {
+            Some(tpu_addr) => self.send_to_tpu(tpu_addr, &Message::DeactivateStake(action)).await?,
+            None => self.rpc.deactivate_stake(&action).await?,
+        }
+        self.wait_for_stakes(&keypair.pubkey(), |accounts| {
+            accounts
+                .iter()
+                .find(|account| account.address == stake_account && account.deactivation_epoch.is_some())
+                .map(drop)
+        })
+        .await?;
+        println!("Deactivated stake account {}", stake_account);
+        Ok(())
+    }
+
+    async fn withdraw(&self, stake_account: String) -> io::Result<()> {
+        let keypair = self.keypair()?;
+        let blockhash = self.rpc.get_latest_blockhash().await?;
+        let action = StakeAccountAction::new(&keypair, stake_account.clone(), StakeAction::Withdraw, blockhash);
+        let amount = match self.tpu_addr {
+            Some(tpu_addr) => {
+                self.send_to_tpu(tpu_addr, &Message::WithdrawStake(action)).await?;
+                None
+            }
+            None => Some(self.rpc.withdraw_stake(&action).await?),
+        };
+        self.wait_for_stakes(&keypair.pubkey(), |accounts| {
+            (!accounts.iter().any(|account| account.address == stake_account)).then_some(())
+        })
+        .await?;
+        match amount {
+            Some(amount) => println!("Withdrew {} from stake account {}", amount, stake_account),
+            None => println!("Withdrew stake account {}", stake_account),
+        }
+        Ok(())
+    }
+
+    async fn run(&self, command: Command) -> io::Result<()> {
+        match command {
+            Command::Address => println!("{}", self.keypair()?.pubkey()),
+            Command::Balance { pubkey } => {
+                let pubkey = self.pubkey_or_own(pubkey)?;
+                println!("{}", self.rpc.get_balance(&pubkey).await?);
+            }
+            Command::Airdrop { amount, pubkey } => {
+                let pubkey = self.pubkey_or_own(pubkey)?;
+                let signature = request_airdrop(self.faucet_addr, &pubkey, amount).await?;
+                println!("Signature: {}", signature);
+                self.confirm(&signature).await?;
+            }
+            Command::Transfer { to, amount, no_wait } => self.transfer(to, amount, no_wait).await?,
+            Command::Delegate { validator, amount } => self.delegate(validator, amount).await?,
+            Command::Deactivate { stake_account } => self.deactivate(stake_account).await?,
+            Command::Withdraw { stake_account } => self.withdraw(stake_account).await?,
+            Command::Stakes { pubkey } => {
+                let pubkey = self.pubkey_or_own(pubkey)?;
+                for account in self.rpc.get_stake_accounts(&pubkey).await? {
+                    let state = match account.deactivation_epoch {
+                        Some(epoch) => format!("deactivating at epoch {}", epoch),
+                        None => format!("active from epoch {}", account.activation_epoch),
+                    };
+                    println!("{} {} to {}, {}", account.address, account.amount, account.validator_id, state);
+                }
+            }
+            Command::Confirm { signature } => self.confirm(&signature).await?,
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let wallet = Wallet {
+        keypair: cli.keypair,
+        rpc: RpcClient::new(cli.rpc_addr),
+        faucet_addr: cli.faucet_addr,
+        tpu_addr: cli.tpu_addr,
+        commitment: cli.commitment,
+        timeout: Duration::from_secs(cli.timeout_secs),
+    };
+    if let Err(e) = wallet.run(cli.command).await {
+        eprintln!("wallet: {}", e);
+        std::process::exit(1);
+    }
+}
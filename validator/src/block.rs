@@ -1,21 +1,178 @@
 use serde::{Serialize, Deserialize};
-use crate::poh_handler::PohEntry;
+use sha2::{Sha256, Digest};
+use crate::catchup::ChainTip;
+use crate::crds::{BloomFilter, CrdsValue};
+use crate::keypair::{self, Keypair};
+use crate::gossip::{GossipFrame, PruneMessage};
+use crate::poh_handler::{PohCheckpoint, PohEntry};
+use crate::repair::{RepairResponse, SignedRepairRequest};
 use crate::registration::Validator;
+use crate::slashing::Evidence;
+use crate::snapshot::SnapshotArchive;
+use crate::stake::{Stake, StakeAccountAction, StakeInstruction};
 use crate::transaction::Transaction;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
-    pub poh_entries: Vec<PohEntry>,
-    pub block_hash: Vec<u8>,
+    pub parent_hash: String,
+    pub block_hash: String,
+    pub block_height: u64,
+    pub timestamp: u64,
     pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub evidence: Vec<Evidence>,
+    /// Applied after the evidence and before the transactions.
+    #[serde(default)]
+    pub stake_instructions: Vec<StakeInstruction>,
+    #[serde(default)]
+    pub leader: String,
+    #[serde(default)]
+    pub signature: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The signed part of a block, enough to prove which hash a leader produced for a slot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub leader: String,
+    pub block_height: u64,
+    pub parent_hash: String,
+    pub block_hash: String,
+    pub signature: Vec<u8>,
+}
+
+impl BlockHeader {
+    pub fn verify_signature(&self) -> bool {
+        keypair::verify(&self.leader, &block_signing_message(self.block_height, &self.block_hash), &self.signature)
+    }
+}
+
+fn block_signing_message(block_height: u64, block_hash: &str) -> Vec<u8> {
+    let mut message = b"block".to_vec();
+    message.extend_from_slice(&block_height.to_be_bytes());
+    message.extend_from_slice(block_hash.as_bytes());
+    message
+}
+
+impl Block {
+    pub fn sign(&mut self, leader: &Keypair) {
+        self.leader = leader.pubkey();
+        self.signature = leader.sign(&block_signing_message(self.block_height, &self.block_hash));
+    }
+
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            leader: self.leader.clone(),
+            block_height: self.block_height,
+            parent_hash: self.parent_hash.clone(),
+            block_hash: self.block_hash.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Vote {
+    pub voter: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub signature: Vec<u8>,
+}
+
+fn vote_signing_message(block_height: u64, block_hash: &str) -> Vec<u8> {
+    let mut message = b"vote".to_vec();
+    message.extend_from_slice(&block_height.to_be_bytes());
+    message.extend_from_slice(block_hash.as_bytes());
+    message
+}
+
+impl Vote {
+    pub fn new(block: &Block, voter: &Keypair) -> Self {
+        Vote {
+            voter: voter.pubkey(),
+            block_height: block.block_height,
+            block_hash: block.block_hash.clone(),
+            signature: voter.sign(&vote_signing_message(block.block_height, &block.block_hash)),
+        }
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        keypair::verify(&self.voter, &vote_signing_message(self.block_height, &self.block_hash), &self.signature)
+    }
+}
+
+pub fn generate_block_hash(
+    parent_hash: &[u8],
+    block_height: u64,
+    timestamp: u64,
+    transactions: &[Transaction],
+    evidence: &[Evidence],
+    stake_instructions: &[StakeInstruction],
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(parent_hash);
+    hasher.update(block_height.to_be_bytes());
+    hasher.update(timestamp.to_be_bytes());
+    for tx in transactions {
+        hasher.update(serde_json::to_vec(tx).unwrap());
+    }
+    for item in evidence {
+        hasher.update(serde_json::to_vec(item).unwrap());
+    }
+    for instruction in stake_instructions {
+        hasher.update(serde_json::to_vec(instruction).unwrap());
+    }
+    let result = hasher.finalize();
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&result);
+    hash
+}
+
+/// The hex hash a block's contents give, or None if its parent hash isn't
+/// hex. Leaders hash with the parent's height, so that is what is used here.
+pub fn compute_block_hash(block: &Block) -> Option<String> {
+    let parent_hash = hex::decode(&block.parent_hash).ok()?;
+    let hash = generate_block_hash(
+        &parent_hash,
+        block.block_height.saturating_sub(1),
+        block.timestamp,
+        &block.transactions,
+        &block.evidence,
+        &block.stake_instructions,
+    );
+    Some(hex::encode(hash))
+}
+
+/// Recomputes the block hash from its contents.
+pub fn verify_block_hash(block: &Block) -> bool {
+    compute_block_hash(block).is_some_and(|hash| hash == block.block_hash)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum Message {
-    PoHEntries(Vec<PohEntry>),
-    RetransmissionRequest(usize),
+    /// Index to stream from and the hash of the entry just before it.
+    PohSubscribe(usize, Vec<u8>),
+    /// Index of the first entry, then the entries.
+    PohBatch(usize, Vec<PohEntry>),
+    /// Sent by the leader when a subscriber must restart from this checkpoint.
+    PohCheckpoint(PohCheckpoint),
     BlockProposal(Block),
-    ConsensusVote(Block),
+    ConsensusVote(Vote),
     RegisterValidator(Validator),
     Transaction(Transaction),
+    StakeTokens(Stake),
+    DeactivateStake(StakeAccountAction),
+    WithdrawStake(StakeAccountAction),
+    Evidence(Evidence),
+    Gossip(GossipFrame),
+    GossipPrune(PruneMessage),
+    CrdsPush(Vec<CrdsValue>),
+    CrdsPullRequest(BloomFilter, CrdsValue),
+    CrdsPullResponse(Vec<CrdsValue>),
+    ChainTip(ChainTip),
+    Repair(SignedRepairRequest),
+    RepairResponse(RepairResponse),
+    SnapshotRequest,
+    /// The leader's latest snapshot, or None if it hasn't taken one yet.
+    Snapshot(Option<SnapshotArchive>),
 }
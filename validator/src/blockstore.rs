@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use crate::block::Block;
+use crate::transaction::Transaction;
+
+pub const DEFAULT_LEDGER_DIR: &str = "ledger";
+const BLOCKS_FILE: &str = "blocks.jsonl";
+
+/// Blocks by height. With a ledger directory every inserted block is
+/// appended to `blocks.jsonl` there, so a restarted node picks up where it
+/// stopped. A node that started from a snapshot holds no blocks at or
+/// below the snapshot's slot, only its `base` height and hash.
+#[derive(Debug, Default)]
+pub struct Blockstore {
+    path: Option<PathBuf>,
+    blocks: BTreeMap<u64, Block>,
+    base: Option<(u64, String)>,
+    /// Height of the oldest block holding each transaction signature.
+    signatures: HashMap<Vec<u8>, u64>,
+}
+
+impl Blockstore {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn open(ledger_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(ledger_dir)?;
+        let path = ledger_dir.join(BLOCKS_FILE);
+        let mut blockstore = Blockstore { path: Some(path.clone()), ..Blockstore::default() };
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let block: Block = serde_json::from_str(&line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                blockstore.index(&block);
+                blockstore.blocks.insert(block.block_height, block);
+            }
+        }
+        Ok(blockstore)
+    }
+
+    fn index(&mut self, block: &Block) {
+        for transaction in &block.transactions {
+            self.signatures.entry(transaction.signature.clone()).or_insert(block.block_height);
+        }
+    }
+
+    /// Starts the chain after a snapshot at `height` with block hash `hash`.
+    pub fn set_base(&mut self, height: u64, hash: String) {
+        self.blocks.retain(|block_height, _| *block_height > height);
+        self.signatures.retain(|_, block_height| *block_height > height);
+        self.base = Some((height, hash));
+    }
+
+    pub fn base(&self) -> Option<(u64, &str)> {
+        self.base.as_ref().map(|(height, hash)| (*height, hash.as_str()))
+    }
+
+    /// Stores the block unless one is already held at its height.
+    pub fn insert(&mut self, block: Block) -> io::Result<bool> {
+        if self.blocks.contains_key(&block.block_height) || self.base.as_ref().is_some_and(|(height, _)| block.block_height <= *height) {
+            return Ok(false);
+        }
+        if let Some(path) = &self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", serde_json::to_string(&block).unwrap())?;
+        }
+        self.index(&block);
+        self.blocks.insert(block.block_height, block);
+        Ok(true)
+    }
+
+    pub fn get(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(&height)
+    }
+
+    /// Blocks with `start <= height <= end`, at most `limit` of them.
+    pub fn range(&self, start: u64, end: u64, limit: usize) -> Vec<Block> {
+        if start > end {
+            return Vec::new();
+        }
+        self.blocks.range(start..=end).take(limit).map(|(_, block)| block.clone()).collect()
+    }
+
+    /// Height of the oldest block holding a transaction with this signature.
+    pub fn transaction_slot(&self, signature: &[u8]) -> Option<u64> {
+        self.signatures.get(signature).copied()
+    }
+
+    /// The oldest block holding a transaction with this signature.
+    pub fn find_transaction(&self, signature: &[u8]) -> Option<(&Block, &Transaction)> {
+        let block = self.blocks.get(&self.transaction_slot(signature)?)?;
+        let transaction = block.transactions.iter().find(|transaction| transaction.signature == signature)?;
+        Some((block, transaction))
+    }
+
+    pub fn latest(&self) -> Option<&Block> {
+        self.blocks.values().next_back()
+    }
+
+    /// Height of the newest block, the base height or 0 before the first one.
+    pub fn height(&self) -> u64 {
+        match self.latest() {
+            Some(block) => block.block_height,
+            None => self.base.as_ref().map_or(0, |(height, _)| *height),
+        }
+    }
+
+    /// Hash of the newest block or the base, None at genesis.
+    pub fn tip_hash(&self) -> Option<String> {
+        match self.latest() {
+            Some(block) => Some(block.block_hash.clone()),
+            None => self.base.as_ref().map(|(_, hash)| hash.clone()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
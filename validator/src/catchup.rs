@@ -0,0 +1,139 @@
+use serde::{Serialize, Deserialize};
+use crate::block::{verify_block_hash, Block};
+use crate::blockstore::Blockstore;
+use crate::poh_handler::{validate_poh_entries, PohEntry, PohHistory};
+use crate::repair::RepairRequest;
+
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 64;
+pub const MAX_POH_ENTRIES_PER_REQUEST: usize = 1_000;
+/// How often a node behind the leader retries unanswered repairs.
+pub const REPAIR_INTERVAL_MS: u64 = 2_000;
+
+/// What the leader has, sent to a validator right after it registers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainTip {
+    /// The leader's identity; blocks it signs are the only ones we accept
+    /// from peers.
+    pub leader: String,
+    pub block_height: u64,
+    pub block_hash: String,
+    pub poh_entries: usize,
+    /// Hash of the entry ending at `poh_entries`, which PoH repaired from
+    /// peers has to lead to.
+    #[serde(default)]
+    pub poh_hash: Vec<u8>,
+}
+
+/// Tracks how far behind the leader this node is and what to ask for next.
+#[derive(Debug)]
+pub struct CatchUp {
+    genesis_hash: [u8; 32],
+    pub target_height: u64,
+    target_poh_entries: usize,
+    target_poh_hash: Vec<u8>,
+    /// Repaired PoH entries following our history, held until they reach
+    /// the target.
+    pending_poh: Vec<PohEntry>,
+}
+
+impl CatchUp {
+    pub fn new(genesis_hash: [u8; 32]) -> Self {
+        CatchUp {
+            genesis_hash,
+            target_height: 0,
+            target_poh_entries: 0,
+            target_poh_hash: Vec::new(),
+            pending_poh: Vec::new(),
+        }
+    }
+
+    pub fn is_caught_up(&self, blockstore: &Blockstore) -> bool {
+        blockstore.height() >= self.target_height
+    }
+
+    /// The next block range to repair, if any are still missing.
+    pub fn next_block_repair(&self, blockstore: &Blockstore) -> Option<RepairRequest> {
+        let start = blockstore.height() + 1;
+        if start > self.target_height {
+            return None;
+        }
+        Some(RepairRequest::Blocks {
+            start,
+            end: self.target_height.min(start + MAX_BLOCKS_PER_REQUEST - 1),
+        })
+    }
+
+    pub fn target_poh_entries(&self) -> usize {
+        self.target_poh_entries
+    }
+
+    /// Where the leader's PoH history was when it told us: `entries` long,
+    /// ending in `hash`.
+    pub fn set_poh_target(&mut self, entries: usize, hash: Vec<u8>) {
+        self.target_poh_entries = entries;
+        self.target_poh_hash = hash;
+        self.pending_poh.clear();
+    }
+
+    pub fn next_poh_repair(&self, poh: &PohHistory) -> Option<RepairRequest> {
+        let start = poh.len() + self.pending_poh.len();
+        if start >= self.target_poh_entries {
+            return None;
+        }
+        Some(RepairRequest::Poh {
+            start,
+            end: self.target_poh_entries.min(start + MAX_POH_ENTRIES_PER_REQUEST),
+        })
+    }
+
+    /// Takes PoH entries repaired from `start`. Anyone can compute entries
+    /// that chain from ours, so they are held back until they chain all the
+    /// way to the leader's target and only then returned for appending.
+    /// Returns None for a range that doesn't continue what we hold, or a
+    /// chain that misses the target hash, and an empty list while entries
+    /// are still held.
+    pub fn take_poh_repair(&mut self, poh: &PohHistory, start: usize, entries: Vec<PohEntry>) -> Option<Vec<PohEntry>> {
+        let held = poh.len() + self.pending_poh.len();
+        if start != held || held >= self.target_poh_entries {
+            return None;
+        }
+        let previous = self.pending_poh.last().map_or_else(|| poh.last_hash(), |entry| entry.hash.clone());
+        let mut chained = vec![PohEntry { timestamp: 0, hash: previous }];
+        chained.extend(entries.into_iter().take(self.target_poh_entries - held));
+        validate_poh_entries(&chained).ok()?;
+        self.pending_poh.extend(chained.into_iter().skip(1));
+        if poh.len() + self.pending_poh.len() < self.target_poh_entries {
+            return Some(Vec::new());
+        }
+        let pending = std::mem::take(&mut self.pending_poh);
+        (pending.last().map(|entry| &entry.hash) == Some(&self.target_poh_hash)).then_some(pending)
+    }
+
+    /// True if `block` is signed by `leader`, hashes to what it claims and
+    /// builds directly on our newest block, which is how repaired blocks from
+    /// untrusted peers are checked.
+    pub fn extends_tip(&self, blockstore: &Blockstore, block: &Block, leader: &str) -> bool {
+        let tip_hash = blockstore.tip_hash().unwrap_or_else(|| hex::encode(self.genesis_hash));
+        block.leader == leader
+            && block.block_height == blockstore.height() + 1
+            && block.parent_hash == tip_hash
+            && verify_block_hash(block)
+            && block.header().verify_signature()
+    }
+
+    /// Stores the blocks from `leader` that extend our chain in order,
+    /// returning how many did.
+    pub fn apply_blocks(&self, blockstore: &mut Blockstore, leader: &str, mut blocks: Vec<Block>) -> std::io::Result<usize> {
+        blocks.sort_by_key(|block| block.block_height);
+        let mut applied = 0;
+        for block in blocks {
+            if !self.extends_tip(blockstore, &block, leader) {
+                continue;
+            }
+            if blockstore.insert(block)? {
+                applied += 1;
+            }
+        }
+        Ok(applied)
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where node logic reads the time, so the simulator can run it on virtual
+/// time instead of the system clock.
+pub trait Clock {
+    /// Milliseconds since the Unix epoch.
+    fn now_ms(&self) -> u64;
+
+    fn now_secs(&self) -> u64 {
+        self.now_ms() / 1000
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+    }
+}
+
+/// Sleeps until `clock` reads `deadline_ms`, so a loop that advances its
+/// deadline by a fixed period doesn't drift by the time each round takes.
+pub async fn sleep_until(clock: &impl Clock, deadline_ms: u64) {
+    tokio::time::sleep(std::time::Duration::from_millis(deadline_ms.saturating_sub(clock.now_ms()))).await;
+}
@@ -0,0 +1,162 @@
+use clap::Parser;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use crate::genesis::DEFAULT_GENESIS_PATH;
+use crate::gossip::DEFAULT_GOSSIP_FANOUT;
+use crate::logging::LogFormat;
+
+pub const DEFAULT_GOSSIP_ADDR: &str = "127.0.0.1:8081";
+pub const DEFAULT_ENTRYPOINT: &str = "127.0.0.1:8000";
+pub const DEFAULT_LEADER_ADDR: &str = "127.0.0.1:8080";
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Reads a TOML config file. Unknown keys are rejected so typos don't go
+/// unnoticed.
+pub fn load_toml<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to read config {}: {}", path.display(), e)))?;
+    toml::from_str(&text).map_err(|e| invalid(format!("invalid config {}: {}", path.display(), e)))
+}
+
+pub fn to_toml<T: Serialize>(config: &T) -> String {
+    toml::to_string_pretty(config).expect("config serializes to TOML")
+}
+
+/// Fails if two of the named addresses are the same, since only one
+/// listener could bind it.
+pub fn check_distinct_addrs(addrs: &[(&str, Option<SocketAddr>)]) -> io::Result<()> {
+    for (index, (name, addr)) in addrs.iter().enumerate() {
+        let addr = match addr {
+            Some(addr) => addr,
+            None => continue,
+        };
+        if let Some((other, _)) = addrs[index + 1..].iter().find(|(_, other)| other.as_ref() == Some(addr)) {
+            return Err(invalid(format!("{} and {} are both {}", name, other, addr)));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidatorConfig {
+    pub genesis: PathBuf,
+    /// Keypair file holding the node identity.
+    pub identity: Option<PathBuf>,
+    /// Where blocks, PoH and snapshots are kept; in memory only if unset.
+    pub ledger: Option<PathBuf>,
+    /// Our gossip listener, which is also the tpu/tvu address peers use.
+    pub gossip_addr: SocketAddr,
+    /// Gossip node to discover the cluster through, normally the leader.
+    pub entrypoint: SocketAddr,
+    /// The leader's tpu address, where we register and send votes.
+    pub leader_addr: SocketAddr,
+    pub gossip_fanout: usize,
+    /// Serves Prometheus metrics when set.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Log filter in `RUST_LOG` syntax; falls back to `RUST_LOG`, then info.
+    pub log: Option<String>,
+    pub log_format: LogFormat,
+}
+
+impl Default for ValidatorConfig {
+    fn default() -> Self {
+        ValidatorConfig {
+            genesis: PathBuf::from(DEFAULT_GENESIS_PATH),
+            identity: None,
+            ledger: None,
+            gossip_addr: DEFAULT_GOSSIP_ADDR.parse().unwrap(),
+            entrypoint: DEFAULT_ENTRYPOINT.parse().unwrap(),
+            leader_addr: DEFAULT_LEADER_ADDR.parse().unwrap(),
+            gossip_fanout: DEFAULT_GOSSIP_FANOUT,
+            metrics_addr: None,
+            log: None,
+            log_format: LogFormat::Text,
+        }
+    }
+}
+
+impl ValidatorConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        if self.gossip_fanout == 0 {
+            return Err(invalid("gossip_fanout must be at least 1".to_string()));
+        }
+        check_distinct_addrs(&[("gossip_addr", Some(self.gossip_addr)), ("metrics_addr", self.metrics_addr)])
+    }
+}
+
+/// Command line for the validator. Flags override the config file, which
+/// overrides the defaults.
+#[derive(Parser, Debug)]
+#[command(name = "validator", version, about = "Runs a validator that follows and votes on the leader's blocks")]
+pub struct ValidatorArgs {
+    /// TOML file with any of the settings below
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Print the resolved configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+    /// Genesis file the cluster was created from
+    #[arg(long)]
+    pub genesis: Option<PathBuf>,
+    /// Keypair file holding the node identity (see `keygen new`)
+    #[arg(long)]
+    pub identity: Option<PathBuf>,
+    /// Ledger directory; in memory only if unset
+    #[arg(long)]
+    pub ledger: Option<PathBuf>,
+    /// Gossip listener, also used as our tpu/tvu address
+    #[arg(long)]
+    pub gossip_addr: Option<SocketAddr>,
+    /// Gossip node to discover the cluster through
+    #[arg(long)]
+    pub entrypoint: Option<SocketAddr>,
+    /// The leader's tpu address
+    #[arg(long)]
+    pub leader_addr: Option<SocketAddr>,
+    /// Peers each gossip message is pushed to
+    #[arg(long)]
+    pub gossip_fanout: Option<usize>,
+    /// Serve Prometheus metrics on this address
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Log filter, e.g. `info,validator::gossip=debug`
+    #[arg(long)]
+    pub log: Option<String>,
+    /// `text` or `json`
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+}
+
+impl ValidatorArgs {
+    pub fn resolve(&self) -> io::Result<ValidatorConfig> {
+        let mut config = match &self.config {
+            Some(path) => load_toml(path)?,
+            None => ValidatorConfig::default(),
+        };
+        if let Some(genesis) = &self.genesis {
+            config.genesis = genesis.clone();
+        }
+        if let Some(identity) = &self.identity {
+            config.identity = Some(identity.clone());
+        }
+        if let Some(ledger) = &self.ledger {
+            config.ledger = Some(ledger.clone());
+        }
+        config.gossip_addr = self.gossip_addr.unwrap_or(config.gossip_addr);
+        config.entrypoint = self.entrypoint.unwrap_or(config.entrypoint);
+        config.leader_addr = self.leader_addr.unwrap_or(config.leader_addr);
+        config.gossip_fanout = self.gossip_fanout.unwrap_or(config.gossip_fanout);
+        config.metrics_addr = self.metrics_addr.or(config.metrics_addr);
+        config.log = self.log.clone().or(config.log);
+        config.log_format = self.log_format.unwrap_or(config.log_format);
+        config.validate()?;
+        Ok(config)
+    }
+}
@@ -0,0 +1,156 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Duration;
+use crate::block::Message;
+use crate::crds::wallclock;
+use crate::metrics::metrics;
+use tracing::{info, warn};
+use crate::network::write_message;
+
+const OUTBOUND_QUEUE_SIZE: usize = 1024;
+const INITIAL_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 10_000;
+/// Connect attempts spent on one message before it is dropped.
+const MAX_SEND_ATTEMPTS: u32 = 5;
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct PeerHealth {
+    pub addr: String,
+    pub connected: bool,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    pub send_failures: u64,
+    pub reconnects: u64,
+    pub last_error: Option<String>,
+    /// Wallclock in ms of the last successful write.
+    pub last_sent: Option<u64>,
+}
+
+struct PeerConnection {
+    addr: String,
+    outbound: mpsc::Sender<Message>,
+    health: Arc<Mutex<PeerHealth>>,
+}
+
+/// One long-lived outbound connection per peer identity. Every message type
+/// shares it; the receiver tells them apart by the `Message` variant. A
+/// writer task per peer reconnects with exponential backoff when it drops.
+#[derive(Default)]
+pub struct ConnectionManager {
+    peers: Mutex<HashMap<String, PeerConnection>>,
+}
+
+impl ConnectionManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `message` for the peer, opening a connection to `addr` the
+    /// first time or when the peer has moved. Returns false if the peer's
+    /// queue is full and the message was dropped.
+    pub async fn send(&self, identity: &str, addr: &str, message: Message) -> bool {
+        let mut peers = self.peers.lock().await;
+        if peers.get(identity).is_none_or(|peer| peer.addr != addr || peer.outbound.is_closed()) {
+            let (outbound, receiver) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+            let health = Arc::new(Mutex::new(PeerHealth {
+                addr: addr.to_string(),
+                ..PeerHealth::default()
+            }));
+            tokio::spawn(run_peer(addr.to_string(), receiver, Arc::clone(&health)));
+            peers.insert(identity.to_string(), PeerConnection {
+                addr: addr.to_string(),
+                outbound,
+                health,
+            });
+        }
+
+        let peer = &peers[identity];
+        if peer.outbound.try_send(message).is_ok() {
+            return true;
+        }
+        peer.health.lock().await.messages_dropped += 1;
+        false
+    }
+
+    /// Closes the connection; its writer task exits once the queue drains.
+    pub async fn remove(&self, identity: &str) {
+        self.peers.lock().await.remove(identity);
+    }
+
+    pub async fn health(&self) -> HashMap<String, PeerHealth> {
+        let peers = self.peers.lock().await;
+        let mut health = HashMap::new();
+        for (identity, peer) in peers.iter() {
+            health.insert(identity.clone(), peer.health.lock().await.clone());
+        }
+        health
+    }
+}
+
+async fn run_peer(addr: String, mut outbound: mpsc::Receiver<Message>, health: Arc<Mutex<PeerHealth>>) {
+    let mut stream: Option<TcpStream> = None;
+    let mut backoff_ms = INITIAL_BACKOFF_MS;
+    let mut ever_connected = false;
+
+    while let Some(message) = outbound.recv().await {
+        let mut attempts = 0;
+        loop {
+            if stream.is_none() {
+                attempts += 1;
+                match TcpStream::connect(&addr).await {
+                    Ok(connection) => {
+                        let mut health = health.lock().await;
+                        health.connected = true;
+                        if ever_connected {
+                            health.reconnects += 1;
+                            info!("Reconnected to peer {}", addr);
+                        }
+                        ever_connected = true;
+                        backoff_ms = INITIAL_BACKOFF_MS;
+                        stream = Some(connection);
+                        metrics().connections.with_label_values(&["outbound"]).inc();
+                    }
+                    Err(e) => {
+                        {
+                            let mut health = health.lock().await;
+                            health.connected = false;
+                            health.send_failures += 1;
+                            health.last_error = Some(e.to_string());
+                        }
+                        if attempts >= MAX_SEND_ATTEMPTS {
+                            health.lock().await.messages_dropped += 1;
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                        backoff_ms = (backoff_ms * 2).min(MAX_BACKOFF_MS);
+                        continue;
+                    }
+                }
+            }
+
+            match write_message(stream.as_mut().unwrap(), &message).await {
+                Ok(()) => {
+                    let mut health = health.lock().await;
+                    health.messages_sent += 1;
+                    health.last_sent = Some(wallclock());
+                    break;
+                }
+                Err(e) => {
+                    warn!("Lost connection to peer {}: {}", addr, e);
+                    stream = None;
+                    metrics().connections.with_label_values(&["outbound"]).dec();
+                    let mut health = health.lock().await;
+                    health.connected = false;
+                    health.send_failures += 1;
+                    health.last_error = Some(e.to_string());
+                }
+            }
+        }
+    }
+    if stream.is_some() {
+        metrics().connections.with_label_values(&["outbound"]).dec();
+    }
+}
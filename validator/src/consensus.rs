@@ -0,0 +1,190 @@
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::block::{generate_block_hash, Block, Vote};
+use crate::blockstore::Blockstore;
+use crate::catchup::CatchUp;
+use crate::clock::Clock;
+use crate::keypair::Keypair;
+use crate::repair::RepairRequest;
+use crate::slashing::{ConflictDetector, Evidence, Offense};
+use crate::genesis::EpochSchedule;
+use crate::stake::{StakeAccounts, StakeInstruction};
+use crate::transaction::{execute_transaction, Transaction};
+use tracing::{error, warn};
+
+/// Slots further than this behind the newest vote are forgotten.
+pub const MAX_TRACKED_SLOTS: u64 = 512;
+
+/// Stake-weighted votes per slot. A slot is confirmed once validators
+/// holding more than two thirds of the active stake have voted for it.
+#[derive(Debug, Default)]
+pub struct ConfirmationTracker {
+    /// Kept after a slot confirms, so a vote repeated then is recognised.
+    votes: BTreeMap<u64, HashSet<String>>,
+    confirmed: BTreeSet<u64>,
+    newest: u64,
+    pub confirmed_count: u64,
+}
+
+impl ConfirmationTracker {
+    /// Records a vote, returning whether it just confirmed the slot, or None
+    /// if it isn't for a block in `blockstore` or is for a slot already
+    /// forgotten. Votes for slots past our tip or for a hash we didn't store
+    /// would otherwise count towards nothing and, being the newest slot seen,
+    /// prune the slots that matter.
+    pub fn record_vote(&mut self, vote: &Vote, blockstore: &Blockstore, active_stakes: &HashMap<String, u64>) -> Option<bool> {
+        let slot = vote.block_height;
+        if slot < self.newest.saturating_sub(MAX_TRACKED_SLOTS) || blockstore.get(slot)?.block_hash != vote.block_hash {
+            return None;
+        }
+        let voters = self.votes.entry(slot).or_default();
+        voters.insert(vote.voter.clone());
+        if self.confirmed.contains(&slot) {
+            return Some(false);
+        }
+
+        let total_stake: u64 = active_stakes.values().sum();
+        let voted_stake: u64 = voters.iter().filter_map(|voter| active_stakes.get(voter)).sum();
+        let confirmed = total_stake > 0 && voted_stake * 3 > total_stake * 2;
+        if confirmed {
+            self.confirmed.insert(slot);
+            self.confirmed_count += 1;
+        }
+        self.newest = self.newest.max(slot);
+        self.prune();
+        Some(confirmed)
+    }
+
+    /// Whether a vote from `voter` for `slot` is already recorded.
+    pub fn has_voted(&self, slot: u64, voter: &str) -> bool {
+        self.votes.get(&slot).is_some_and(|voters| voters.contains(voter))
+    }
+
+    pub fn is_confirmed(&self, slot: u64) -> bool {
+        self.confirmed.contains(&slot)
+    }
+
+    /// The newest confirmed slot still tracked.
+    pub fn latest_confirmed(&self) -> Option<u64> {
+        self.confirmed.last().copied()
+    }
+
+    fn prune(&mut self) {
+        let oldest = self.newest.saturating_sub(MAX_TRACKED_SLOTS);
+        self.votes = self.votes.split_off(&oldest);
+        self.confirmed = self.confirmed.split_off(&oldest);
+    }
+}
+
+/// Picks a leader with probability proportional to active stake. Candidates
+/// are ordered by identity so the same rng state always picks the same one.
+pub fn select_leader(active_stakes: &HashMap<String, u64>, rng: &mut impl Rng) -> Option<String> {
+    let candidates: BTreeMap<&String, u64> = active_stakes.iter().map(|(identity, &stake)| (identity, stake)).collect();
+    let total_stake: u64 = candidates.values().sum();
+    if total_stake == 0 {
+        return None;
+    }
+    let mut choice = rng.gen_range(0..total_stake);
+    for (identity, stake) in candidates {
+        if choice < stake {
+            return Some(identity.clone());
+        }
+        choice -= stake;
+    }
+    None
+}
+
+/// Picks the leader for the slot after `height` by the stake active in its
+/// epoch. Given the same `rng` every node running the election agrees.
+pub fn elect_leader(
+    stakes: &StakeAccounts,
+    epoch_schedule: &EpochSchedule,
+    height: u64,
+    rng: &mut impl Rng,
+) -> Option<String> {
+    select_leader(&stakes.active_stakes(epoch_schedule.epoch_for_slot(height)), rng)
+}
+
+/// Builds and signs the block that follows `parent_height`. Transfers run in
+/// arrival order against `balances`; those that fail are left out. Evidence
+/// and stake instructions are the caller's to apply first.
+#[allow(clippy::too_many_arguments)]
+pub fn build_block(
+    clock: &impl Clock,
+    leader: &Keypair,
+    parent_hash: [u8; 32],
+    parent_height: u64,
+    pending: Vec<Transaction>,
+    balances: &mut HashMap<String, u64>,
+    evidence: Vec<Evidence>,
+    stake_instructions: Vec<StakeInstruction>,
+) -> Block {
+    let mut transactions = Vec::new();
+    for transaction in pending {
+        match execute_transaction(balances, &transaction) {
+            Ok(()) => transactions.push(transaction),
+            Err(e) => warn!("Dropping transaction {}: {}", bs58::encode(&transaction.signature).into_string(), e),
+        }
+    }
+    let timestamp = clock.now_secs();
+    let block_hash = generate_block_hash(&parent_hash, parent_height, timestamp, &transactions, &evidence, &stake_instructions);
+    let mut block = Block {
+        parent_hash: hex::encode(parent_hash),
+        block_hash: hex::encode(block_hash),
+        block_height: parent_height + 1,
+        timestamp,
+        transactions,
+        evidence,
+        stake_instructions,
+        leader: String::new(),
+        signature: Vec::new(),
+    };
+    block.sign(leader);
+    block
+}
+
+/// What a validator made of a proposed block.
+#[derive(Debug)]
+pub enum Replayed {
+    InvalidSignature,
+    /// The leader already signed a different block for this slot.
+    Equivocation(Offense),
+    /// At or below our tip.
+    Known,
+    /// Doesn't build on our tip. `repair` asks for the blocks in between if
+    /// we were caught up until now; otherwise a repair is already under way.
+    Gap { repair: Option<RepairRequest> },
+    /// Extends our tip and was stored, so it gets our vote.
+    Stored,
+}
+
+pub fn replay_block(
+    block: &Block,
+    blockstore: &mut Blockstore,
+    catch_up: &mut CatchUp,
+    conflict_detector: &mut ConflictDetector,
+) -> Replayed {
+    let header = block.header();
+    if !header.verify_signature() {
+        return Replayed::InvalidSignature;
+    }
+    if let Some(offense) = conflict_detector.observe_block(header) {
+        return Replayed::Equivocation(offense);
+    }
+    if block.block_height <= blockstore.height() {
+        return Replayed::Known;
+    }
+
+    // Only vote on blocks that extend our ledger; a gap means we missed some
+    let was_caught_up = catch_up.is_caught_up(blockstore);
+    catch_up.target_height = catch_up.target_height.max(block.block_height);
+    // The caller only hands us blocks proposed by its leader
+    if !catch_up.extends_tip(blockstore, block, &block.leader) {
+        let repair = if was_caught_up { catch_up.next_block_repair(blockstore) } else { None };
+        return Replayed::Gap { repair };
+    }
+    if let Err(e) = blockstore.insert(block.clone()) {
+        error!("Failed to store block {}: {}", block.block_height, e);
+    }
+    Replayed::Stored
+}
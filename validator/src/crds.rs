@@ -0,0 +1,378 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use rand::seq::SliceRandom;
+use crate::block::Message;
+use crate::clock::{Clock, SystemClock};
+use crate::keypair::{self, Keypair};
+use crate::network::{read_message, write_message};
+use tracing::{debug, warn};
+
+const PUSH_FANOUT: usize = 3;
+const GOSSIP_INTERVAL_MS: u64 = 1000;
+/// A peer that doesn't finish a push or pull in time is skipped for the round.
+const GOSSIP_TIMEOUT: Duration = Duration::from_millis(GOSSIP_INTERVAL_MS);
+/// Identities a node keeps contact info for. Once full, a new identity only
+/// gets in by replacing the stalest entry.
+pub const MAX_CRDS_VALUES: usize = 4096;
+/// Nodes re-sign their contact info every gossip round, so a value this old
+/// belongs to a node that has gone away.
+pub const CRDS_VALUE_TIMEOUT_MS: u64 = 60_000;
+/// Each hash is cut into four 8-byte words, one per bit position.
+const MAX_BLOOM_HASHES: usize = 4;
+/// Enough for about 400k values at ten bits each.
+const MAX_BLOOM_WORDS: usize = 1 << 16;
+
+/// How a node can be reached. `wallclock` doubles as the version: a newer
+/// value from the same identity replaces the older one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContactInfo {
+    pub identity: String,
+    pub gossip_addr: String,
+    pub tpu_addr: String,
+    pub tvu_addr: String,
+    pub shred_version: u16,
+    pub wallclock: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CrdsValue {
+    pub contact_info: ContactInfo,
+    pub signature: Vec<u8>,
+}
+
+impl CrdsValue {
+    pub fn new_signed(contact_info: ContactInfo, keypair: &Keypair) -> Self {
+        let signature = keypair.sign(&serde_json::to_vec(&contact_info).unwrap());
+        CrdsValue { contact_info, signature }
+    }
+
+    pub fn verify(&self) -> bool {
+        keypair::verify(
+            &self.contact_info.identity,
+            &serde_json::to_vec(&self.contact_info).unwrap(),
+            &self.signature,
+        )
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(self).unwrap());
+        let result = hasher.finalize();
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&result);
+        hash
+    }
+}
+
+/// Nodes only gossip with peers booted from the same genesis.
+pub fn shred_version(genesis_hash: &[u8; 32]) -> u16 {
+    let version = genesis_hash
+        .chunks(2)
+        .fold(0u16, |acc, chunk| acc ^ u16::from_be_bytes([chunk[0], chunk[1]]));
+    version.max(1)
+}
+
+pub fn wallclock() -> u64 {
+    SystemClock.now_ms()
+}
+
+/// Set of value hashes a node already holds, sent with pull requests so the
+/// responder only returns what is missing. Filters come from peers, so one
+/// that would index past the hash or the bits fails to deserialize.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "RawBloomFilter")]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: usize,
+}
+
+#[derive(Deserialize)]
+struct RawBloomFilter {
+    bits: Vec<u64>,
+    num_hashes: usize,
+}
+
+impl TryFrom<RawBloomFilter> for BloomFilter {
+    type Error = String;
+
+    fn try_from(raw: RawBloomFilter) -> Result<Self, Self::Error> {
+        if !(1..=MAX_BLOOM_HASHES).contains(&raw.num_hashes) {
+            return Err(format!("bloom filter with {} hashes", raw.num_hashes));
+        }
+        if raw.bits.is_empty() || raw.bits.len() > MAX_BLOOM_WORDS {
+            return Err(format!("bloom filter with {} words", raw.bits.len()));
+        }
+        Ok(BloomFilter { bits: raw.bits, num_hashes: raw.num_hashes })
+    }
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize) -> Self {
+        let num_bits = (expected_items * 10).clamp(64, MAX_BLOOM_WORDS * 64);
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64)],
+            num_hashes: 3,
+        }
+    }
+
+    fn positions(&self, hash: &[u8; 32]) -> Vec<usize> {
+        let num_bits = self.bits.len() * 64;
+        (0..self.num_hashes)
+            .map(|i| {
+                let mut word = [0u8; 8];
+                word.copy_from_slice(&hash[i * 8..i * 8 + 8]);
+                (u64::from_le_bytes(word) % num_bits as u64) as usize
+            })
+            .collect()
+    }
+
+    pub fn add(&mut self, hash: &[u8; 32]) {
+        for position in self.positions(hash) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    pub fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.positions(hash)
+            .into_iter()
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+/// Cluster replicated data store: the latest signed contact info per
+/// identity, for at most `capacity` identities.
+#[derive(Debug)]
+pub struct Crds {
+    table: HashMap<String, (CrdsValue, u64)>,
+    next_ordinal: u64,
+    capacity: usize,
+}
+
+impl Default for Crds {
+    fn default() -> Self {
+        Crds::with_capacity(MAX_CRDS_VALUES)
+    }
+}
+
+impl Crds {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Crds { table: HashMap::new(), next_ordinal: 0, capacity }
+    }
+
+    /// Keeps the value if it is validly signed and newer than what we hold.
+    pub fn insert(&mut self, value: CrdsValue) -> bool {
+        if !value.verify() {
+            return false;
+        }
+        if let Some((current, _)) = self.table.get(&value.contact_info.identity) {
+            if current.contact_info.wallclock >= value.contact_info.wallclock {
+                return false;
+            }
+        } else if self.table.len() >= self.capacity {
+            let stalest = self
+                .table
+                .values()
+                .map(|(value, _)| &value.contact_info)
+                .min_by_key(|info| info.wallclock)
+                .filter(|info| info.wallclock < value.contact_info.wallclock)
+                .map(|info| info.identity.clone());
+            match stalest {
+                Some(identity) => self.table.remove(&identity),
+                None => return false,
+            };
+        }
+        self.table.insert(value.contact_info.identity.clone(), (value, self.next_ordinal));
+        self.next_ordinal += 1;
+        true
+    }
+
+    /// Values inserted at or after `cursor`, and the cursor to resume from.
+    pub fn values_since(&self, cursor: u64) -> (Vec<CrdsValue>, u64) {
+        let values = self
+            .table
+            .values()
+            .filter(|(_, ordinal)| *ordinal >= cursor)
+            .map(|(value, _)| value.clone())
+            .collect();
+        (values, self.next_ordinal)
+    }
+
+    pub fn build_filter(&self) -> BloomFilter {
+        let mut filter = BloomFilter::new(self.table.len());
+        for (value, _) in self.table.values() {
+            filter.add(&value.hash());
+        }
+        filter
+    }
+
+    pub fn missing_from(&self, filter: &BloomFilter) -> Vec<CrdsValue> {
+        self.table
+            .values()
+            .filter(|(value, _)| !filter.contains(&value.hash()))
+            .map(|(value, _)| value.clone())
+            .collect()
+    }
+
+    /// Drops values signed before `wallclock`, except `keep`'s.
+    pub fn purge_older_than(&mut self, wallclock: u64, keep: &str) {
+        self.table
+            .retain(|identity, (value, _)| identity == keep || value.contact_info.wallclock >= wallclock);
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    pub fn get(&self, identity: &str) -> Option<&ContactInfo> {
+        self.table.get(identity).map(|(value, _)| &value.contact_info)
+    }
+
+    pub fn contact_infos(&self) -> impl Iterator<Item = &ContactInfo> {
+        self.table.values().map(|(value, _)| &value.contact_info)
+    }
+}
+
+/// This node's view of the cluster plus the identity it publishes under.
+pub struct ClusterInfo {
+    keypair: Arc<Keypair>,
+    my_info: Mutex<ContactInfo>,
+    crds: Mutex<Crds>,
+}
+
+impl ClusterInfo {
+    pub fn new(keypair: Arc<Keypair>, my_info: ContactInfo) -> Self {
+        let mut crds = Crds::default();
+        crds.insert(CrdsValue::new_signed(my_info.clone(), &keypair));
+        ClusterInfo {
+            keypair,
+            my_info: Mutex::new(my_info),
+            crds: Mutex::new(crds),
+        }
+    }
+
+    pub fn id(&self) -> String {
+        self.keypair.pubkey()
+    }
+
+    pub async fn shred_version(&self) -> u16 {
+        self.my_info.lock().await.shred_version
+    }
+
+    /// Peers from the same cluster, never including ourselves.
+    pub async fn peers(&self) -> Vec<ContactInfo> {
+        let id = self.id();
+        let shred_version = self.shred_version().await;
+        self.crds
+            .lock()
+            .await
+            .contact_infos()
+            .filter(|info| info.identity != id && info.shred_version == shred_version)
+            .cloned()
+            .collect()
+    }
+
+    pub async fn gossip_peers(&self) -> Vec<String> {
+        self.peers().await.into_iter().map(|info| info.gossip_addr).collect()
+    }
+
+    pub async fn lookup(&self, identity: &str) -> Option<ContactInfo> {
+        self.crds.lock().await.get(identity).cloned()
+    }
+
+    pub async fn insert_values(&self, values: Vec<CrdsValue>) -> usize {
+        let shred_version = self.shred_version().await;
+        let mut crds = self.crds.lock().await;
+        values
+            .into_iter()
+            .filter(|value| value.contact_info.shred_version == shred_version)
+            .filter(|value| crds.insert(value.clone()))
+            .count()
+    }
+
+    /// Records the caller's own contact info and returns everything it lacks.
+    pub async fn handle_pull_request(&self, filter: &BloomFilter, caller: CrdsValue) -> Vec<CrdsValue> {
+        self.insert_values(vec![caller]).await;
+        self.crds.lock().await.missing_from(filter)
+    }
+
+    /// Re-signs our contact info and forgets nodes that stopped doing so.
+    async fn refresh_self(&self) -> CrdsValue {
+        let mut my_info = self.my_info.lock().await;
+        my_info.wallclock = wallclock();
+        let value = CrdsValue::new_signed(my_info.clone(), &self.keypair);
+        let mut crds = self.crds.lock().await;
+        crds.insert(value.clone());
+        crds.purge_older_than(my_info.wallclock.saturating_sub(CRDS_VALUE_TIMEOUT_MS), &my_info.identity);
+        value
+    }
+
+    async fn pull_from(&self, addr: &str, filter: BloomFilter, caller: CrdsValue) -> io::Result<usize> {
+        let response = tokio::time::timeout(GOSSIP_TIMEOUT, async {
+            let mut stream = TcpStream::connect(addr).await?;
+            write_message(&mut stream, &Message::CrdsPullRequest(filter, caller)).await?;
+            read_message(&mut stream).await
+        })
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        match response {
+            Message::CrdsPullResponse(values) => Ok(self.insert_values(values).await),
+            _ => Ok(0),
+        }
+    }
+
+    async fn push_to(addr: &str, values: &[CrdsValue]) -> io::Result<()> {
+        tokio::time::timeout(GOSSIP_TIMEOUT, async {
+            let mut stream = TcpStream::connect(addr).await?;
+            write_message(&mut stream, &Message::CrdsPush(values.to_vec())).await
+        })
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    }
+
+    /// Periodically re-signs our contact info, pushes new values to a few
+    /// peers and pulls what we're missing from one, starting at `entrypoint`.
+    pub async fn run_gossip(self: Arc<Self>, entrypoint: Option<String>) {
+        let mut push_cursor = 0;
+        loop {
+            let me = self.refresh_self().await;
+            let peers = self.gossip_peers().await;
+
+            let (values, cursor) = self.crds.lock().await.values_since(push_cursor);
+            push_cursor = cursor;
+            let push_targets: Vec<String> = peers
+                .choose_multiple(&mut rand::thread_rng(), PUSH_FANOUT)
+                .cloned()
+                .collect();
+            for addr in push_targets {
+                if let Err(e) = Self::push_to(&addr, &values).await {
+                    debug!("Failed to push cluster info to {}: {}", addr, e);
+                }
+            }
+
+            let pull_target = match peers.choose(&mut rand::thread_rng()) {
+                Some(addr) => Some(addr.clone()),
+                None => entrypoint.clone(),
+            };
+            if let Some(addr) = pull_target {
+                let filter = self.crds.lock().await.build_filter();
+                match self.pull_from(&addr, filter, me).await {
+                    Ok(0) => {}
+                    Ok(count) => debug!("Discovered {} cluster info updates from {}", count, addr),
+                    Err(e) => warn!("Failed to pull cluster info from {}: {}", addr, e),
+                }
+            }
+
+            tokio::time::sleep(tokio::time::Duration::from_millis(GOSSIP_INTERVAL_MS)).await;
+        }
+    }
+}
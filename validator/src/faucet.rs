@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use crate::keypair::Keypair;
+use crate::network::{read_message, write_message};
+use crate::rpc_client::RpcClient;
+use crate::transaction::Transaction;
+use tracing::{info, info_span, warn, Instrument};
+
+pub const DEFAULT_FAUCET_ADDR: &str = "127.0.0.1:9900";
+pub const DEFAULT_PER_REQUEST_CAP: u64 = 1_000;
+pub const DEFAULT_PER_ADDRESS_CAP: u64 = 10_000;
+pub const DEFAULT_PER_WINDOW_CAP: u64 = 1_000_000;
+pub const DEFAULT_WINDOW_SECS: u64 = 3600;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AirdropRequest {
+    pub pubkey: String,
+    pub amount: u64,
+}
+
+/// The transfer's base58 signature, or why the airdrop was refused.
+pub type AirdropResponse = Result<String, String>;
+
+/// How much the faucet hands out. Limits are per window, which restarts
+/// `window` after it began.
+#[derive(Debug, Clone)]
+pub struct FaucetLimits {
+    pub per_request: u64,
+    pub per_address: u64,
+    pub per_window: u64,
+    pub window: Duration,
+}
+
+impl Default for FaucetLimits {
+    fn default() -> Self {
+        FaucetLimits {
+            per_request: DEFAULT_PER_REQUEST_CAP,
+            per_address: DEFAULT_PER_ADDRESS_CAP,
+            per_window: DEFAULT_PER_WINDOW_CAP,
+            window: Duration::from_secs(DEFAULT_WINDOW_SECS),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FaucetError {
+    InvalidPubkey(String),
+    ZeroAmount,
+    RequestTooLarge { amount: u64, cap: u64 },
+    AddressLimit { pubkey: String, remaining: u64 },
+    WindowLimit { remaining: u64 },
+    Transfer(String),
+}
+
+impl fmt::Display for FaucetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaucetError::InvalidPubkey(pubkey) => write!(f, "{} is not a base58 public key", pubkey),
+            FaucetError::ZeroAmount => write!(f, "airdrop amount must be greater than zero"),
+            FaucetError::RequestTooLarge { amount, cap } => write!(f, "{} is more than the {} allowed per airdrop", amount, cap),
+            FaucetError::AddressLimit { pubkey, remaining } => {
+                write!(f, "{} can only receive {} more until the window resets", pubkey, remaining)
+            }
+            FaucetError::WindowLimit { remaining } => {
+                write!(f, "the faucet can only hand out {} more until the window resets", remaining)
+            }
+            FaucetError::Transfer(e) => write!(f, "transfer failed: {}", e),
+        }
+    }
+}
+
+/// Airdrops handed out since the window started.
+#[derive(Debug)]
+pub struct AirdropWindow {
+    started: Instant,
+    total: u64,
+    per_address: HashMap<String, u64>,
+}
+
+impl AirdropWindow {
+    pub fn new(started: Instant) -> Self {
+        AirdropWindow {
+            started,
+            total: 0,
+            per_address: HashMap::new(),
+        }
+    }
+
+    /// Starts over once `limits.window` has passed since this window began.
+    pub fn roll(&mut self, now: Instant, limits: &FaucetLimits) {
+        if now.saturating_duration_since(self.started) >= limits.window {
+            *self = AirdropWindow::new(now);
+        }
+    }
+
+    /// Whether `request` fits in what is left of the limits.
+    pub fn check(&self, limits: &FaucetLimits, request: &AirdropRequest) -> Result<(), FaucetError> {
+        if !is_pubkey(&request.pubkey) {
+            return Err(FaucetError::InvalidPubkey(request.pubkey.clone()));
+        }
+        if request.amount == 0 {
+            return Err(FaucetError::ZeroAmount);
+        }
+        if request.amount > limits.per_request {
+            return Err(FaucetError::RequestTooLarge { amount: request.amount, cap: limits.per_request });
+        }
+        let received = self.per_address.get(&request.pubkey).copied().unwrap_or(0);
+        let remaining = limits.per_address.saturating_sub(received);
+        if request.amount > remaining {
+            return Err(FaucetError::AddressLimit { pubkey: request.pubkey.clone(), remaining });
+        }
+        let remaining = limits.per_window.saturating_sub(self.total);
+        if request.amount > remaining {
+            return Err(FaucetError::WindowLimit { remaining });
+        }
+        Ok(())
+    }
+
+    /// Counts an airdrop that was sent.
+    pub fn record(&mut self, request: &AirdropRequest) {
+        self.total += request.amount;
+        *self.per_address.entry(request.pubkey.clone()).or_insert(0) += request.amount;
+    }
+}
+
+/// Hands out tokens from a funded keypair, submitting each airdrop as an
+/// ordinary signed transfer through the leader's RPC.
+pub struct Faucet {
+    keypair: Keypair,
+    rpc: RpcClient,
+    limits: FaucetLimits,
+    window: Mutex<AirdropWindow>,
+}
+
+fn is_pubkey(pubkey: &str) -> bool {
+    bs58::decode(pubkey).into_vec().is_ok_and(|bytes| bytes.len() == 32)
+}
+
+impl Faucet {
+    pub fn new(keypair: Keypair, rpc: RpcClient, limits: FaucetLimits) -> Self {
+        Faucet {
+            keypair,
+            rpc,
+            limits,
+            window: Mutex::new(AirdropWindow::new(Instant::now())),
+        }
+    }
+
+    pub fn pubkey(&self) -> String {
+        self.keypair.pubkey()
+    }
+
+    /// Checks the limits and sends the transfer. Requests are handled one at
+    /// a time so that concurrent ones can't slip past a limit together.
+    pub async fn airdrop(&self, request: &AirdropRequest) -> Result<String, FaucetError> {
+        let mut window = self.window.lock().await;
+        window.roll(Instant::now(), &self.limits);
+        window.check(&self.limits, request)?;
+
+        let blockhash = self.rpc.get_latest_blockhash().await.map_err(|e| FaucetError::Transfer(e.to_string()))?;
+        let transaction = Transaction::new(&self.keypair, request.pubkey.clone(), request.amount, blockhash);
+        let signature = self
+            .rpc
+            .send_transaction(&transaction)
+            .await
+            .map_err(|e| FaucetError::Transfer(e.to_string()))?;
+        window.record(request);
+        Ok(signature)
+    }
+}
+
+/// Answers airdrop requests, one request and response per frame, until the
+/// client hangs up.
+async fn serve_client(faucet: &Faucet, mut stream: TcpStream) {
+    while let Ok(request) = read_message::<_, AirdropRequest>(&mut stream).await {
+        let response: AirdropResponse = match faucet.airdrop(&request).await {
+            Ok(signature) => {
+                info!("Sent {} to {} in {}", request.amount, request.pubkey, signature);
+                Ok(signature)
+            }
+            Err(e) => {
+                warn!("Refused airdrop of {} to {}: {}", request.amount, request.pubkey, e);
+                Err(e.to_string())
+            }
+        };
+        if write_message(&mut stream, &response).await.is_err() {
+            break;
+        }
+    }
+}
+
+pub async fn run_faucet(faucet: Arc<Faucet>, listener: TcpListener) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept faucet connection: {}", e);
+                continue;
+            }
+        };
+        let faucet = Arc::clone(&faucet);
+        tokio::spawn(async move { serve_client(&faucet, stream).await }.instrument(info_span!("airdrop", peer = %addr)));
+    }
+}
+
+/// Asks the faucet at `addr` for `amount` tokens, returning the transfer's
+/// signature.
+pub async fn request_airdrop(addr: SocketAddr, pubkey: &str, amount: u64) -> io::Result<String> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to connect to faucet at {}: {}", addr, e)))?;
+    let request = AirdropRequest { pubkey: pubkey.to_string(), amount };
+    write_message(&mut stream, &request).await?;
+    let response: AirdropResponse = read_message(&mut stream).await?;
+    response.map_err(|e| io::Error::other(format!("airdrop refused: {}", e)))
+}
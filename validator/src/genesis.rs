@@ -0,0 +1,104 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::inflation::Inflation;
+use crate::slashing::SlashingConfig;
+
+pub const DEFAULT_GENESIS_PATH: &str = "genesis.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisAccount {
+    pub pubkey: String,
+    pub balance: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisValidator {
+    pub identity: String,
+    pub stake: u64,
+    /// Percentage of stake rewards kept by the validator.
+    #[serde(default)]
+    pub commission: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PohConfig {
+    pub tick_duration_ms: u64,
+    pub ticks_per_slot: u64,
+}
+
+impl Default for PohConfig {
+    fn default() -> Self {
+        PohConfig {
+            tick_duration_ms: 400,
+            ticks_per_slot: 25,
+        }
+    }
+}
+
+impl PohConfig {
+    pub fn slot_duration_ms(&self) -> u64 {
+        self.tick_duration_ms * self.ticks_per_slot
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EpochSchedule {
+    pub slots_per_epoch: u64,
+}
+
+impl Default for EpochSchedule {
+    fn default() -> Self {
+        EpochSchedule { slots_per_epoch: 60 }
+    }
+}
+
+impl EpochSchedule {
+    pub fn epoch_for_slot(&self, slot: u64) -> u64 {
+        slot / self.slots_per_epoch.max(1)
+    }
+}
+
+/// Initial state every node boots from. Two nodes loading the same file
+/// compute the same `hash`, which is used to seed the chain and PoH.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenesisConfig {
+    pub creation_time: u64,
+    pub accounts: Vec<GenesisAccount>,
+    pub validators: Vec<GenesisValidator>,
+    pub poh: PohConfig,
+    pub epoch_schedule: EpochSchedule,
+    #[serde(default)]
+    pub inflation: Inflation,
+    #[serde(default)]
+    pub slashing: SlashingConfig,
+}
+
+impl GenesisConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, data)
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(self).unwrap());
+        let result = hasher.finalize();
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&result);
+        hash
+    }
+
+    pub fn total_supply(&self) -> u64 {
+        let balances: u64 = self.accounts.iter().map(|account| account.balance).sum();
+        let stakes: u64 = self.validators.iter().map(|validator| validator.stake).sum();
+        balances + stakes
+    }
+}
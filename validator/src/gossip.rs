@@ -0,0 +1,264 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use rand::seq::SliceRandom;
+use crate::block::Message;
+use crate::connection::ConnectionManager;
+use crate::crds::{wallclock, ContactInfo};
+use crate::keypair::{self, Keypair};
+use crate::metrics::metrics;
+use tracing::trace;
+
+/// Hops a gossiped message may travel before peers stop forwarding it.
+pub const DEFAULT_GOSSIP_TTL: u8 = 4;
+pub const DEFAULT_GOSSIP_FANOUT: usize = 3;
+const SEEN_CACHE_CAPACITY: usize = 10_000;
+/// How long a prune holds. Afterwards the origin is pushed to that peer
+/// again, so a path pruned once can recover if the others fail. Prunes
+/// stamped further than this from our clock are ignored.
+const PRUNE_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GossipFrame {
+    /// Identity of the node that first gossiped the payload.
+    pub origin: String,
+    /// Identity of the node that sent this copy, where prunes go.
+    pub from: String,
+    pub ttl: u8,
+    /// Origin wallclock in ms, for propagation latency.
+    pub created_at: u64,
+    pub payload: Box<Message>,
+}
+
+pub fn message_hash(message: &Message) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(message).unwrap());
+    let result = hasher.finalize();
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&result);
+    hash
+}
+
+/// Asks the receiver to stop pushing messages from `origins` to `from`.
+/// Signed by `from`, so no one else can cut a node off from an origin.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PruneMessage {
+    pub from: String,
+    pub origins: Vec<String>,
+    pub wallclock: u64,
+    pub signature: Vec<u8>,
+}
+
+impl PruneMessage {
+    pub fn new_signed(keypair: &Keypair, origins: Vec<String>) -> Self {
+        let mut prune = PruneMessage {
+            from: keypair.pubkey(),
+            origins,
+            wallclock: wallclock(),
+            signature: Vec::new(),
+        };
+        prune.signature = keypair.sign(&prune.signing_bytes());
+        prune
+    }
+
+    pub fn verify(&self) -> bool {
+        keypair::verify(&self.from, &self.signing_bytes(), &self.signature)
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&(&self.from, &self.origins, self.wallclock)).unwrap()
+    }
+}
+
+/// Bounded set of message hashes already delivered, oldest evicted first.
+#[derive(Debug, Default)]
+pub struct SeenCache {
+    hashes: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl SeenCache {
+    /// Returns true if the hash had not been seen before.
+    pub fn insert(&mut self, hash: [u8; 32]) -> bool {
+        if !self.hashes.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > SEEN_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct GossipActivity {
+    pub messages_sent: usize,
+    pub messages_received: usize,
+    pub duplicates: usize,
+    pub prunes_sent: usize,
+    pub prunes_received: usize,
+    pub total_hops: u64,
+    pub total_latency_ms: u64,
+}
+
+impl GossipActivity {
+    pub fn average_hops(&self) -> f64 {
+        if self.messages_received == 0 {
+            return 0.0;
+        }
+        self.total_hops as f64 / self.messages_received as f64
+    }
+
+    pub fn average_latency_ms(&self) -> f64 {
+        if self.messages_received == 0 {
+            return 0.0;
+        }
+        self.total_latency_ms as f64 / self.messages_received as f64
+    }
+}
+
+/// Push gossip: new messages go to `fanout` random peers, duplicates are
+/// dropped and answered with a prune so the redundant path stops sending
+/// that origin's messages to us.
+pub struct PushGossip {
+    keypair: Arc<Keypair>,
+    identity: String,
+    fanout: usize,
+    connections: Arc<ConnectionManager>,
+    seen: Mutex<SeenCache>,
+    /// Per peer identity, the origins that peer asked us not to forward and
+    /// when it asked.
+    pruned: Mutex<HashMap<String, HashMap<String, u64>>>,
+    pub activity: Arc<Mutex<GossipActivity>>,
+}
+
+impl PushGossip {
+    pub fn new(
+        keypair: Arc<Keypair>,
+        fanout: usize,
+        connections: Arc<ConnectionManager>,
+        activity: Arc<Mutex<GossipActivity>>,
+    ) -> Self {
+        PushGossip {
+            identity: keypair.pubkey(),
+            keypair,
+            fanout: fanout.max(1),
+            connections,
+            seen: Mutex::new(SeenCache::default()),
+            pruned: Mutex::new(HashMap::new()),
+            activity,
+        }
+    }
+
+    /// Starts gossiping a message this node created. Marks it seen so it is
+    /// not delivered back to us when peers forward it.
+    pub async fn originate(&self, message: &Message, peers: &[ContactInfo]) {
+        if !self.seen.lock().await.insert(message_hash(message)) {
+            return;
+        }
+        let frame = GossipFrame {
+            origin: self.identity.clone(),
+            from: self.identity.clone(),
+            ttl: DEFAULT_GOSSIP_TTL,
+            created_at: wallclock(),
+            payload: Box::new(message.clone()),
+        };
+        self.push(&frame, peers, None).await;
+    }
+
+    /// Returns the payload the first time it arrives and forwards it on;
+    /// later copies are counted and pruned.
+    pub async fn receive(&self, frame: GossipFrame, peers: &[ContactInfo]) -> Option<Message> {
+        if !self.seen.lock().await.insert(message_hash(&frame.payload)) {
+            self.activity.lock().await.duplicates += 1;
+            metrics().gossip_duplicates.inc();
+            self.send_prune(&frame, peers).await;
+            return None;
+        }
+
+        {
+            let mut activity = self.activity.lock().await;
+            activity.messages_received += 1;
+            metrics().gossip_received.inc();
+            activity.total_hops += (DEFAULT_GOSSIP_TTL.saturating_sub(frame.ttl) + 1) as u64;
+            activity.total_latency_ms += wallclock().saturating_sub(frame.created_at);
+        }
+
+        if frame.ttl > 1 {
+            let forward = GossipFrame {
+                from: self.identity.clone(),
+                ttl: frame.ttl - 1,
+                ..frame.clone()
+            };
+            self.push(&forward, peers, Some(&frame.from)).await;
+        }
+        Some(*frame.payload)
+    }
+
+    /// Records a peer's prune. Unsigned or stale prunes are dropped, and
+    /// expired entries are cleared out while we hold the lock.
+    pub async fn handle_prune(&self, prune: PruneMessage) {
+        let now = wallclock();
+        if now.abs_diff(prune.wallclock) > PRUNE_TIMEOUT_MS || !prune.verify() {
+            trace!("Dropped prune from {}", prune.from);
+            return;
+        }
+        self.activity.lock().await.prunes_received += 1;
+
+        let mut pruned = self.pruned.lock().await;
+        pruned.retain(|_, origins| {
+            origins.retain(|_, pruned_at| now.saturating_sub(*pruned_at) < PRUNE_TIMEOUT_MS);
+            !origins.is_empty()
+        });
+        let origins = pruned.entry(prune.from).or_default();
+        for origin in prune.origins {
+            origins.insert(origin, now);
+        }
+    }
+
+    async fn send_prune(&self, frame: &GossipFrame, peers: &[ContactInfo]) {
+        let sender = match peers.iter().find(|peer| peer.identity == frame.from) {
+            Some(sender) => sender,
+            None => return,
+        };
+        let prune = Message::GossipPrune(PruneMessage::new_signed(&self.keypair, vec![frame.origin.clone()]));
+        if self.connections.send(&sender.identity, &sender.gossip_addr, prune).await {
+            self.activity.lock().await.prunes_sent += 1;
+        }
+    }
+
+    async fn push(&self, frame: &GossipFrame, peers: &[ContactInfo], exclude: Option<&String>) {
+        let targets: Vec<&ContactInfo> = {
+            let now = wallclock();
+            let pruned = self.pruned.lock().await;
+            let eligible: Vec<&ContactInfo> = peers
+                .iter()
+                .filter(|peer| Some(&peer.identity) != exclude && peer.identity != self.identity)
+                .filter(|peer| {
+                    pruned
+                        .get(&peer.identity)
+                        .and_then(|origins| origins.get(&frame.origin))
+                        .is_none_or(|pruned_at| now.saturating_sub(*pruned_at) >= PRUNE_TIMEOUT_MS)
+                })
+                .collect();
+            eligible
+                .choose_multiple(&mut rand::thread_rng(), self.fanout)
+                .copied()
+                .collect()
+        };
+
+        for peer in targets {
+            let message = Message::Gossip(frame.clone());
+            if self.connections.send(&peer.identity, &peer.gossip_addr, message).await {
+                self.activity.lock().await.messages_sent += 1;
+                metrics().gossip_sent.inc();
+                trace!("Gossiped message to {}", peer.gossip_addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::io;
+use crate::block::{Block, Message, Vote};
+use crate::blockstore::Blockstore;
+use crate::catchup::CatchUp;
+use crate::clock::Clock;
+use crate::consensus::{build_block, replay_block, ConfirmationTracker, Replayed};
+use crate::genesis::GenesisConfig;
+use crate::inflation::{pay_epoch_rewards, EpochRewards};
+use crate::keypair::Keypair;
+use crate::network::Network;
+use crate::repair::SignedRepairRequest;
+use crate::slashing::{ConflictDetector, Evidence, EvidencePool};
+use crate::stake::{StakeAccounts, StakeInstruction};
+use crate::transaction::Transaction;
+use tracing::{error, warn};
+
+/// A validator's chain and how it reacts to the leader's blocks. The
+/// validator drives it from its connections and the simulator from virtual
+/// time; both pass the `Clock` and `Network` it runs on.
+#[derive(Debug)]
+pub struct ValidatorChain {
+    pub blockstore: Blockstore,
+    pub catch_up: CatchUp,
+    pub conflict_detector: ConflictDetector,
+}
+
+impl ValidatorChain {
+    pub fn new(blockstore: Blockstore, genesis_hash: [u8; 32]) -> Self {
+        ValidatorChain {
+            blockstore,
+            catch_up: CatchUp::new(genesis_hash),
+            conflict_detector: ConflictDetector::default(),
+        }
+    }
+
+    /// Replays a block proposed by `leader` and answers it: a vote if it
+    /// extends our chain, a repair request if it leaves a gap and evidence
+    /// if the leader signed another block for the slot.
+    pub fn handle_proposal(
+        &mut self,
+        clock: &impl Clock,
+        net: &mut impl Network,
+        identity: &Keypair,
+        leader: &str,
+        block: &Block,
+    ) -> Replayed {
+        let replayed = replay_block(block, &mut self.blockstore, &mut self.catch_up, &mut self.conflict_detector);
+        let id = identity.pubkey();
+        match &replayed {
+            Replayed::Stored => net.send(&id, leader, Message::ConsensusVote(Vote::new(block, identity))),
+            Replayed::Gap { repair: Some(request) } => {
+                let repair = SignedRepairRequest::new_signed(identity, request.clone(), clock.now_ms());
+                net.send(&id, leader, Message::Repair(repair));
+            }
+            Replayed::Equivocation(offense) => {
+                net.send(&id, leader, Message::Evidence(Evidence::new(offense.clone(), identity)));
+            }
+            _ => {}
+        }
+        replayed
+    }
+
+    /// Stores the repaired blocks `leader` signed that extend our chain and
+    /// asks for more while we are still behind. Returns how many were stored.
+    pub fn handle_repaired_blocks(
+        &mut self,
+        clock: &impl Clock,
+        net: &mut impl Network,
+        identity: &Keypair,
+        leader: &str,
+        blocks: Vec<Block>,
+    ) -> io::Result<usize> {
+        let applied = self.catch_up.apply_blocks(&mut self.blockstore, leader, blocks)?;
+        if applied > 0 {
+            self.repair(clock, net, identity, leader);
+        }
+        Ok(applied)
+    }
+
+    /// Asks `leader` for the next blocks we are missing. Returns false if
+    /// there are none.
+    pub fn repair(&self, clock: &impl Clock, net: &mut impl Network, identity: &Keypair, leader: &str) -> bool {
+        match self.catch_up.next_block_repair(&self.blockstore) {
+            Some(request) => {
+                let repair = SignedRepairRequest::new_signed(identity, request, clock.now_ms());
+                net.send(&identity.pubkey(), leader, Message::Repair(repair));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// What the leader made of a vote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteOutcome {
+    /// The voter already voted for another block at this slot. Evidence
+    /// against it is queued and the vote doesn't count.
+    DoubleVote,
+    /// For a block the leader doesn't hold.
+    Unknown,
+    /// The voter's vote for this slot was already counted.
+    Repeated,
+    /// The voter's first vote for the slot, which earned it a vote credit.
+    Counted { confirmed: bool },
+}
+
+/// The leader's vote counting state, borrowed for one vote. The leader
+/// assembles it from its locks, the simulator from its own fields.
+pub struct VoteCounter<'a> {
+    pub identity: &'a Keypair,
+    pub blockstore: &'a Blockstore,
+    pub conflict_detector: &'a mut ConflictDetector,
+    pub evidence_pool: &'a mut EvidencePool,
+    pub confirmations: &'a mut ConfirmationTracker,
+    pub vote_credits: &'a mut HashMap<String, u64>,
+}
+
+impl VoteCounter<'_> {
+    /// Counts a vote whose signature the caller checked against the
+    /// connection or peer it came from.
+    pub fn handle_vote(&mut self, vote: &Vote, active_stakes: &HashMap<String, u64>) -> VoteOutcome {
+        if let Some(offense) = self.conflict_detector.observe_vote(vote.clone()) {
+            self.evidence_pool.add(Evidence::new(offense, self.identity));
+            return VoteOutcome::DoubleVote;
+        }
+        // A voter earns one credit per slot however often it sends the vote
+        let repeated = self.confirmations.has_voted(vote.block_height, &vote.voter);
+        match self.confirmations.record_vote(vote, self.blockstore, active_stakes) {
+            Some(_) if repeated => VoteOutcome::Repeated,
+            Some(confirmed) => {
+                *self.vote_credits.entry(vote.voter.clone()).or_insert(0) += 1;
+                VoteOutcome::Counted { confirmed }
+            }
+            None => VoteOutcome::Unknown,
+        }
+    }
+}
+
+/// What the leader does to stake before building a block: slashes the
+/// offenders in `evidence`, then applies `stake_instructions` in arrival
+/// order, leaving out those that fail against the bank as it then stands.
+/// Returns the stake burned and the instructions the block carries.
+pub fn apply_block_stake(
+    balances: &mut HashMap<String, u64>,
+    stakes: &mut StakeAccounts,
+    evidence: &[Evidence],
+    stake_instructions: Vec<StakeInstruction>,
+    epoch: u64,
+    slash_fraction: f64,
+) -> (u64, Vec<StakeInstruction>) {
+    let mut burned = 0;
+    for evidence in evidence {
+        let offender = evidence.offense.offender();
+        let slashed = stakes.slash(offender, slash_fraction);
+        warn!(
+            "Slashed validator {} for an offense at slot {}: burned {} and removed from the leader schedule",
+            offender, evidence.offense.slot(), slashed
+        );
+        burned += slashed;
+    }
+    let applied = stake_instructions
+        .into_iter()
+        .filter(|instruction| match stakes.apply(balances, instruction, epoch) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropping stake instruction {}: {}", bs58::encode(instruction.signature()).into_string(), e);
+                false
+            }
+        })
+        .collect();
+    (burned, applied)
+}
+
+/// A block the leader produced and what producing it did to the bank.
+#[derive(Debug)]
+pub struct ProducedBlock {
+    pub block: Block,
+    /// Stake burned slashing the offenders in the block's evidence.
+    pub burned: u64,
+    /// Paid out if the block was the first of an epoch.
+    pub rewards: Option<EpochRewards>,
+}
+
+/// The leader's ledger and bank, borrowed to produce one block. The leader
+/// assembles it from its locks, the simulator from its own fields.
+pub struct BlockProducer<'a> {
+    pub identity: &'a Keypair,
+    pub genesis: &'a GenesisConfig,
+    pub blockstore: &'a mut Blockstore,
+    pub balances: &'a mut HashMap<String, u64>,
+    pub stakes: &'a mut StakeAccounts,
+    pub vote_credits: &'a mut HashMap<String, u64>,
+}
+
+impl BlockProducer<'_> {
+    /// Builds the block on `parent_hash` at `parent_height` from what is
+    /// pending: evidence is slashed, then stake instructions and transfers
+    /// apply in arrival order. The block is stored and, if it starts an
+    /// epoch, the finished epoch's rewards are paid.
+    pub fn produce_block(
+        &mut self,
+        clock: &impl Clock,
+        parent_hash: [u8; 32],
+        parent_height: u64,
+        transactions: Vec<Transaction>,
+        evidence: Vec<Evidence>,
+        stake_instructions: Vec<StakeInstruction>,
+    ) -> ProducedBlock {
+        let epoch_schedule = &self.genesis.epoch_schedule;
+        let epoch = epoch_schedule.epoch_for_slot(parent_height + 1);
+        let (burned, stake_instructions) = apply_block_stake(
+            self.balances,
+            self.stakes,
+            &evidence,
+            stake_instructions,
+            epoch,
+            self.genesis.slashing.slash_fraction,
+        );
+        let block = build_block(
+            clock,
+            self.identity,
+            parent_hash,
+            parent_height,
+            transactions,
+            self.balances,
+            evidence,
+            stake_instructions,
+        );
+        if let Err(e) = self.blockstore.insert(block.clone()) {
+            error!("Failed to write block {} to the ledger: {}", block.block_height, e);
+        }
+        let finished_epoch = epoch_schedule.epoch_for_slot(parent_height);
+        let rewards = (epoch > finished_epoch)
+            .then(|| pay_epoch_rewards(self.genesis, self.stakes, self.balances, self.vote_credits, finished_epoch));
+        ProducedBlock { block, burned, rewards }
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use crate::genesis::{EpochSchedule, GenesisConfig, PohConfig};
+use crate::stake::StakeAccounts;
+use tracing::info;
+
+const MS_PER_YEAR: f64 = 365.25 * 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// Yearly inflation that starts at `initial`, shrinks by `taper` every year
+/// and never drops below `terminal`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Inflation {
+    pub initial: f64,
+    pub taper: f64,
+    pub terminal: f64,
+}
+
+impl Default for Inflation {
+    fn default() -> Self {
+        Inflation {
+            initial: 0.08,
+            taper: 0.15,
+            terminal: 0.015,
+        }
+    }
+}
+
+impl Inflation {
+    pub fn rate(&self, year: f64) -> f64 {
+        (self.initial * (1.0 - self.taper).powf(year)).max(self.terminal)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct EpochRewards {
+    pub epoch: u64,
+    pub inflation_rate: f64,
+    pub total: u64,
+    pub commission: u64,
+}
+
+pub fn epoch_duration_years(poh: &PohConfig, epoch_schedule: &EpochSchedule) -> f64 {
+    (poh.slot_duration_ms() * epoch_schedule.slots_per_epoch) as f64 / MS_PER_YEAR
+}
+
+/// Newly issued tokens for `epoch`, given the supply at the start of it.
+pub fn epoch_issuance(
+    inflation: &Inflation,
+    poh: &PohConfig,
+    epoch_schedule: &EpochSchedule,
+    epoch: u64,
+    total_supply: u64,
+) -> (f64, u64) {
+    let epoch_years = epoch_duration_years(poh, epoch_schedule);
+    let rate = inflation.rate(epoch as f64 * epoch_years);
+    (rate, (total_supply as f64 * rate * epoch_years) as u64)
+}
+
+/// Splits `issuance` across the stake accounts active in `epoch`, weighted by
+/// stake times the vote credits their validator earned. Stake delegated to a
+/// slashed validator earns nothing, as it no longer counts for the schedule. The validator's
+/// commission goes to its identity balance, the rest compounds into the stake.
+pub fn distribute_rewards(
+    stakes: &mut StakeAccounts,
+    balances: &mut HashMap<String, u64>,
+    vote_credits: &HashMap<String, u64>,
+    commissions: &HashMap<String, u8>,
+    issuance: u64,
+    epoch: u64,
+) -> (u64, u64) {
+    let points = |validator_id: &str, amount: u64| -> u128 {
+        amount as u128 * vote_credits.get(validator_id).copied().unwrap_or(0) as u128
+    };
+    let total_points: u128 = stakes
+        .active_accounts(epoch)
+        .map(|account| points(&account.validator_id, account.amount))
+        .sum();
+    if total_points == 0 {
+        return (0, 0);
+    }
+
+    let mut distributed = 0;
+    let mut total_commission = 0;
+    for account in stakes.active_accounts_mut(epoch) {
+        let reward = (issuance as u128 * points(&account.validator_id, account.amount) / total_points) as u64;
+        if reward == 0 {
+            continue;
+        }
+        let rate = commissions.get(&account.validator_id).copied().unwrap_or(0).min(100) as u64;
+        let commission = reward * rate / 100;
+        if commission > 0 {
+            *balances.entry(account.validator_id.clone()).or_insert(0) += commission;
+        }
+        account.amount += reward - commission;
+        distributed += reward;
+        total_commission += commission;
+    }
+    (distributed, total_commission)
+}
+
+/// Issues inflation for `epoch`, which just finished, and pays it out to
+/// stake accounts by the vote credits earned in it, then starts a fresh
+/// credit count.
+pub fn pay_epoch_rewards(
+    genesis: &GenesisConfig,
+    stakes: &mut StakeAccounts,
+    balances: &mut HashMap<String, u64>,
+    vote_credits: &mut HashMap<String, u64>,
+    epoch: u64,
+) -> EpochRewards {
+    let commissions: HashMap<String, u8> = genesis
+        .validators
+        .iter()
+        .map(|validator| (validator.identity.clone(), validator.commission))
+        .collect();
+    let total_supply = balances.values().sum::<u64>() + stakes.total_staked();
+    let (inflation_rate, issuance) =
+        epoch_issuance(&genesis.inflation, &genesis.poh, &genesis.epoch_schedule, epoch, total_supply);
+    let (total, commission) = distribute_rewards(stakes, balances, vote_credits, &commissions, issuance, epoch);
+    vote_credits.clear();
+
+    info!(
+        "Epoch {} rewards: inflation {:.4}, issued {} of {} ({} commission)",
+        epoch, inflation_rate, total, issuance, commission
+    );
+    EpochRewards { epoch, inflation_rate, total, commission }
+}
@@ -0,0 +1,115 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, KEYPAIR_LENGTH};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::warn;
+
+/// An ed25519 identity. The base58 encoded public key is the node's id.
+pub struct Keypair {
+    signing_key: SigningKey,
+}
+
+impl Keypair {
+    pub fn generate() -> Self {
+        Keypair::from_seed(rand::random())
+    }
+
+    /// The same seed always gives the same keypair.
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Keypair {
+            signing_key: SigningKey::from_bytes(&seed),
+        }
+    }
+
+    /// Secret key followed by public key, as in Solana keypair files.
+    pub fn to_bytes(&self) -> [u8; KEYPAIR_LENGTH] {
+        self.signing_key.to_keypair_bytes()
+    }
+
+    /// Rejects anything but 64 bytes whose second half is the public key
+    /// of the first.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let bytes: &[u8; KEYPAIR_LENGTH] = bytes.try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("keypair must be {} bytes, got {}", KEYPAIR_LENGTH, bytes.len()))
+        })?;
+        let signing_key = SigningKey::from_keypair_bytes(bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "public key does not match the secret key"))?;
+        Ok(Keypair { signing_key })
+    }
+
+    /// Reads a keypair file: a JSON array of the 64 keypair bytes, the
+    /// format `solana-keygen` writes.
+    pub fn read_from_file(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to read keypair {}: {}", path.display(), e)))?;
+        let bytes: Vec<u8> = serde_json::from_str(&text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid keypair {}: {}", path.display(), e)))?;
+        Self::from_bytes(&bytes).map_err(|e| io::Error::new(e.kind(), format!("invalid keypair {}: {}", path.display(), e)))
+    }
+
+    /// Writes a keypair file readable only by its owner. Fails rather than
+    /// overwrite an existing key.
+    pub fn write_to_file(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        io::Write::write_all(&mut file, serde_json::to_string(&self.to_bytes().to_vec()).unwrap().as_bytes())
+    }
+
+    pub fn pubkey(&self) -> String {
+        bs58::encode(self.pubkey_bytes()).into_string()
+    }
+
+    pub fn pubkey_bytes(&self) -> Vec<u8> {
+        self.signing_key.verifying_key().to_bytes().to_vec()
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.signing_key.sign(message).to_bytes().to_vec()
+    }
+}
+
+/// The node identity from its keypair file, or a throwaway one if none is
+/// configured.
+pub fn load_identity(path: Option<&Path>) -> io::Result<Keypair> {
+    match path {
+        Some(path) => Keypair::read_from_file(path),
+        None => {
+            warn!("No identity keypair configured, using a new one that won't survive a restart");
+            Ok(Keypair::generate())
+        }
+    }
+}
+
+/// What gets signed for a transaction or instruction: a tag naming its
+/// kind, then each field prefixed with its length so that one field can't
+/// be shifted into the next.
+pub fn signing_message(tag: &[u8], fields: &[&[u8]]) -> Vec<u8> {
+    let mut message = tag.to_vec();
+    for field in fields {
+        message.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        message.extend_from_slice(field);
+    }
+    message
+}
+
+/// Checks `signature` over `message` against a base58 encoded public key.
+pub fn verify(pubkey: &str, message: &[u8], signature: &[u8]) -> bool {
+    let key_bytes: [u8; 32] = match bs58::decode(pubkey).into_vec().ok().and_then(|bytes| bytes.try_into().ok()) {
+        Some(bytes) => bytes,
+        None => return false,
+    };
+    let signature = match Signature::from_slice(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    match VerifyingKey::from_bytes(&key_bytes) {
+        Ok(key) => key.verify(message, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
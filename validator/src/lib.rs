@@ -1,5 +1,26 @@
 pub mod block;
+pub mod blockstore;
+pub mod catchup;
+pub mod clock;
+pub mod config;
+pub mod connection;
+pub mod consensus;
+pub mod crds;
+pub mod faucet;
+pub mod genesis;
+pub mod gossip;
+pub mod handlers;
+pub mod inflation;
+pub mod keypair;
+pub mod logging;
+pub mod metrics;
 pub mod network;
 pub mod poh_handler;
 pub mod registration;
+pub mod repair;
+pub mod rpc_client;
+pub mod sim;
+pub mod slashing;
+pub mod snapshot;
+pub mod stake;
 pub mod transaction;
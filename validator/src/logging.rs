@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+/// Used when neither `--log` nor `RUST_LOG` is set.
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {} (expected text or json)", format)),
+        }
+    }
+}
+
+/// Installs the global subscriber. `filter` uses `RUST_LOG` syntax, a
+/// default level plus per-module overrides such as
+/// `info,validator::gossip=debug,leader_node::network=trace`.
+pub fn init(filter: Option<&str>, format: LogFormat) -> Result<(), String> {
+    let filter = match filter {
+        Some(filter) => filter.to_string(),
+        None => std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_string()),
+    };
+    let filter = EnvFilter::try_new(&filter).map_err(|e| format!("invalid log filter {}: {}", filter, e))?;
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match format {
+        LogFormat::Text => subscriber.try_init(),
+        // One object per line, with the fields of every enclosing span
+        LogFormat::Json => subscriber.json().with_current_span(true).with_span_list(true).try_init(),
+    };
+    result.map_err(|e| e.to_string())
+}
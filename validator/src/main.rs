@@ -1,143 +1,552 @@
-use tokio::net::TcpStream;
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use serde::{Serialize, Deserialize};
-use rand::{Rng, SeedableRng, rngs::StdRng};
-use rand::seq::IteratorRandom;
-use validator::poh_handler::{PohEntry, validate_poh_entries};
-use validator::transaction::{Transaction, create_transaction};
-use bs58;
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Block {
-    poh_entries: Vec<PohEntry>,
-    block_hash: Vec<u8>,
-    transactions: Vec<Transaction>,
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::io;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{Duration, Instant};
+use rand::seq::SliceRandom;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use validator::block::{Block, Message};
+use validator::blockstore::Blockstore;
+use validator::catchup::{MAX_BLOCKS_PER_REQUEST, REPAIR_INTERVAL_MS};
+use validator::clock::{Clock, SystemClock};
+use validator::consensus::Replayed;
+use validator::handlers::ValidatorChain;
+use validator::repair::{RepairRequest, RepairResponse, RepairService, ShredCollector, SignedRepairRequest};
+use validator::crds::{self, ClusterInfo, ContactInfo};
+use validator::keypair::{load_identity, Keypair};
+use validator::logging;
+use validator::metrics::{metrics, serve_metrics};
+use validator::gossip::{GossipActivity, PushGossip};
+use validator::connection::ConnectionManager;
+use validator::network::{read_message, run_gossip_listener, write_message, Inbound, Outbox};
+use validator::poh_handler::PohHistory;
+use validator::registration::Validator;
+use validator::snapshot::{Snapshot, SnapshotArchive};
+use validator::genesis::GenesisConfig;
+use validator::transaction::{Transaction, MAX_PENDING_TRANSACTIONS};
+use validator::config::{to_toml, ValidatorArgs};
+use clap::Parser;
+use std::net::SocketAddr;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+const INITIAL_RECONNECT_BACKOFF_MS: u64 = 500;
+const MAX_RECONNECT_BACKOFF_MS: u64 = 30_000;
+
+/// Connects and registers with the leader. The spawned reader forwards the
+/// leader's messages tagged with `session`, then `None` once it disconnects.
+async fn connect_to_leader(
+    leader_addr: SocketAddr,
+    register: &Message,
+    session: u64,
+    leader_tx: mpsc::Sender<(u64, Option<Message>)>,
+) -> io::Result<OwnedWriteHalf> {
+    let (mut reader, mut writer) = TcpStream::connect(leader_addr).await?.into_split();
+    write_message(&mut writer, register).await?;
+
+    tokio::spawn(async move {
+        loop {
+            match read_message::<_, Message>(&mut reader).await {
+                Ok(message) => {
+                    if leader_tx.send((session, Some(message))).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    metrics().frame_decode_errors.inc();
+                    warn!("Failed to parse message from leader: {}", e);
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = leader_tx.send((session, None)).await;
+    });
+    Ok(writer)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Validator {
-    id: String,
-    public_key: Vec<u8>,
+/// Writes to the leader, dropping the connection on failure so the main loop reconnects.
+async fn send_to_leader(leader: &mut Option<OwnedWriteHalf>, message: &Message) {
+    if let Some(stream) = leader.as_mut() {
+        if let Err(e) = write_message(stream, message).await {
+            warn!("Failed to send to leader: {}", e);
+            *leader = None;
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-enum Message {
-    PoHEntries(Vec<PohEntry>),
-    RetransmissionRequest(usize),
-    BlockProposal(Block),
-    ConsensusVote(Block),
-    RegisterValidator(Validator),
-    Transaction(Transaction),
-    GossipMessage(String), 
+/// Forgets pending transactions that made it into `blocks`.
+fn drop_included(transactions: &mut VecDeque<Transaction>, blocks: &[Block]) {
+    let included: HashSet<&[u8]> = blocks
+        .iter()
+        .flat_map(|block| &block.transactions)
+        .map(|transaction| transaction.signature.as_slice())
+        .collect();
+    transactions.retain(|transaction| !included.contains(transaction.signature.as_slice()));
+    metrics().mempool_size.set(transactions.len() as i64);
 }
 
-fn generate_sol_address() -> Vec<u8> {
-    let mut rng = rand::thread_rng();
-    let mut address = [0u8; 32];
-    rng.fill(&mut address);
-    address.to_vec()
+/// Subscribes to the leader's PoH stream from the end of our history.
+fn poh_subscription(poh: &PohHistory) -> Message {
+    Message::PohSubscribe(poh.len(), poh.last_hash())
 }
 
-async fn gossip_message(message: &Message, peer_addrs: &Vec<String>) {
-    let serialized_message = serde_json::to_string(&message).unwrap();
-    let message_length = (serialized_message.len() as u32).to_be_bytes();
-    let mut rng = StdRng::from_entropy();
+/// Starts our chain and PoH history at a verified snapshot, so only the
+/// blocks after it need repairing.
+fn apply_snapshot(snapshot: &Snapshot, blockstore: &mut Blockstore, poh: &mut PohHistory) -> io::Result<()> {
+    blockstore.set_base(snapshot.slot, snapshot.block_hash.clone());
+    if poh.len() < snapshot.poh.height {
+        poh.reset_to(Some(snapshot.poh.clone()))?;
+    }
+    info!(
+        "Starting from snapshot at slot {}: {} accounts, {} staked",
+        snapshot.slot,
+        snapshot.balances.len(),
+        snapshot.stakes.total_staked()
+    );
+    Ok(())
+}
 
-    for addr in peer_addrs.iter().choose_multiple(&mut rng, peer_addrs.len() / 2) {
-        if let Ok(mut stream) = TcpStream::connect(addr).await {
-            if let Err(_) = stream.write_all(&message_length).await {
-                continue;
-            }
-            if let Err(_) = stream.write_all(serialized_message.as_bytes()).await {
-                continue;
+/// Sends a repair request to the leader, or to a random peer when the
+/// leader is unreachable or `prefer_peer` is set because it did not answer.
+async fn request_repair(
+    identity: &Keypair,
+    leader: &mut Option<OwnedWriteHalf>,
+    leader_identity: Option<&String>,
+    cluster: &ClusterInfo,
+    connections: &ConnectionManager,
+    request: RepairRequest,
+    prefer_peer: bool,
+) {
+    let repair = SignedRepairRequest::new_signed(identity, request, SystemClock.now_ms());
+    send_repair(leader, leader_identity, cluster, connections, repair, prefer_peer).await;
+}
+
+/// Routes an already signed repair request like `request_repair`.
+async fn send_repair(
+    leader: &mut Option<OwnedWriteHalf>,
+    leader_identity: Option<&String>,
+    cluster: &ClusterInfo,
+    connections: &ConnectionManager,
+    repair: SignedRepairRequest,
+    prefer_peer: bool,
+) {
+    let repair = Message::Repair(repair);
+    if leader.is_some() && !prefer_peer {
+        send_to_leader(leader, &repair).await;
+        return;
+    }
+    let peers: Vec<ContactInfo> = cluster
+        .peers()
+        .await
+        .into_iter()
+        .filter(|peer| Some(&peer.identity) != leader_identity)
+        .collect();
+    match peers.choose(&mut rand::thread_rng()) {
+        Some(peer) => {
+            connections.send(&peer.identity, &peer.tvu_addr, repair).await;
+        }
+        None => send_to_leader(leader, &repair).await,
+    }
+}
+
+/// Delivers what a `ValidatorChain` handler sent, all of it addressed to
+/// the leader. Repairs are routed like any other, and evidence is gossiped
+/// as well so every node learns of the offense.
+async fn send_outbox(
+    outbox: &mut Outbox,
+    leader: &mut Option<OwnedWriteHalf>,
+    leader_identity: Option<&String>,
+    cluster: &ClusterInfo,
+    connections: &ConnectionManager,
+    gossip: &PushGossip,
+) {
+    for (_, message) in outbox.drain() {
+        match message {
+            Message::Repair(repair) => send_repair(leader, leader_identity, cluster, connections, repair, false).await,
+            Message::Evidence(_) => {
+                gossip.originate(&message, &cluster.peers().await).await;
+                send_to_leader(leader, &message).await;
             }
-            println!("Gossiped message to {}", addr);
+            message => send_to_leader(leader, &message).await,
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let mut rng = rand::thread_rng();
-    let validator_id = format!("validator_{}", rng.gen::<u32>());
-    let public_key = generate_sol_address();
-    let mut stream = TcpStream::connect("127.0.0.1:8080").await?;
-    let peer_addrs = vec!["127.0.0.1:8081".to_string(), "127.0.0.1:8082".to_string()]; 
+    let args = ValidatorArgs::parse();
+    let config = match args.resolve() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if args.print_config {
+        print!("{}", to_toml(&config));
+        return Ok(());
+    }
+    logging::init(config.log.as_deref(), config.log_format).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let genesis_path = &config.genesis;
+    let genesis = GenesisConfig::load(genesis_path)?;
+    let genesis_hash = bs58::encode(genesis.hash()).into_string();
+    let ledger_dir = config.ledger.clone();
+    let (mut blockstore, mut poh) = match &ledger_dir {
+        Some(ledger_dir) => (Blockstore::open(ledger_dir)?, PohHistory::open(ledger_dir, genesis.hash())?),
+        None => (Blockstore::in_memory(), PohHistory::new(genesis.hash())),
+    };
+    // A node that bootstrapped from a snapshot keeps starting from it
+    if let Some(archive) = ledger_dir.as_deref().map(SnapshotArchive::latest).transpose()?.flatten() {
+        apply_snapshot(&archive.unpack()?, &mut blockstore, &mut poh)?;
+    }
+    let mut chain = ValidatorChain::new(blockstore, genesis.hash());
+    info!("Loaded genesis {} from {}", genesis_hash, genesis_path.display());
 
+    let gossip_addr = config.gossip_addr.to_string();
+    let entrypoint = config.entrypoint.to_string();
+    let identity = match load_identity(config.identity.as_deref()) {
+        Ok(identity) => Arc::new(identity),
+        Err(e) => {
+            error!("Failed to load identity keypair: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let public_key = identity.pubkey_bytes();
+
+    // A single listener serves gossip, so it is also our tpu/tvu address
+    let cluster = Arc::new(ClusterInfo::new(
+        Arc::clone(&identity),
+        ContactInfo {
+            identity: identity.pubkey(),
+            gossip_addr: gossip_addr.clone(),
+            tpu_addr: gossip_addr.clone(),
+            tvu_addr: gossip_addr.clone(),
+            shred_version: crds::shred_version(&genesis.hash()),
+            wallclock: crds::wallclock(),
+        },
+    ));
+    let connections = Arc::new(ConnectionManager::new());
+    let gossip = Arc::new(PushGossip::new(
+        Arc::clone(&identity),
+        config.gossip_fanout,
+        Arc::clone(&connections),
+        Arc::new(Mutex::new(GossipActivity::default())),
+    ));
+
+    let listener = TcpListener::bind(&gossip_addr).await?;
+    info!("Gossip listener running on {}, entrypoint {}", gossip_addr, entrypoint);
+    let (gossip_tx, mut gossip_rx) = mpsc::channel(1024);
+    tokio::spawn(run_gossip_listener(listener, Arc::clone(&cluster), Arc::clone(&gossip), gossip_tx));
+    tokio::spawn(Arc::clone(&cluster).run_gossip(Some(entrypoint)));
+    if let Some(metrics_addr) = config.metrics_addr {
+        let listener = TcpListener::bind(metrics_addr).await?;
+        info!("Serving metrics on http://{}/metrics", metrics_addr);
+        tokio::spawn(serve_metrics(listener));
+    }
+
+    let validator_id = identity.pubkey();
     let register_message = Message::RegisterValidator(Validator {
         id: validator_id.clone(),
         public_key: public_key.clone(),
+        genesis_hash: genesis_hash.clone(),
     });
-    let serialized_register = serde_json::to_string(&register_message).unwrap();
-    stream.write_all(&(serialized_register.len() as u32).to_be_bytes()).await?;
-    stream.write_all(serialized_register.as_bytes()).await?;
-    println!("Registered validator with ID {}", validator_id);
-
-    let mut transactions = Vec::new();
-
-    // Create a sample transaction and send it to the leader node
-    let sample_transaction = create_transaction(
-        validator_id.clone(),
-        "recipient_1".to_string(),
-        100,
-        public_key.clone(),
-    );
-    let transaction_message = Message::Transaction(sample_transaction.clone());
-    let serialized_transaction = serde_json::to_string(&transaction_message).unwrap();
-    stream.write_all(&(serialized_transaction.len() as u32).to_be_bytes()).await?;
-    stream.write_all(serialized_transaction.as_bytes()).await?;
-    println!("Sent transaction to leader");
 
-    loop {
-        let mut length_buffer = [0; 4];
-        if stream.read_exact(&mut length_buffer).await.is_err() {
-            break;
-        }
+    let mut transactions = VecDeque::new();
+    let mut repair_service = RepairService::default();
+    let mut shreds = ShredCollector::default();
+    let mut repair_tick = tokio::time::interval(Duration::from_millis(REPAIR_INTERVAL_MS));
 
-        let message_length = u32::from_be_bytes(length_buffer) as usize;
-        let mut buffer = vec![0; message_length];
-        if stream.read_exact(&mut buffer).await.is_err() {
-            break;
-        }
+    let (leader_tx, mut leader_rx) = mpsc::channel(1024);
+    let mut leader: Option<OwnedWriteHalf> = None;
+    let mut leader_identity: Option<String> = None;
+    let mut session = 0;
+    let mut reconnect_at = Instant::now();
+    let mut backoff_ms = INITIAL_RECONNECT_BACKOFF_MS;
+    let mut awaiting_snapshot = false;
+
+    loop {
+        // Only our own connection to the leader is trusted: it alone may move
+        // our chain tip, PoH history or leader identity, and what arrives on
+        // it is gossiped on. Anyone can dial the gossip listener, so of the
+        // messages sent straight to us only blocks signed by our leader are
+        // gossiped on; gossip itself was already forwarded by the listener.
+        let (message, from_leader, direct) = tokio::select! {
+            Some((message_session, message)) = leader_rx.recv() => match message {
+                Some(message) if message_session == session => (message, true, true),
+                Some(_) => continue,
+                None if message_session == session && leader.is_some() => {
+                    warn!("Lost connection to leader, reconnecting");
+                    leader = None;
+                    awaiting_snapshot = false;
+                    reconnect_at = Instant::now();
+                    continue;
+                }
+                None => continue,
+            },
+            Some(inbound) = gossip_rx.recv() => match inbound {
+                Inbound::Direct(message) => (message, false, true),
+                Inbound::Gossip(message) => (message, false, false),
+            },
+            _ = tokio::time::sleep_until(reconnect_at), if leader.is_none() => {
+                session += 1;
+                match connect_to_leader(config.leader_addr, &register_message, session, leader_tx.clone()).await {
+                    Ok(stream) => {
+                        info!("Registered validator with ID {}", validator_id);
+                        leader = Some(stream);
+                        backoff_ms = INITIAL_RECONNECT_BACKOFF_MS;
+                    }
+                    Err(e) => {
+                        warn!("Failed to connect to leader at {}: {}, retrying in {} ms", config.leader_addr, e, backoff_ms);
+                        reconnect_at = Instant::now() + Duration::from_millis(backoff_ms);
+                        backoff_ms = (backoff_ms * 2).min(MAX_RECONNECT_BACKOFF_MS);
+                    }
+                }
+                continue;
+            }
+            _ = repair_tick.tick() => {
+                // Retry unanswered repairs through peers. While the leader is
+                // down we don't know its height, so ask for whatever follows ours.
+                let block_repair = match chain.catch_up.next_block_repair(&chain.blockstore) {
+                    _ if awaiting_snapshot => None,
+                    Some(request) => Some(request),
+                    None if leader.is_none() => {
+                        let start = chain.blockstore.height() + 1;
+                        Some(RepairRequest::Blocks { start, end: start + MAX_BLOCKS_PER_REQUEST - 1 })
+                    }
+                    None => None,
+                };
+                // PoH arrives through the leader's stream while we are connected
+                let poh_repair = chain.catch_up.next_poh_repair(&poh).filter(|_| leader.is_none());
+                let repairs = block_repair.into_iter().chain(poh_repair);
+                for request in repairs {
+                    request_repair(&identity, &mut leader, leader_identity.as_ref(), &cluster, &connections, request, true).await;
+                }
+                continue;
+            }
+        };
 
-        match serde_json::from_slice::<Message>(&buffer) {
-            Ok(Message::PoHEntries(poh_entries)) => {
-                match validate_poh_entries(&poh_entries) {
-                    Ok(_) => println!("Valid PoH entries received"),
-                    Err(index) => {
-                        println!("Invalid PoH entry at index {}, requesting retransmission", index);
-                        let request = Message::RetransmissionRequest(index);
-                        let serialized_request = serde_json::to_string(&request).unwrap();
-                        stream.write_all(&(serialized_request.len() as u32).to_be_bytes()).await?;
-                        stream.write_all(serialized_request.as_bytes()).await?;
+        match message {
+            Message::ChainTip(tip) if from_leader => {
+                info!("Leader {} is at block height {} with {} PoH entries", tip.leader, tip.block_height, tip.poh_entries);
+                leader_identity = Some(tip.leader.clone());
+                if tip.block_height < chain.blockstore.height() {
+                    warn!("Leader is behind our ledger at height {}, waiting for it", chain.blockstore.height());
+                }
+                chain.catch_up.target_height = chain.catch_up.target_height.max(tip.block_height);
+                chain.catch_up.set_poh_target(tip.poh_entries, tip.poh_hash.clone());
+                // A fresh node starts from the leader's snapshot rather than replaying from genesis
+                if chain.blockstore.is_empty() && chain.blockstore.base().is_none() && tip.block_height > 0 {
+                    info!("Requesting a snapshot from the leader");
+                    awaiting_snapshot = true;
+                    send_to_leader(&mut leader, &Message::SnapshotRequest).await;
+                } else if let Some(request) = chain.catch_up.next_block_repair(&chain.blockstore) {
+                    info!("Catching up to block height {}", chain.catch_up.target_height);
+                    request_repair(&identity, &mut leader, leader_identity.as_ref(), &cluster, &connections, request, false).await;
+                }
+                send_to_leader(&mut leader, &poh_subscription(&poh)).await;
+            },
+            Message::ChainTip(_) => {},
+            Message::Snapshot(archive) if from_leader && awaiting_snapshot => {
+                awaiting_snapshot = false;
+                match archive.map(|archive| (archive.unpack(), archive)) {
+                    Some((Ok(snapshot), archive)) if snapshot.genesis_hash == genesis_hash && snapshot.slot > chain.blockstore.height() => {
+                        apply_snapshot(&snapshot, &mut chain.blockstore, &mut poh)?;
+                        if let Some(ledger_dir) = &ledger_dir {
+                            archive.save(ledger_dir)?;
+                        }
+                        send_to_leader(&mut leader, &poh_subscription(&poh)).await;
                     }
+                    Some((Ok(snapshot), _)) if snapshot.genesis_hash != genesis_hash => {
+                        warn!("Rejecting snapshot from genesis {}", snapshot.genesis_hash);
+                    }
+                    Some((Ok(snapshot), _)) => info!("Ignoring snapshot at slot {} that doesn't advance our chain", snapshot.slot),
+                    Some((Err(e), _)) => warn!("Rejecting snapshot from leader: {}", e),
+                    None => info!("Leader has no snapshot yet, replaying from genesis"),
+                }
+                if let Some(request) = chain.catch_up.next_block_repair(&chain.blockstore) {
+                    info!("Catching up to block height {}", chain.catch_up.target_height);
+                    request_repair(&identity, &mut leader, leader_identity.as_ref(), &cluster, &connections, request, false).await;
                 }
             },
-            Ok(Message::BlockProposal(block)) => {
-                println!("Received block proposal");
-                gossip_message(&Message::BlockProposal(block.clone()), &peer_addrs).await;
-                let vote = Message::ConsensusVote(block.clone());
-                let serialized_vote = serde_json::to_string(&vote).unwrap();
-                stream.write_all(&(serialized_vote.len() as u32).to_be_bytes()).await?;
-                stream.write_all(serialized_vote.as_bytes()).await?;
-                println!("Sent consensus vote");
+            Message::Snapshot(_) => {},
+            // The leader moves us to one of its checkpoints when our history
+            // doesn't match its own or it no longer holds the entries we need
+            Message::PohCheckpoint(checkpoint) if from_leader => {
+                if checkpoint.height == poh.len() && checkpoint.hash == poh.last_hash() {
+                    continue;
+                }
+                warn!("Restarting PoH history from the leader's checkpoint at {}", checkpoint.height);
+                if let Err(e) = poh.reset_to(Some(checkpoint)) {
+                    error!("Failed to store PoH checkpoint: {}", e);
+                }
             },
-            Ok(Message::ConsensusVote(block)) => {
-                println!("Received consensus vote");
-                gossip_message(&Message::ConsensusVote(block), &peer_addrs).await;
+            Message::PohCheckpoint(_) => {},
+            Message::PohBatch(start, entries) if from_leader => {
+                if start > poh.len() {
+                    send_to_leader(&mut leader, &poh_subscription(&poh)).await;
+                    continue;
+                }
+                match poh.append_verified(start, entries) {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(count)) => {
+                        debug!("Verified {} PoH entries, history at {}", count, poh.len());
+                        if poh.len() > chain.catch_up.target_poh_entries() {
+                            chain.catch_up.set_poh_target(poh.len(), poh.last_hash());
+                        }
+                    }
+                    Ok(Err(index)) => {
+                        warn!("PoH entry {} does not extend our history, resyncing", index);
+                        send_to_leader(&mut leader, &poh_subscription(&poh)).await;
+                    }
+                    Err(e) => error!("Failed to store PoH entries: {}", e),
+                }
             },
-            Ok(Message::Transaction(transaction)) => {
-                println!("Received transaction: {:?}", transaction);
+            Message::PohBatch(..) => {},
+            Message::Repair(repair) if repair.verify(SystemClock.now_ms()) => {
+                let response = match repair_service.serve(&SystemClock, &repair.requester, &repair.request, &chain.blockstore, &poh) {
+                    Some(response) => response,
+                    None => {
+                        debug!("Dropping repair request from {}", repair.requester);
+                        continue;
+                    }
+                };
+                if let Some(peer) = cluster.lookup(&repair.requester).await {
+                    connections.send(&repair.requester, &peer.tvu_addr, Message::RepairResponse(response)).await;
+                }
+            },
+            Message::Repair(repair) => debug!("Dropping unsigned or stale repair request from {}", repair.requester),
+            Message::RepairResponse(RepairResponse::Poh { start, entries }) => {
+                let entries = match chain.catch_up.take_poh_repair(&poh, start, entries) {
+                    Some(entries) => entries,
+                    None => {
+                        warn!("Repaired PoH entries from {} don't lead to the leader's history, dropping them", start);
+                        continue;
+                    }
+                };
+                if !entries.is_empty() {
+                    match poh.append_verified(poh.len(), entries) {
+                        Ok(Ok(count)) => info!("Repaired {} PoH entries, history at {}", count, poh.len()),
+                        Ok(Err(index)) => warn!("Repaired PoH entry {} does not extend our history, dropping it", index),
+                        Err(e) => error!("Failed to store repaired PoH entries: {}", e),
+                    }
+                }
+                if let Some(request) = chain.catch_up.next_poh_repair(&poh) {
+                    request_repair(&identity, &mut leader, leader_identity.as_ref(), &cluster, &connections, request, false).await;
+                }
+            },
+            Message::RepairResponse(response) => {
+                let blocks = match response {
+                    RepairResponse::Blocks(blocks) => blocks,
+                    RepairResponse::Shred(shred) => {
+                        let (block_height, first) = (shred.block_height, shred.index == 0);
+                        match shreds.insert(shred) {
+                            Ok(block) => vec![block],
+                            Err(missing) => {
+                                // The first shred answers a block request; ask for the rest once
+                                if first {
+                                    for index in missing {
+                                        let request = RepairRequest::Shred { block_height, index };
+                                        request_repair(&identity, &mut leader, leader_identity.as_ref(), &cluster, &connections, request, false).await;
+                                    }
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    RepairResponse::Poh { .. } => continue,
+                };
+                // Repaired blocks are only kept if our leader signed them and
+                // they verify against our chain
+                let Some(leader_id) = leader_identity.as_deref() else {
+                    debug!("Dropping repaired blocks until the leader tells us who it is");
+                    continue;
+                };
+                let previous_height = chain.blockstore.height();
+                let mut outbox = Outbox::default();
+                match chain.handle_repaired_blocks(&SystemClock, &mut outbox, &identity, leader_id, blocks) {
+                    Ok(0) => {}
+                    Ok(applied) => {
+                        info!("Repaired {} blocks, now at height {}", applied, chain.blockstore.height());
+                        drop_included(&mut transactions, &chain.blockstore.range(previous_height + 1, chain.blockstore.height(), usize::MAX));
+                        shreds.purge_below(chain.blockstore.height() + 1);
+                        if chain.catch_up.is_caught_up(&chain.blockstore) {
+                            info!("Caught up at block height {}, resuming voting", chain.blockstore.height());
+                        }
+                        send_outbox(&mut outbox, &mut leader, leader_identity.as_ref(), &cluster, &connections, &gossip).await;
+                    }
+                    Err(e) => error!("Failed to store blocks: {}", e),
+                }
+            },
+            Message::BlockProposal(block) => {
+                let span = info_span!("block", height = block.block_height, leader = %block.leader);
+                async {
+                    debug!("Received block proposal");
+                    if leader_identity.as_ref() != Some(&block.leader) {
+                        debug!("Ignoring block {} from {}, which is not our leader", block.block_height, block.leader);
+                        return;
+                    }
+                    let mut outbox = Outbox::default();
+                    let replayed = chain.handle_proposal(&SystemClock, &mut outbox, &identity, &block.leader, &block);
+                    match &replayed {
+                        Replayed::InvalidSignature => {
+                            warn!("Ignoring block {} with an invalid leader signature", block.block_height);
+                            return;
+                        }
+                        Replayed::Equivocation(offense) => {
+                            warn!("Leader {} produced two blocks for slot {}, reporting evidence", offense.offender(), offense.slot());
+                            send_outbox(&mut outbox, &mut leader, leader_identity.as_ref(), &cluster, &connections, &gossip).await;
+                            return;
+                        }
+                        _ => {}
+                    }
+                    for evidence in &block.evidence {
+                        info!("Block {} slashes {} for an offense at slot {}", block.block_height, evidence.offense.offender(), evidence.offense.slot());
+                    }
+                    if direct {
+                        gossip.originate(&Message::BlockProposal(block.clone()), &cluster.peers().await).await;
+                    }
+                    match replayed {
+                        Replayed::Gap { repair: Some(_) } => info!("Missing blocks below {}, repairing", block.block_height),
+                        Replayed::Stored => drop_included(&mut transactions, std::slice::from_ref(&block)),
+                        _ => {}
+                    }
+                    send_outbox(&mut outbox, &mut leader, leader_identity.as_ref(), &cluster, &connections, &gossip).await;
+                    if matches!(replayed, Replayed::Stored) {
+                        debug!("Sent consensus vote");
+                    }
+                }
+                .instrument(span)
+                .await;
+            },
+            Message::ConsensusVote(vote) => {
+                debug!("Received consensus vote");
+                if from_leader {
+                    gossip.originate(&Message::ConsensusVote(vote), &cluster.peers().await).await;
+                }
+            },
+            Message::Evidence(evidence) if evidence.verify() => {
+                info!("Received evidence against {}", evidence.offense.offender());
+                if from_leader {
+                    gossip.originate(&Message::Evidence(evidence), &cluster.peers().await).await;
+                }
+            },
+            Message::Transaction(transaction) => {
+                debug!("Received transaction: {:?}", transaction);
                 if transaction.validate() {
-                    transactions.push(transaction.clone());
-                    gossip_message(&Message::Transaction(transaction), &peer_addrs).await;
+                    // Only blocks drain the queue, so a leader that stops producing them can't grow it forever
+                    if transactions.len() >= MAX_PENDING_TRANSACTIONS {
+                        transactions.pop_front();
+                    }
+                    transactions.push_back(transaction.clone());
+                    metrics().mempool_size.set(transactions.len() as i64);
+                    if from_leader {
+                        gossip.originate(&Message::Transaction(transaction), &cluster.peers().await).await;
+                    }
                 } else {
-                    println!("Invalid transaction received");
+                    warn!("Invalid transaction received");
                 }
             },
             _ => {},
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,151 @@
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// Requests whose headers don't fit in this many bytes are answered with 431.
+pub const MAX_REQUEST_HEAD_BYTES: usize = 8 * 1024;
+/// Connections that don't finish sending their headers in time are dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters and gauges shared by the leader and validators. Each binary
+/// only moves the ones that apply to it; the rest stay at zero.
+pub struct Metrics {
+    registry: Registry,
+    /// SHA-256 hashes computed for PoH, generated or verified.
+    pub poh_hashes: IntCounter,
+    /// Entries appended to this node's PoH history.
+    pub poh_ticks: IntCounter,
+    pub blocks_produced: IntCounter,
+    pub blocks_confirmed: IntCounter,
+    pub votes_received: IntCounterVec,
+    pub mempool_size: IntGauge,
+    pub gossip_sent: IntCounter,
+    pub gossip_received: IntCounter,
+    pub gossip_duplicates: IntCounter,
+    /// Open peer connections, labelled inbound or outbound.
+    pub connections: IntGaugeVec,
+    pub frame_decode_errors: IntCounter,
+    pub transaction_execution_seconds: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let metrics = Metrics {
+            poh_hashes: IntCounter::new("poh_hashes_total", "PoH hashes computed").unwrap(),
+            poh_ticks: IntCounter::new("poh_ticks_total", "PoH entries appended to the history").unwrap(),
+            blocks_produced: IntCounter::new("blocks_produced_total", "Blocks produced by this node").unwrap(),
+            blocks_confirmed: IntCounter::new("blocks_confirmed_total", "Blocks confirmed by a supermajority of stake")
+                .unwrap(),
+            votes_received: IntCounterVec::new(
+                Opts::new("votes_received_total", "Consensus votes received, by validator"),
+                &["validator"],
+            )
+            .unwrap(),
+            mempool_size: IntGauge::new("mempool_size", "Transactions waiting to be included in a block").unwrap(),
+            gossip_sent: IntCounter::new("gossip_messages_sent_total", "Gossip messages pushed to peers").unwrap(),
+            gossip_received: IntCounter::new("gossip_messages_received_total", "New gossip messages received").unwrap(),
+            gossip_duplicates: IntCounter::new("gossip_duplicates_total", "Gossip messages received more than once")
+                .unwrap(),
+            connections: IntGaugeVec::new(Opts::new("connections", "Open peer connections"), &["direction"]).unwrap(),
+            frame_decode_errors: IntCounter::new("frame_decode_errors_total", "Frames that failed to deserialize")
+                .unwrap(),
+            // Executing a transfer takes microseconds, far below the default buckets
+            transaction_execution_seconds: Histogram::with_opts(
+                HistogramOpts::new("transaction_execution_seconds", "Time to execute one transaction")
+                    .buckets(exponential_buckets(1e-6, 4.0, 10).unwrap()),
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.poh_hashes.clone())).unwrap();
+        registry.register(Box::new(metrics.poh_ticks.clone())).unwrap();
+        registry.register(Box::new(metrics.blocks_produced.clone())).unwrap();
+        registry.register(Box::new(metrics.blocks_confirmed.clone())).unwrap();
+        registry.register(Box::new(metrics.votes_received.clone())).unwrap();
+        registry.register(Box::new(metrics.mempool_size.clone())).unwrap();
+        registry.register(Box::new(metrics.gossip_sent.clone())).unwrap();
+        registry.register(Box::new(metrics.gossip_received.clone())).unwrap();
+        registry.register(Box::new(metrics.gossip_duplicates.clone())).unwrap();
+        registry.register(Box::new(metrics.connections.clone())).unwrap();
+        registry.register(Box::new(metrics.frame_decode_errors.clone())).unwrap();
+        registry.register(Box::new(metrics.transaction_execution_seconds.clone())).unwrap();
+        metrics
+    }
+
+    /// Every metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Reads up to the blank line ending the request head. Returns None if the
+/// peer hangs up first, or the head so far if it passes `MAX_REQUEST_HEAD_BYTES`.
+async fn read_request_head(reader: &mut (impl AsyncRead + Unpin)) -> Option<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD_BYTES {
+            break;
+        }
+        let read = reader.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    Some(head)
+}
+
+/// The response to a request head.
+fn metrics_response(head: &[u8]) -> String {
+    if head.len() > MAX_REQUEST_HEAD_BYTES {
+        "HTTP/1.1 431 Request Header Fields Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    } else if head.starts_with(b"GET /metrics ") {
+        let body = metrics().encode();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    }
+}
+
+/// Serves `GET /metrics` for Prometheus to scrape. Nodes without a web
+/// server of their own use this; anything else gets a 404.
+pub async fn serve_metrics(listener: TcpListener) {
+    loop {
+        let (mut socket, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept metrics connection: {}", e);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let head = match tokio::time::timeout(REQUEST_TIMEOUT, read_request_head(&mut socket)).await {
+                Ok(Some(head)) => head,
+                _ => return,
+            };
+            let _ = socket.write_all(metrics_response(&head).as_bytes()).await;
+        });
+    }
+}
@@ -1,24 +1,142 @@
-use tokio::net::TcpStream;
-use tokio::io::AsyncWriteExt;
-use rand::seq::IteratorRandom;
-use rand::rngs::StdRng;
-use rand::SeedableRng;
+use tokio::net::TcpListener;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use std::sync::Arc;
 use crate::block::Message;
+use crate::crds::ClusterInfo;
+use crate::gossip::PushGossip;
+use crate::metrics::metrics;
+use tracing::{info_span, warn, Instrument};
 
-pub async fn gossip_message(message: &Message, peer_addrs: &Vec<String>) {
-    let serialized_message = serde_json::to_string(&message).unwrap();
-    let message_length = (serialized_message.len() as u32).to_be_bytes();
-    let mut rng = StdRng::from_entropy();
+/// How a message reached us: relayed through gossip, or sent straight to us
+/// over a peer's connection.
+#[derive(Debug)]
+pub enum Inbound {
+    Gossip(Message),
+    Direct(Message),
+}
+
+/// Where node logic sends messages, addressed by peer identity. The
+/// simulator's implementation delays, drops and reorders them; the nodes
+/// collect them in an `Outbox` and write them to their connections.
+pub trait Network {
+    fn send(&mut self, from: &str, to: &str, message: Message);
+}
+
+/// Messages a handler sent, in order, for a node to deliver once the
+/// handler returns and no state is borrowed.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    messages: Vec<(String, Message)>,
+}
+
+impl Outbox {
+    /// The messages and who they are addressed to.
+    pub fn drain(&mut self) -> std::vec::Drain<'_, (String, Message)> {
+        self.messages.drain(..)
+    }
+}
+
+impl Network for Outbox {
+    fn send(&mut self, _from: &str, to: &str, message: Message) {
+        self.messages.push((to.to_string(), message));
+    }
+}
+
+/// Frames announcing a larger length are refused before anything is
+/// allocated. Snapshot archives are the largest messages and fit well within.
+pub const MAX_FRAME_BYTES: usize = 64 * 1024 * 1024;
+
+/// Writes one frame: a big-endian length, then the message as JSON.
+pub async fn write_message<W: AsyncWrite + Unpin, T: Serialize>(stream: &mut W, message: &T) -> std::io::Result<()> {
+    let serialized_message = serde_json::to_string(message).unwrap();
+    stream.write_all(&(serialized_message.len() as u32).to_be_bytes()).await?;
+    stream.write_all(serialized_message.as_bytes()).await
+}
+
+/// Reads one frame written by `write_message`. A frame that doesn't decode
+/// is an `InvalidData` error that leaves the stream at the next frame, so
+/// callers may skip it; any other error means the stream is unusable.
+pub async fn read_message<R: AsyncRead + Unpin, T: DeserializeOwned>(stream: &mut R) -> std::io::Result<T> {
+    let mut length_buffer = [0; 4];
+    stream.read_exact(&mut length_buffer).await?;
+    let length = u32::from_be_bytes(length_buffer) as usize;
+    if length > MAX_FRAME_BYTES {
+        return Err(std::io::Error::other(format!("frame of {} bytes exceeds {}", length, MAX_FRAME_BYTES)));
+    }
+    let mut buffer = vec![0; length];
+    stream.read_exact(&mut buffer).await?;
+    serde_json::from_slice(&buffer).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
 
-    for addr in peer_addrs.iter().choose_multiple(&mut rng, peer_addrs.len() / 2) {
-        if let Ok(mut stream) = TcpStream::connect(addr).await {
-            if let Err(_) = stream.write_all(&message_length).await {
+/// Accepts peer connections, which stay open and carry every message type.
+/// Gossip frames go through the push engine, which delivers each new payload
+/// once; prunes, cluster info pushes and pull requests are answered here and
+/// anything else is delivered as a direct message.
+pub async fn run_gossip_listener(
+    listener: TcpListener,
+    cluster: Arc<ClusterInfo>,
+    gossip: Arc<PushGossip>,
+    inbound: mpsc::Sender<Inbound>,
+) {
+    loop {
+        let (mut socket, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept gossip connection: {}", e);
                 continue;
             }
-            if let Err(_) = stream.write_all(serialized_message.as_bytes()).await {
-                continue;
+        };
+        let cluster = Arc::clone(&cluster);
+        let gossip = Arc::clone(&gossip);
+        let inbound = inbound.clone();
+
+        tokio::spawn(async move {
+            let connections = metrics().connections.with_label_values(&["inbound"]);
+            connections.inc();
+            loop {
+                let frame = match read_message::<_, Message>(&mut socket).await {
+                    Ok(Message::Gossip(frame)) => frame,
+                    Ok(Message::GossipPrune(prune)) => {
+                        gossip.handle_prune(prune).await;
+                        continue;
+                    }
+                    Ok(Message::CrdsPush(values)) => {
+                        cluster.insert_values(values).await;
+                        continue;
+                    }
+                    Ok(Message::CrdsPullRequest(filter, caller)) => {
+                        let values = cluster.handle_pull_request(&filter, caller).await;
+                        if write_message(&mut socket, &Message::CrdsPullResponse(values)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok(message) => {
+                        if inbound.send(Inbound::Direct(message)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        metrics().frame_decode_errors.inc();
+                        warn!("Failed to parse gossip frame: {}", e);
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+
+                let payload = match gossip.receive(frame, &cluster.peers().await).await {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+                if inbound.send(Inbound::Gossip(payload)).await.is_err() {
+                    break;
+                }
             }
-            println!("Gossiped message to {}", addr);
-        }
+            connections.dec();
+        }.instrument(info_span!("gossip_connection", peer = %addr)));
     }
 }
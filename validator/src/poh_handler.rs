@@ -1,5 +1,20 @@
 use sha2::{Sha256, Digest};
 use serde::{Serialize, Deserialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use crate::clock::Clock;
+use crate::metrics::metrics;
+use tracing::warn;
+
+/// How often the leader pushes new entries to PoH subscribers.
+pub const POH_BATCH_INTERVAL_MS: u64 = 1_000;
+pub const MAX_POH_BATCH_ENTRIES: usize = 1_000;
+/// A checkpoint is recorded every this many entries.
+pub const POH_CHECKPOINT_INTERVAL: usize = 1_000;
+pub const DEFAULT_MAX_POH_ENTRIES_IN_MEMORY: usize = 10_000;
+const POH_FILE: &str = "poh.jsonl";
+const POH_ANCHOR_FILE: &str = "poh_anchor.json";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PohEntry {
@@ -7,20 +22,24 @@ pub struct PohEntry {
     pub hash: Vec<u8>,
 }
 
-pub fn validate_poh_entries(poh_entries: &Vec<PohEntry>) -> Result<(), usize> {
+/// The hash of the entry that follows one hashing to `prev_hash`.
+pub fn poh_hash(prev_hash: &[u8], timestamp: u64) -> Vec<u8> {
+    metrics().poh_hashes.inc();
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash);
+    hasher.update(timestamp.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+pub fn validate_poh_entries(poh_entries: &[PohEntry]) -> Result<(), usize> {
     for i in 1..poh_entries.len() {
         let prev_entry = &poh_entries[i - 1];
         let curr_entry = &poh_entries[i];
 
-        let mut hasher = Sha256::new();
-        let timestamp_bytes = curr_entry.timestamp.to_be_bytes();
-
-        hasher.update(&prev_entry.hash);
-        hasher.update(&timestamp_bytes);
-        let expected_hash = hasher.finalize_reset().to_vec();
+        let expected_hash = poh_hash(&prev_entry.hash, curr_entry.timestamp);
 
         if curr_entry.hash != expected_hash {
-            println!(
+            warn!(
                 "Validation failed at index {}: expected={:?}, got={:?}",
                 i, expected_hash, curr_entry.hash
             );
@@ -29,3 +48,209 @@ pub fn validate_poh_entries(poh_entries: &Vec<PohEntry>) -> Result<(), usize> {
     }
     Ok(())
 }
+
+/// The PoH height (number of entries so far) and the hash of the entry
+/// ending there. Entries after a checkpoint can be verified from it alone.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PohCheckpoint {
+    pub height: usize,
+    pub hash: Vec<u8>,
+}
+
+/// PoH entries after `anchor`, bounded in memory. Entries are indexed by
+/// absolute height; once more than `max_entries` are held, everything up to
+/// a checkpoint is evicted. With a ledger directory every entry is also
+/// appended to `poh.jsonl` there, so evicted history stays on disk.
+#[derive(Debug)]
+pub struct PohHistory {
+    genesis: PohCheckpoint,
+    anchor: PohCheckpoint,
+    entries: Vec<PohEntry>,
+    checkpoints: Vec<PohCheckpoint>,
+    max_entries: usize,
+    ledger_dir: Option<PathBuf>,
+}
+
+impl PohHistory {
+    /// History starting at genesis; the first entry chains from the genesis hash.
+    pub fn new(genesis_hash: [u8; 32]) -> Self {
+        let genesis = PohCheckpoint {
+            height: 0,
+            hash: genesis_hash.to_vec(),
+        };
+        PohHistory {
+            anchor: genesis.clone(),
+            genesis,
+            entries: Vec::new(),
+            checkpoints: Vec::new(),
+            max_entries: DEFAULT_MAX_POH_ENTRIES_IN_MEMORY,
+            ledger_dir: None,
+        }
+    }
+
+    /// Reloads history from the ledger, keeping only the newest entries in memory.
+    pub fn open(ledger_dir: &Path, genesis_hash: [u8; 32]) -> io::Result<Self> {
+        Self::open_with_max_entries(ledger_dir, genesis_hash, DEFAULT_MAX_POH_ENTRIES_IN_MEMORY)
+    }
+
+    /// Like `open`, but holding up to `max_entries` in memory; offline tools
+    /// pass `usize::MAX` to load the whole history.
+    pub fn open_with_max_entries(ledger_dir: &Path, genesis_hash: [u8; 32], max_entries: usize) -> io::Result<Self> {
+        fs::create_dir_all(ledger_dir)?;
+        let mut history = PohHistory::new(genesis_hash).with_max_entries(max_entries);
+        let anchor_path = ledger_dir.join(POH_ANCHOR_FILE);
+        if anchor_path.exists() {
+            history.anchor = serde_json::from_slice(&fs::read(&anchor_path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        let path = ledger_dir.join(POH_FILE);
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry = serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                history.record(entry);
+            }
+        }
+        history.ledger_dir = Some(ledger_dir.to_path_buf());
+        Ok(history)
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(POH_CHECKPOINT_INTERVAL);
+        self
+    }
+
+    /// Total number of entries, including evicted ones.
+    pub fn len(&self) -> usize {
+        self.anchor.height + self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn last_hash(&self) -> Vec<u8> {
+        self.entries.last().map_or_else(|| self.anchor.hash.clone(), |entry| entry.hash.clone())
+    }
+
+    /// Hash of the entry ending at `height`, if it is still held.
+    pub fn hash_at(&self, height: usize) -> Option<Vec<u8>> {
+        if height == self.anchor.height {
+            return Some(self.anchor.hash.clone());
+        }
+        if height < self.anchor.height {
+            return None;
+        }
+        self.entries.get(height - self.anchor.height - 1).map(|entry| entry.hash.clone())
+    }
+
+    /// Entries with `start <= index < end` still held in memory.
+    pub fn range(&self, start: usize, end: usize) -> Vec<PohEntry> {
+        if start < self.anchor.height {
+            return Vec::new();
+        }
+        let start = start - self.anchor.height;
+        let end = end.saturating_sub(self.anchor.height).min(self.entries.len());
+        self.entries.get(start..end).map(<[PohEntry]>::to_vec).unwrap_or_default()
+    }
+
+    /// Entries held in memory, oldest first.
+    pub fn entries(&self) -> &[PohEntry] {
+        &self.entries
+    }
+
+    /// The oldest point history can be streamed or verified from.
+    pub fn anchor(&self) -> &PohCheckpoint {
+        &self.anchor
+    }
+
+    pub fn latest_checkpoint(&self) -> Option<&PohCheckpoint> {
+        self.checkpoints.last()
+    }
+
+    /// Appends the next entry, stamped with the time on `clock`.
+    pub fn tick(&mut self, clock: &impl Clock) -> io::Result<()> {
+        let timestamp = clock.now_secs();
+        let hash = poh_hash(&self.last_hash(), timestamp);
+        self.push(PohEntry { timestamp, hash })
+    }
+
+    pub fn push(&mut self, entry: PohEntry) -> io::Result<()> {
+        if let Some(dir) = &self.ledger_dir {
+            let mut file = OpenOptions::new().create(true).append(true).open(dir.join(POH_FILE))?;
+            writeln!(file, "{}", serde_json::to_string(&entry).unwrap())?;
+        }
+        self.record(entry);
+        metrics().poh_ticks.inc();
+        Ok(())
+    }
+
+    fn record(&mut self, entry: PohEntry) {
+        self.entries.push(entry);
+        if self.len().is_multiple_of(POH_CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(PohCheckpoint {
+                height: self.len(),
+                hash: self.last_hash(),
+            });
+        }
+        if self.entries.len() > self.max_entries {
+            self.evict();
+        }
+    }
+
+    /// Drops entries up to the newest checkpoint that still leaves half the
+    /// in-memory budget, which becomes the new anchor.
+    fn evict(&mut self) {
+        let keep_from = self.len() - self.max_entries / 2;
+        let checkpoint = match self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.height <= keep_from && checkpoint.height > self.anchor.height)
+        {
+            Some(checkpoint) => checkpoint.clone(),
+            None => return,
+        };
+        self.entries.drain(..checkpoint.height - self.anchor.height);
+        self.anchor = checkpoint;
+    }
+
+    /// Appends entries that continue our history from `start`, verifying each
+    /// chains from the one before. Returns how many were appended, or the
+    /// failing index on a mismatch; the outer error is a failed write.
+    pub fn append_verified(&mut self, start: usize, entries: Vec<PohEntry>) -> io::Result<Result<usize, usize>> {
+        if start != self.len() || entries.is_empty() {
+            return Ok(Ok(0));
+        }
+        let previous = PohEntry {
+            timestamp: 0,
+            hash: self.last_hash(),
+        };
+        let mut chained = vec![previous];
+        chained.extend(entries.iter().cloned());
+        if let Err(index) = validate_poh_entries(&chained) {
+            return Ok(Err(start + index - 1));
+        }
+        let count = entries.len();
+        for entry in entries {
+            self.push(entry)?;
+        }
+        Ok(Ok(count))
+    }
+
+    /// Restarts history at a checkpoint from a trusted source (the leader),
+    /// or at genesis with `None`. Nothing before it is verified again.
+    pub fn reset_to(&mut self, checkpoint: Option<PohCheckpoint>) -> io::Result<()> {
+        self.anchor = checkpoint.unwrap_or_else(|| self.genesis.clone());
+        self.entries.clear();
+        self.checkpoints = vec![self.anchor.clone()];
+        if let Some(dir) = &self.ledger_dir {
+            fs::write(dir.join(POH_ANCHOR_FILE), serde_json::to_vec(&self.anchor).unwrap())?;
+            File::create(dir.join(POH_FILE))?;
+        }
+        Ok(())
+    }
+}
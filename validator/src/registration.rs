@@ -4,4 +4,6 @@ use serde::{Serialize, Deserialize};
 pub struct Validator {
     pub id: String,
     pub public_key: Vec<u8>,
+    #[serde(default)]
+    pub genesis_hash: String,
 }
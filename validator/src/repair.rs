@@ -0,0 +1,233 @@
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+use crate::block::Block;
+use crate::blockstore::Blockstore;
+use crate::clock::Clock;
+use crate::catchup::{MAX_BLOCKS_PER_REQUEST, MAX_POH_ENTRIES_PER_REQUEST};
+use crate::keypair::{self, Keypair};
+use crate::poh_handler::{PohEntry, PohHistory};
+
+/// Blocks larger than this are only served shred by shred.
+pub const MAX_REPAIR_RESPONSE_BYTES: usize = 1024 * 1024;
+pub const SHRED_PAYLOAD_SIZE: usize = 64 * 1024;
+pub const MAX_REPAIR_REQUESTS_PER_SECOND: u32 = 50;
+const MAX_TRACKED_REQUESTERS: usize = 10_000;
+/// Requests stamped further than this from our clock are refused, which
+/// limits how long a captured request can be replayed.
+const REPAIR_REQUEST_TIMEOUT_MS: u64 = 10_000;
+/// Blocks of up to 16 MiB can be rebuilt from shreds.
+pub const MAX_SHREDS_PER_BLOCK: usize = 256;
+const MAX_PENDING_SHRED_BLOCKS: usize = 8;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RepairRequest {
+    /// Inclusive range of block heights.
+    Blocks { start: u64, end: u64 },
+    Shred { block_height: u64, index: usize },
+    /// Half-open range of PoH entry indices.
+    Poh { start: usize, end: usize },
+}
+
+/// A repair request signed by the node asking for it. The rate limit and
+/// the response both go to `requester`, so it can't be named by anyone else.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedRepairRequest {
+    pub requester: String,
+    pub request: RepairRequest,
+    pub wallclock: u64,
+    pub signature: Vec<u8>,
+}
+
+impl SignedRepairRequest {
+    pub fn new_signed(keypair: &Keypair, request: RepairRequest, wallclock: u64) -> Self {
+        let mut signed = SignedRepairRequest {
+            requester: keypair.pubkey(),
+            request,
+            wallclock,
+            signature: Vec::new(),
+        };
+        signed.signature = keypair.sign(&signed.signing_bytes());
+        signed
+    }
+
+    /// Signed by the requester and stamped close to `now_ms`.
+    pub fn verify(&self, now_ms: u64) -> bool {
+        now_ms.abs_diff(self.wallclock) <= REPAIR_REQUEST_TIMEOUT_MS
+            && keypair::verify(&self.requester, &self.signing_bytes(), &self.signature)
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(&(&self.requester, &self.request, self.wallclock)).unwrap()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RepairResponse {
+    Blocks(Vec<Block>),
+    Shred(Shred),
+    Poh { start: usize, entries: Vec<PohEntry> },
+}
+
+/// A fixed-size piece of a serialized block.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Shred {
+    pub block_height: u64,
+    pub index: usize,
+    pub num_shreds: usize,
+    pub payload: Vec<u8>,
+}
+
+pub fn shred_block(block: &Block) -> Vec<Shred> {
+    let data = serde_json::to_vec(block).unwrap();
+    let num_shreds = data.len().div_ceil(SHRED_PAYLOAD_SIZE).max(1);
+    data.chunks(SHRED_PAYLOAD_SIZE)
+        .enumerate()
+        .map(|(index, chunk)| Shred {
+            block_height: block.block_height,
+            index,
+            num_shreds,
+            payload: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// Collects repaired shreds until a whole block can be rebuilt. Shreds come
+/// from peers, so at most `MAX_PENDING_SHRED_BLOCKS` blocks are collected at
+/// once, the lowest heights first since those are the ones we need next.
+#[derive(Debug, Default)]
+pub struct ShredCollector {
+    pending: BTreeMap<u64, BTreeMap<usize, Shred>>,
+}
+
+impl ShredCollector {
+    /// Returns the shred indices still missing for the shred's block, or the
+    /// rebuilt block once every shred is in. A shred that is malformed,
+    /// disagrees with the shred count we hold or doesn't fit is dropped,
+    /// and nothing is reported missing.
+    pub fn insert(&mut self, shred: Shred) -> Result<Block, Vec<usize>> {
+        let block_height = shred.block_height;
+        let num_shreds = shred.num_shreds;
+        if num_shreds == 0
+            || num_shreds > MAX_SHREDS_PER_BLOCK
+            || shred.index >= num_shreds
+            || shred.payload.len() > SHRED_PAYLOAD_SIZE
+        {
+            return Err(Vec::new());
+        }
+        if !self.pending.contains_key(&block_height) && self.pending.len() >= MAX_PENDING_SHRED_BLOCKS {
+            match self.pending.last_key_value() {
+                Some((&highest, _)) if highest > block_height => {
+                    self.pending.remove(&highest);
+                }
+                _ => return Err(Vec::new()),
+            }
+        }
+        let shreds = self.pending.entry(block_height).or_default();
+        if shreds.values().next().is_some_and(|held| held.num_shreds != num_shreds) {
+            return Err(Vec::new());
+        }
+        shreds.insert(shred.index, shred);
+
+        let missing: Vec<usize> = (0..num_shreds).filter(|index| !shreds.contains_key(index)).collect();
+        if !missing.is_empty() {
+            return Err(missing);
+        }
+        let data: Vec<u8> = shreds.values().flat_map(|shred| shred.payload.iter().copied()).collect();
+        self.pending.remove(&block_height);
+        serde_json::from_slice(&data).map_err(|_| (0..num_shreds).collect())
+    }
+
+    /// Drops blocks below `height`, which we no longer need.
+    pub fn purge_below(&mut self, height: u64) {
+        self.pending = self.pending.split_off(&height);
+    }
+
+    pub fn pending_blocks(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Serves repair requests from our blockstore and PoH history, at most
+/// `max_requests_per_second` per requester.
+#[derive(Debug)]
+pub struct RepairService {
+    max_requests_per_second: u32,
+    /// Per requester, when its one-second window started (ms) and how many
+    /// requests it made in it.
+    windows: HashMap<String, (u64, u32)>,
+}
+
+impl Default for RepairService {
+    fn default() -> Self {
+        RepairService::new(MAX_REPAIR_REQUESTS_PER_SECOND)
+    }
+}
+
+impl RepairService {
+    pub fn new(max_requests_per_second: u32) -> Self {
+        RepairService {
+            max_requests_per_second,
+            windows: HashMap::new(),
+        }
+    }
+
+    fn allow(&mut self, now: u64, requester: &str) -> bool {
+        if self.windows.len() > MAX_TRACKED_REQUESTERS {
+            self.windows.retain(|_, (start, _)| now.saturating_sub(*start) < 1000);
+        }
+        let window = self.windows.entry(requester.to_string()).or_insert((now, 0));
+        if now.saturating_sub(window.0) >= 1000 {
+            *window = (now, 0);
+        }
+        window.1 += 1;
+        window.1 <= self.max_requests_per_second
+    }
+
+    /// None if the requester is over its rate limit or we lack the shred.
+    pub fn serve(
+        &mut self,
+        clock: &impl Clock,
+        requester: &str,
+        request: &RepairRequest,
+        blockstore: &Blockstore,
+        poh: &PohHistory,
+    ) -> Option<RepairResponse> {
+        if !self.allow(clock.now_ms(), requester) {
+            return None;
+        }
+        let response = match *request {
+            RepairRequest::Blocks { start, end } => {
+                let end = end.min(start.saturating_add(MAX_BLOCKS_PER_REQUEST - 1));
+                let mut blocks = Vec::new();
+                let mut size = 0;
+                for block in blockstore.range(start, end, MAX_BLOCKS_PER_REQUEST as usize) {
+                    size += serde_json::to_vec(&block).unwrap().len();
+                    if size > MAX_REPAIR_RESPONSE_BYTES {
+                        // A block too big to send whole goes out as shreds,
+                        // starting with the first so the requester learns the count
+                        if blocks.is_empty() {
+                            return Some(RepairResponse::Shred(shred_block(&block).swap_remove(0)));
+                        }
+                        break;
+                    }
+                    blocks.push(block);
+                }
+                RepairResponse::Blocks(blocks)
+            }
+            RepairRequest::Shred { block_height, index } => {
+                let shred = blockstore
+                    .get(block_height)
+                    .and_then(|block| shred_block(block).into_iter().nth(index))?;
+                RepairResponse::Shred(shred)
+            }
+            RepairRequest::Poh { start, end } => {
+                let end = end.min(start.saturating_add(MAX_POH_ENTRIES_PER_REQUEST));
+                RepairResponse::Poh {
+                    start,
+                    entries: poh.range(start, end),
+                }
+            }
+        };
+        Some(response)
+    }
+}
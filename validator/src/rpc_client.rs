@@ -0,0 +1,181 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::stake::{Stake, StakeAccountAction};
+use crate::transaction::Transaction;
+
+/// Where the leader serves JSON-RPC unless configured otherwise.
+pub const DEFAULT_RPC_ADDR: &str = "127.0.0.1:8899";
+
+/// How often `confirm_transaction` asks for the signature status.
+const CONFIRMATION_POLL_INTERVAL_MS: u64 = 400;
+
+/// How far along a transaction is, weakest first.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Commitment {
+    /// Included in a produced block.
+    Processed,
+    /// Included in a block confirmed by a supermajority of stake.
+    Confirmed,
+}
+
+impl std::str::FromStr for Commitment {
+    type Err = String;
+
+    fn from_str(commitment: &str) -> Result<Self, Self::Err> {
+        match commitment {
+            "processed" => Ok(Commitment::Processed),
+            "confirmed" => Ok(Commitment::Confirmed),
+            _ => Err(format!("unknown commitment {} (expected processed or confirmed)", commitment)),
+        }
+    }
+}
+
+impl fmt::Display for Commitment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Commitment::Processed => write!(f, "processed"),
+            Commitment::Confirmed => write!(f, "confirmed"),
+        }
+    }
+}
+
+/// An entry of `getSignatureStatuses`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureStatus {
+    pub slot: u64,
+    pub confirmation_status: Commitment,
+}
+
+/// An entry of `getStakeAccounts`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcStakeAccount {
+    pub address: String,
+    pub validator_id: String,
+    pub amount: u64,
+    pub activation_epoch: u64,
+    pub deactivation_epoch: Option<u64>,
+}
+
+/// Talks JSON-RPC to the leader, one HTTP request per call.
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    addr: SocketAddr,
+}
+
+impl RpcClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        RpcClient { addr }
+    }
+
+    /// Calls `method`, returning its result or the server's error message.
+    pub async fn call(&self, method: &str, params: Value) -> io::Result<Value> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let request = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.addr,
+            body.len(),
+            body
+        );
+        let mut stream = TcpStream::connect(self.addr)
+            .await
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to connect to RPC at {}: {}", self.addr, e)))?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        let header_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .ok_or_else(|| io::Error::other("malformed HTTP response"))?;
+        if !response.starts_with(b"HTTP/1.1 200") {
+            let status_line = String::from_utf8_lossy(&response[..header_end]).lines().next().unwrap_or_default().to_string();
+            return Err(io::Error::other(format!("RPC request failed: {}", status_line)));
+        }
+        let mut response: Value = serde_json::from_slice(&response[header_end + 4..])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("invalid RPC response: {}", e)))?;
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or("unknown error");
+            return Err(io::Error::other(format!("{} failed: {}", method, message)));
+        }
+        Ok(response["result"].take())
+    }
+
+    async fn call_as<T: DeserializeOwned>(&self, method: &str, params: Value) -> io::Result<T> {
+        let result = self.call(method, params).await?;
+        serde_json::from_value(result)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected {} result: {}", method, e)))
+    }
+
+    pub async fn get_balance(&self, pubkey: &str) -> io::Result<u64> {
+        let result = self.call("getBalance", json!([pubkey])).await?;
+        serde_json::from_value(result["value"].clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected getBalance result: {}", e)))
+    }
+
+    pub async fn get_latest_blockhash(&self) -> io::Result<String> {
+        let result = self.call("getLatestBlockhash", json!([])).await?;
+        result["value"]["blockhash"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| io::Error::other("unexpected getLatestBlockhash result"))
+    }
+
+    /// Queues a transaction for the next block, returning its base58 signature.
+    pub async fn send_transaction(&self, transaction: &Transaction) -> io::Result<String> {
+        self.call_as("sendTransaction", json!([transaction])).await
+    }
+
+    pub async fn get_signature_status(&self, signature: &str) -> io::Result<Option<SignatureStatus>> {
+        let result = self.call("getSignatureStatuses", json!([[signature]])).await?;
+        serde_json::from_value(result["value"][0].clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected getSignatureStatuses result: {}", e)))
+    }
+
+    /// Waits until the transaction reaches `commitment`, returning its slot.
+    pub async fn confirm_transaction(&self, signature: &str, commitment: Commitment, timeout: Duration) -> io::Result<u64> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get_signature_status(signature).await? {
+                Some(status) if status.confirmation_status >= commitment => return Ok(status.slot),
+                _ if Instant::now() >= deadline => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("transaction {} was not {} within {:?}", signature, commitment, timeout),
+                    ));
+                }
+                _ => tokio::time::sleep(Duration::from_millis(CONFIRMATION_POLL_INTERVAL_MS)).await,
+            }
+        }
+    }
+
+    /// Returns the address of the stake account the delegation creates once
+    /// it lands in a block.
+    pub async fn delegate_stake(&self, stake: &Stake) -> io::Result<String> {
+        self.call_as("delegateStake", json!([stake])).await
+    }
+
+    pub async fn deactivate_stake(&self, action: &StakeAccountAction) -> io::Result<()> {
+        self.call("deactivateStake", json!([action])).await.map(|_| ())
+    }
+
+    /// Returns the amount the withdrawal moves back to the staker's balance,
+    /// as of when the leader accepted it.
+    pub async fn withdraw_stake(&self, action: &StakeAccountAction) -> io::Result<u64> {
+        self.call_as("withdrawStake", json!([action])).await
+    }
+
+    /// Stake accounts owned by `staker`.
+    pub async fn get_stake_accounts(&self, staker: &str) -> io::Result<Vec<RpcStakeAccount>> {
+        self.call_as("getStakeAccounts", json!([staker])).await
+    }
+}
@@ -0,0 +1,575 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use crate::block::Message;
+use crate::blockstore::Blockstore;
+use crate::catchup::REPAIR_INTERVAL_MS;
+use crate::clock::Clock;
+use crate::consensus::{elect_leader, ConfirmationTracker};
+use crate::genesis::{EpochSchedule, GenesisAccount, GenesisConfig, GenesisValidator, PohConfig};
+use crate::gossip::message_hash;
+use crate::handlers::{BlockProducer, ValidatorChain, VoteCounter, VoteOutcome};
+use crate::keypair::Keypair;
+use crate::network::Network;
+use crate::poh_handler::PohHistory;
+use crate::repair::{RepairResponse, RepairService};
+use crate::slashing::{ConflictDetector, EvidencePool};
+use crate::stake::{Stake, StakeAccounts, StakeInstruction};
+use crate::transaction::Transaction;
+use tracing::{debug, trace};
+
+/// Identity the simulated client submits transfers under.
+pub const CLIENT: &str = "client";
+/// Virtual time starts here, in ms since the Unix epoch.
+const START_MS: u64 = 1_700_000_000_000;
+const CLIENT_ACCOUNTS: usize = 8;
+const MAX_TRANSFER: u64 = 100;
+/// Chance that the client also delegates some stake each slot.
+const DELEGATION_CHANCE: f64 = 0.2;
+const MINT_BALANCE: u64 = 1_000_000;
+
+/// What the simulated network does to every message.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    /// Each message takes between `min_delay_ms` and `max_delay_ms` to arrive.
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Chance that a message is lost, from 0.0 to 1.0.
+    pub drop_rate: f64,
+    /// Let a message overtake ones sent before it to the same peer.
+    pub reorder: bool,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            min_delay_ms: 10,
+            max_delay_ms: 100,
+            drop_rate: 0.0,
+            reorder: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// Everything random in a run (identities, message faults, elections,
+    /// transfers, delegations) derives from this.
+    pub seed: u64,
+    pub validators: usize,
+    /// Blocks the leader produces before the run winds down.
+    pub slots: u64,
+    pub slot_duration_ms: u64,
+    pub election_interval_ms: u64,
+    /// Genesis stake of each validator.
+    pub stake: u64,
+    pub transfers_per_slot: usize,
+    pub faults: Faults,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            seed: 0,
+            validators: 4,
+            slots: 100,
+            slot_duration_ms: 400,
+            election_interval_ms: 30_000,
+            stake: 10_000,
+            transfers_per_slot: 2,
+            faults: Faults::default(),
+        }
+    }
+}
+
+/// Virtual time, advanced by the simulation as it works through events.
+#[derive(Debug, Clone, Copy)]
+pub struct SimClock {
+    pub now_ms: u64,
+}
+
+impl Clock for SimClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms
+    }
+}
+
+#[derive(Debug)]
+enum Event {
+    Deliver { from: String, to: String, message: Box<Message> },
+    Slot,
+    Election,
+    Transfers,
+    RepairTick(usize),
+}
+
+/// Messages in flight and timers, ordered by due time and then by when they
+/// were scheduled.
+pub struct SimNetwork {
+    now_ms: u64,
+    faults: Faults,
+    rng: StdRng,
+    queue: BTreeMap<(u64, u64), Event>,
+    next_seq: u64,
+    /// When the last message on each link arrives, to keep links in order
+    /// unless reordering is on.
+    last_arrival: HashMap<(String, String), u64>,
+    pub sent: u64,
+    pub dropped: u64,
+}
+
+impl SimNetwork {
+    fn new(faults: Faults, rng: StdRng) -> Self {
+        SimNetwork {
+            now_ms: START_MS,
+            faults,
+            rng,
+            queue: BTreeMap::new(),
+            next_seq: 0,
+            last_arrival: HashMap::new(),
+            sent: 0,
+            dropped: 0,
+        }
+    }
+
+    fn schedule(&mut self, at_ms: u64, event: Event) {
+        self.queue.insert((at_ms, self.next_seq), event);
+        self.next_seq += 1;
+    }
+
+    fn next_event(&mut self, until_ms: u64) -> Option<(u64, Event)> {
+        let entry = self.queue.first_entry().filter(|entry| entry.key().0 <= until_ms)?;
+        let ((at_ms, _), event) = entry.remove_entry();
+        self.now_ms = at_ms;
+        Some((at_ms, event))
+    }
+}
+
+impl Network for SimNetwork {
+    fn send(&mut self, from: &str, to: &str, message: Message) {
+        self.sent += 1;
+        if self.rng.gen_bool(self.faults.drop_rate.clamp(0.0, 1.0)) {
+            self.dropped += 1;
+            trace!("Dropped message from {} to {}", from, to);
+            return;
+        }
+        let delay = self.rng.gen_range(self.faults.min_delay_ms..=self.faults.max_delay_ms.max(self.faults.min_delay_ms));
+        let mut arrival = self.now_ms + delay;
+        if !self.faults.reorder {
+            let last = self.last_arrival.entry((from.to_string(), to.to_string())).or_insert(0);
+            arrival = arrival.max(*last);
+            *last = arrival;
+        }
+        let event = Event::Deliver { from: from.to_string(), to: to.to_string(), message: Box::new(message) };
+        self.schedule(arrival, event);
+    }
+}
+
+/// The leader's side of consensus: a block per slot, vote counting and
+/// repairs, without the TCP and timers of the real leader. Blocks and votes
+/// go through the leader's own handlers.
+struct SimLeader {
+    identity: Keypair,
+    genesis: GenesisConfig,
+    blockstore: Blockstore,
+    poh: PohHistory,
+    parent_hash: [u8; 32],
+    balances: HashMap<String, u64>,
+    stakes: StakeAccounts,
+    mempool: Vec<Transaction>,
+    stake_instructions: Vec<StakeInstruction>,
+    conflict_detector: ConflictDetector,
+    evidence_pool: EvidencePool,
+    confirmations: ConfirmationTracker,
+    vote_credits: HashMap<String, u64>,
+    repair_service: RepairService,
+    validators: Vec<String>,
+    burned: u64,
+    /// Paid out as epoch rewards.
+    issued: u64,
+}
+
+impl SimLeader {
+    /// A slot is one tick, as in the genesis the simulation builds.
+    fn produce_block(&mut self, clock: &impl Clock, net: &mut impl Network) {
+        let _ = self.poh.tick(clock);
+        let parent_height = self.blockstore.height();
+        let produced = BlockProducer {
+            identity: &self.identity,
+            genesis: &self.genesis,
+            blockstore: &mut self.blockstore,
+            balances: &mut self.balances,
+            stakes: &mut self.stakes,
+            vote_credits: &mut self.vote_credits,
+        }
+        .produce_block(
+            clock,
+            self.parent_hash,
+            parent_height,
+            std::mem::take(&mut self.mempool),
+            self.evidence_pool.take_pending(),
+            std::mem::take(&mut self.stake_instructions),
+        );
+        self.burned += produced.burned;
+        self.issued += produced.rewards.map_or(0, |rewards| rewards.total);
+        let block = produced.block;
+        self.parent_hash.copy_from_slice(&hex::decode(&block.block_hash).expect("block hash is hex"));
+        debug!("Leader produced block {} with {} transactions", block.block_height, block.transactions.len());
+        let id = self.identity.pubkey();
+        for validator in &self.validators {
+            net.send(&id, validator, Message::BlockProposal(block.clone()));
+        }
+    }
+
+    fn handle(&mut self, clock: &impl Clock, from: &str, message: Message, net: &mut impl Network) {
+        match message {
+            Message::ConsensusVote(vote) if vote.voter == from && vote.verify_signature() => {
+                let epoch = self.genesis.epoch_schedule.epoch_for_slot(self.blockstore.height());
+                let active_stakes = self.stakes.active_stakes(epoch);
+                let mut counter = VoteCounter {
+                    identity: &self.identity,
+                    blockstore: &self.blockstore,
+                    conflict_detector: &mut self.conflict_detector,
+                    evidence_pool: &mut self.evidence_pool,
+                    confirmations: &mut self.confirmations,
+                    vote_credits: &mut self.vote_credits,
+                };
+                if counter.handle_vote(&vote, &active_stakes) == (VoteOutcome::Counted { confirmed: true }) {
+                    debug!("Slot {} confirmed", vote.block_height);
+                }
+            }
+            Message::Transaction(transaction) if transaction.validate() => self.mempool.push(transaction),
+            Message::StakeTokens(stake) => self.queue_stake_instruction(StakeInstruction::Delegate(stake)),
+            Message::DeactivateStake(action) => self.queue_stake_instruction(StakeInstruction::Deactivate(action)),
+            Message::WithdrawStake(action) => self.queue_stake_instruction(StakeInstruction::Withdraw(action)),
+            Message::Evidence(evidence) => {
+                self.evidence_pool.add(evidence);
+            }
+            Message::Repair(repair) if repair.requester == from && repair.verify(clock.now_ms()) => {
+                if let Some(response) = self.repair_service.serve(clock, from, &repair.request, &self.blockstore, &self.poh) {
+                    net.send(&self.identity.pubkey(), from, Message::RepairResponse(response));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Queues a stake instruction that passes against the bank now, as the
+    /// leader does on submission.
+    fn queue_stake_instruction(&mut self, instruction: StakeInstruction) {
+        let epoch = self.genesis.epoch_schedule.epoch_for_slot(self.blockstore.height() + 1);
+        if instruction.verify_signature() && self.stakes.check(&self.balances, &instruction, epoch).is_ok() {
+            self.stake_instructions.push(instruction);
+        }
+    }
+}
+
+/// A validator following the leader through the same `ValidatorChain` the
+/// real validator runs.
+struct SimValidator {
+    identity: Keypair,
+    leader: String,
+    chain: ValidatorChain,
+}
+
+impl SimValidator {
+    fn handle(&mut self, clock: &impl Clock, message: Message, net: &mut impl Network) {
+        match message {
+            Message::BlockProposal(block) if block.leader == self.leader => {
+                self.chain.handle_proposal(clock, net, &self.identity, &self.leader, &block);
+            }
+            Message::RepairResponse(RepairResponse::Blocks(blocks)) => {
+                let _ = self.chain.handle_repaired_blocks(clock, net, &self.identity, &self.leader, blocks);
+            }
+            _ => {}
+        }
+    }
+
+    fn repair(&mut self, clock: &impl Clock, net: &mut impl Network) {
+        self.chain.repair(clock, net, &self.identity, &self.leader);
+    }
+}
+
+/// Outcome of a run. Two runs with the same config produce the same report,
+/// down to the fingerprint of every event they processed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimReport {
+    pub seed: u64,
+    pub leader_height: u64,
+    pub confirmed_slots: u64,
+    pub validator_heights: Vec<u64>,
+    pub transactions: usize,
+    pub stake_instructions: usize,
+    /// Paid out as epoch rewards.
+    pub rewards: u64,
+    pub elections: u64,
+    pub messages_sent: u64,
+    pub messages_dropped: u64,
+    pub fingerprint: String,
+    /// Broken invariants; empty for a good run.
+    pub violations: Vec<String>,
+}
+
+impl fmt::Display for SimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {}: height {}, {} confirmed, validators at {:?}, {} transactions, {} stake instructions, {} rewarded, {} elections, {}/{} messages dropped, fingerprint {}",
+            self.seed,
+            self.leader_height,
+            self.confirmed_slots,
+            self.validator_heights,
+            self.transactions,
+            self.stake_instructions,
+            self.rewards,
+            self.elections,
+            self.messages_dropped,
+            self.messages_sent,
+            self.fingerprint
+        )
+    }
+}
+
+/// A leader, validators and a client in one thread on virtual time. Timers
+/// fire and messages arrive in a fixed order for a given seed, so a failing
+/// run can be replayed exactly from its seed.
+pub struct Simulation {
+    config: SimConfig,
+    genesis: GenesisConfig,
+    rng: StdRng,
+    net: SimNetwork,
+    leader: SimLeader,
+    validators: Vec<SimValidator>,
+    /// The mint first, then accounts it funds.
+    accounts: Vec<Keypair>,
+    produced: u64,
+    elections: u64,
+    trace: Sha256,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> Self {
+        let mut seeds = StdRng::seed_from_u64(config.seed);
+        let leader_identity = Keypair::from_seed(seeds.gen());
+        let validator_identities: Vec<Keypair> = (0..config.validators).map(|_| Keypair::from_seed(seeds.gen())).collect();
+        let accounts: Vec<Keypair> = (0..CLIENT_ACCOUNTS).map(|_| Keypair::from_seed(seeds.gen())).collect();
+        let net = SimNetwork::new(config.faults.clone(), StdRng::seed_from_u64(seeds.gen()));
+        let rng = StdRng::seed_from_u64(seeds.gen());
+
+        let genesis = GenesisConfig {
+            creation_time: START_MS / 1000,
+            accounts: vec![GenesisAccount { pubkey: accounts[0].pubkey(), balance: MINT_BALANCE }],
+            validators: validator_identities
+                .iter()
+                .map(|identity| GenesisValidator { identity: identity.pubkey(), stake: config.stake, commission: 0 })
+                .collect(),
+            poh: PohConfig {
+                tick_duration_ms: config.slot_duration_ms,
+                ticks_per_slot: 1,
+            },
+            epoch_schedule: EpochSchedule::default(),
+            inflation: Default::default(),
+            slashing: Default::default(),
+        };
+        let genesis_hash = genesis.hash();
+
+        let leader = SimLeader {
+            genesis: genesis.clone(),
+            blockstore: Blockstore::in_memory(),
+            poh: PohHistory::new(genesis_hash),
+            parent_hash: genesis_hash,
+            balances: genesis.accounts.iter().map(|account| (account.pubkey.clone(), account.balance)).collect(),
+            stakes: StakeAccounts::from_genesis(&genesis),
+            mempool: Vec::new(),
+            stake_instructions: Vec::new(),
+            conflict_detector: ConflictDetector::default(),
+            evidence_pool: EvidencePool::default(),
+            confirmations: ConfirmationTracker::default(),
+            vote_credits: HashMap::new(),
+            repair_service: RepairService::default(),
+            validators: validator_identities.iter().map(Keypair::pubkey).collect(),
+            burned: 0,
+            issued: 0,
+            identity: leader_identity,
+        };
+        let validators = validator_identities
+            .into_iter()
+            .map(|identity| SimValidator {
+                identity,
+                leader: leader.identity.pubkey(),
+                chain: ValidatorChain::new(Blockstore::in_memory(), genesis_hash),
+            })
+            .collect();
+
+        let mut simulation = Simulation {
+            config,
+            genesis,
+            rng,
+            net,
+            leader,
+            validators,
+            accounts,
+            produced: 0,
+            elections: 0,
+            trace: Sha256::new(),
+        };
+        let start = simulation.net.now_ms;
+        simulation.net.schedule(start + simulation.config.slot_duration_ms, Event::Slot);
+        simulation.net.schedule(start + simulation.config.election_interval_ms, Event::Election);
+        simulation.net.schedule(start, Event::Transfers);
+        for index in 0..simulation.validators.len() {
+            simulation.net.schedule(start + REPAIR_INTERVAL_MS, Event::RepairTick(index));
+        }
+        simulation
+    }
+
+    /// Runs until `slots` blocks are produced, then long enough for votes
+    /// and repairs still in flight to land.
+    pub fn run(mut self) -> SimReport {
+        let faults = &self.config.faults;
+        let settle_ms = 2 * REPAIR_INTERVAL_MS + 4 * faults.max_delay_ms.max(faults.min_delay_ms);
+        let mut end_ms = u64::MAX;
+        while let Some((now_ms, event)) = self.net.next_event(end_ms) {
+            self.trace.update(now_ms.to_be_bytes());
+            let clock = SimClock { now_ms };
+            match event {
+                Event::Deliver { from, to, message } => {
+                    trace!("{} ms: {} -> {}", now_ms - START_MS, from, to);
+                    self.trace.update(from.as_bytes());
+                    self.trace.update(to.as_bytes());
+                    self.trace.update(message_hash(&message));
+                    self.deliver(clock, &from, &to, *message);
+                }
+                Event::Slot => {
+                    self.trace.update(b"slot");
+                    self.leader.produce_block(&clock, &mut self.net);
+                    self.produced += 1;
+                    if self.produced < self.config.slots {
+                        self.net.schedule(now_ms + self.config.slot_duration_ms, Event::Slot);
+                    } else {
+                        end_ms = now_ms + settle_ms;
+                    }
+                }
+                Event::Election => {
+                    let leader = &self.leader;
+                    let elected = elect_leader(&leader.stakes, &leader.genesis.epoch_schedule, leader.blockstore.height(), &mut self.rng);
+                    debug!("Elected {:?}", elected);
+                    self.trace.update(elected.unwrap_or_default().as_bytes());
+                    self.elections += 1;
+                    self.net.schedule(now_ms + self.config.election_interval_ms, Event::Election);
+                }
+                Event::Transfers => {
+                    self.trace.update(b"transfers");
+                    self.submit_transfers();
+                    if self.produced < self.config.slots {
+                        self.net.schedule(now_ms + self.config.slot_duration_ms, Event::Transfers);
+                    }
+                }
+                Event::RepairTick(index) => {
+                    self.validators[index].repair(&clock, &mut self.net);
+                    self.net.schedule(now_ms + REPAIR_INTERVAL_MS, Event::RepairTick(index));
+                }
+            }
+        }
+        self.report()
+    }
+
+    fn deliver(&mut self, clock: SimClock, from: &str, to: &str, message: Message) {
+        if to == self.leader.identity.pubkey() {
+            self.leader.handle(&clock, from, message, &mut self.net);
+        } else if let Some(validator) = self.validators.iter_mut().find(|validator| validator.identity.pubkey() == to) {
+            validator.handle(&clock, message, &mut self.net);
+        }
+    }
+
+    /// Random transfers between the client's accounts, sized from the
+    /// leader's view of their balances as a wallet reading RPC would, and now
+    /// and then a delegation to a validator.
+    fn submit_transfers(&mut self) {
+        let blockhash = self
+            .leader
+            .blockstore
+            .tip_hash()
+            .unwrap_or_else(|| hex::encode(self.genesis.hash()));
+        let leader_id = self.leader.identity.pubkey();
+        for _ in 0..self.config.transfers_per_slot {
+            let funded: Vec<&Keypair> = self
+                .accounts
+                .iter()
+                .filter(|account| self.leader.balances.get(&account.pubkey()).is_some_and(|&balance| balance > 0))
+                .collect();
+            if funded.is_empty() {
+                return;
+            }
+            let sender = funded[self.rng.gen_range(0..funded.len())];
+            let balance = self.leader.balances[&sender.pubkey()];
+            let receiver = self.accounts[self.rng.gen_range(0..self.accounts.len())].pubkey();
+            let amount = self.rng.gen_range(1..=balance.min(MAX_TRANSFER));
+            let transaction = Transaction::new(sender, receiver, amount, blockhash.clone());
+            self.net.send(CLIENT, &leader_id, Message::Transaction(transaction));
+        }
+        if self.rng.gen_bool(DELEGATION_CHANCE) {
+            let staker = &self.accounts[0];
+            let validator = &self.validators[self.rng.gen_range(0..self.validators.len())];
+            let amount = self.rng.gen_range(1..=MAX_TRANSFER);
+            let stake = Stake::new(staker, validator.identity.pubkey(), amount, blockhash);
+            self.net.send(CLIENT, &leader_id, Message::StakeTokens(stake));
+        }
+    }
+
+    fn check_invariants(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+        let leader = &self.leader;
+        for validator in &self.validators {
+            for height in 1..=validator.chain.blockstore.height() {
+                let ours = validator.chain.blockstore.get(height).map(|block| &block.block_hash);
+                let leaders = leader.blockstore.get(height).map(|block| &block.block_hash);
+                if ours != leaders {
+                    violations.push(format!(
+                        "validator {} has block {:?} at height {}, the leader has {:?}",
+                        validator.identity.pubkey(),
+                        ours,
+                        height,
+                        leaders
+                    ));
+                    break;
+                }
+            }
+        }
+        if let Some(slot) = leader.confirmations.latest_confirmed() {
+            if slot > leader.blockstore.height() {
+                violations.push(format!("slot {} confirmed beyond the leader's height {}", slot, leader.blockstore.height()));
+            }
+        }
+        let supply = leader.balances.values().sum::<u64>() + leader.stakes.total_staked() + leader.burned;
+        let expected = self.genesis.total_supply() + leader.issued;
+        if supply != expected {
+            violations.push(format!("total supply is {}, genesis and rewards make {}", supply, expected));
+        }
+        violations
+    }
+
+    fn report(mut self) -> SimReport {
+        let violations = self.check_invariants();
+        self.trace.update(self.leader.parent_hash);
+        let blocks: Vec<_> = (1..=self.leader.blockstore.height())
+            .filter_map(|height| self.leader.blockstore.get(height))
+            .collect();
+        SimReport {
+            seed: self.config.seed,
+            leader_height: self.leader.blockstore.height(),
+            confirmed_slots: self.leader.confirmations.confirmed_count,
+            validator_heights: self.validators.iter().map(|validator| validator.chain.blockstore.height()).collect(),
+            transactions: blocks.iter().map(|block| block.transactions.len()).sum(),
+            stake_instructions: blocks.iter().map(|block| block.stake_instructions.len()).sum(),
+            rewards: self.leader.issued,
+            elections: self.elections,
+            messages_sent: self.net.sent,
+            messages_dropped: self.net.dropped,
+            fingerprint: hex::encode(&self.trace.finalize()[..8]),
+            violations,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::HashMap;
+use crate::block::{BlockHeader, Vote};
+use crate::keypair::{self, Keypair};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SlashingConfig {
+    /// Fraction of the offender's delegated stake that is burned.
+    pub slash_fraction: f64,
+}
+
+impl Default for SlashingConfig {
+    fn default() -> Self {
+        SlashingConfig { slash_fraction: 0.05 }
+    }
+}
+
+/// Two conflicting items signed by the same key for the same slot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Offense {
+    DuplicateBlock { first: BlockHeader, second: BlockHeader },
+    DoubleVote { first: Vote, second: Vote },
+}
+
+impl Offense {
+    pub fn offender(&self) -> &str {
+        match self {
+            Offense::DuplicateBlock { first, .. } => &first.leader,
+            Offense::DoubleVote { first, .. } => &first.voter,
+        }
+    }
+
+    pub fn slot(&self) -> u64 {
+        match self {
+            Offense::DuplicateBlock { first, .. } => first.block_height,
+            Offense::DoubleVote { first, .. } => first.block_height,
+        }
+    }
+
+    /// Both halves must be validly signed by the offender, for the same slot, with different hashes.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Offense::DuplicateBlock { first, second } => {
+                first.leader == second.leader
+                    && first.block_height == second.block_height
+                    && first.block_hash != second.block_hash
+                    && first.verify_signature()
+                    && second.verify_signature()
+            }
+            Offense::DoubleVote { first, second } => {
+                first.voter == second.voter
+                    && first.block_height == second.block_height
+                    && first.block_hash != second.block_hash
+                    && first.verify_signature()
+                    && second.verify_signature()
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Offense::DuplicateBlock { .. } => "duplicate_block",
+            Offense::DoubleVote { .. } => "double_vote",
+        }
+    }
+}
+
+/// An offense as reported and signed by the node that observed it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Evidence {
+    pub offense: Offense,
+    pub reporter: String,
+    pub signature: Vec<u8>,
+}
+
+impl Evidence {
+    pub fn new(offense: Offense, reporter: &Keypair) -> Self {
+        let signature = reporter.sign(&serde_json::to_vec(&offense).unwrap());
+        Evidence {
+            offense,
+            reporter: reporter.pubkey(),
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        self.offense.is_valid()
+            && keypair::verify(&self.reporter, &serde_json::to_vec(&self.offense).unwrap(), &self.signature)
+    }
+
+    /// One offender can only be punished once per slot and offense kind,
+    /// however many nodes report it.
+    pub fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.offense.kind().as_bytes());
+        hasher.update(self.offense.offender().as_bytes());
+        hasher.update(self.offense.slot().to_be_bytes());
+        bs58::encode(hasher.finalize()).into_string()
+    }
+}
+
+/// Remembers the first block and vote seen per signer and slot so a second,
+/// different one can be turned into an offense.
+#[derive(Debug, Default)]
+pub struct ConflictDetector {
+    blocks: HashMap<(String, u64), BlockHeader>,
+    votes: HashMap<(String, u64), Vote>,
+}
+
+impl ConflictDetector {
+    pub fn observe_block(&mut self, header: BlockHeader) -> Option<Offense> {
+        let key = (header.leader.clone(), header.block_height);
+        match self.blocks.get(&key) {
+            Some(first) if first.block_hash != header.block_hash => Some(Offense::DuplicateBlock {
+                first: first.clone(),
+                second: header,
+            }),
+            Some(_) => None,
+            None => {
+                self.blocks.insert(key, header);
+                None
+            }
+        }
+    }
+
+    pub fn observe_vote(&mut self, vote: Vote) -> Option<Offense> {
+        let key = (vote.voter.clone(), vote.block_height);
+        match self.votes.get(&key) {
+            Some(first) if first.block_hash != vote.block_hash => Some(Offense::DoubleVote {
+                first: first.clone(),
+                second: vote,
+            }),
+            Some(_) => None,
+            None => {
+                self.votes.insert(key, vote);
+                None
+            }
+        }
+    }
+
+    /// Forgets everything below `slot` so memory stays bounded.
+    pub fn purge_below(&mut self, slot: u64) {
+        self.blocks.retain(|(_, height), _| *height >= slot);
+        self.votes.retain(|(_, height), _| *height >= slot);
+    }
+}
+
+/// Evidence waiting to be included in a block, plus the offenses already
+/// acted upon by id and slot. Once those are purged below a slot, evidence
+/// for earlier slots is refused outright so nothing is slashed twice.
+#[derive(Debug, Default)]
+pub struct EvidencePool {
+    pending: Vec<Evidence>,
+    processed: HashMap<String, u64>,
+    oldest_slot: u64,
+}
+
+impl EvidencePool {
+    /// Returns false for invalid, already known or too old evidence.
+    pub fn add(&mut self, evidence: Evidence) -> bool {
+        let slot = evidence.offense.slot();
+        if slot < self.oldest_slot || !evidence.verify() {
+            return false;
+        }
+        let id = evidence.id();
+        if self.processed.contains_key(&id) {
+            return false;
+        }
+        self.processed.insert(id, slot);
+        self.pending.push(evidence);
+        true
+    }
+
+    pub fn take_pending(&mut self) -> Vec<Evidence> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Forgets offenses below `slot` and refuses evidence for them from now on.
+    pub fn purge_below(&mut self, slot: u64) {
+        self.oldest_slot = self.oldest_slot.max(slot);
+        let oldest_slot = self.oldest_slot;
+        self.processed.retain(|_, offense_slot| *offense_slot >= oldest_slot);
+    }
+}
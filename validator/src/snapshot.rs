@@ -0,0 +1,140 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::poh_handler::PohCheckpoint;
+use crate::stake::StakeAccounts;
+
+pub const DEFAULT_SNAPSHOT_INTERVAL_SLOTS: u64 = 100;
+/// Older archives beyond this many are deleted after each new snapshot.
+pub const MAX_SNAPSHOTS_RETAINED: usize = 2;
+/// Decompressed snapshots larger than this are rejected.
+pub const MAX_SNAPSHOT_BYTES: u64 = 256 * 1024 * 1024;
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Full account state at a slot, enough for a node to start there instead
+/// of replaying the chain from genesis.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub genesis_hash: String,
+    pub slot: u64,
+    pub block_hash: String,
+    pub poh: PohCheckpoint,
+    pub balances: BTreeMap<String, u64>,
+    pub stakes: StakeAccounts,
+    pub vote_credits: BTreeMap<String, u64>,
+}
+
+/// A gzip-compressed snapshot and the bs58 SHA-256 of the compressed bytes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotArchive {
+    pub slot: u64,
+    pub hash: String,
+    pub data: Vec<u8>,
+}
+
+fn archive_hash(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    bs58::encode(hasher.finalize()).into_string()
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl Snapshot {
+    pub fn archive(&self) -> io::Result<SnapshotArchive> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(self).unwrap())?;
+        let data = encoder.finish()?;
+        Ok(SnapshotArchive {
+            slot: self.slot,
+            hash: archive_hash(&data),
+            data,
+        })
+    }
+}
+
+impl SnapshotArchive {
+    /// Checks the hash before decompressing, so a corrupt archive is
+    /// rejected without being parsed. The hash travels with the data and
+    /// proves nothing about who made it: an archive is only as trusted as
+    /// the connection it came over.
+    pub fn unpack(&self) -> io::Result<Snapshot> {
+        if archive_hash(&self.data) != self.hash {
+            return Err(invalid_data("snapshot hash mismatch"));
+        }
+        let mut json = Vec::new();
+        GzDecoder::new(self.data.as_slice()).take(MAX_SNAPSHOT_BYTES + 1).read_to_end(&mut json)?;
+        if json.len() as u64 > MAX_SNAPSHOT_BYTES {
+            return Err(invalid_data(format!("snapshot is larger than {} bytes", MAX_SNAPSHOT_BYTES)));
+        }
+        let snapshot: Snapshot = serde_json::from_slice(&json).map_err(|e| invalid_data(e.to_string()))?;
+        if snapshot.slot != self.slot {
+            return Err(invalid_data("snapshot slot mismatch"));
+        }
+        Ok(snapshot)
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("snapshot-{}-{}.json.gz", self.slot, self.hash)
+    }
+
+    /// Writes the archive under `<ledger_dir>/snapshots` and prunes old ones.
+    pub fn save(&self, ledger_dir: &Path) -> io::Result<PathBuf> {
+        let dir = ledger_dir.join(SNAPSHOTS_DIR);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(self.file_name());
+        fs::write(&path, &self.data)?;
+
+        for (_, _, old) in list_snapshots(&dir)?.into_iter().rev().skip(MAX_SNAPSHOTS_RETAINED) {
+            fs::remove_file(old)?;
+        }
+        Ok(path)
+    }
+
+    /// The newest archive under `<ledger_dir>/snapshots`, if any.
+    pub fn latest(ledger_dir: &Path) -> io::Result<Option<SnapshotArchive>> {
+        let dir = ledger_dir.join(SNAPSHOTS_DIR);
+        if !dir.exists() {
+            return Ok(None);
+        }
+        let (slot, hash, path) = match list_snapshots(&dir)?.pop() {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        Ok(Some(SnapshotArchive {
+            slot,
+            hash,
+            data: fs::read(path)?,
+        }))
+    }
+}
+
+/// Snapshot files in `dir` as (slot, hash, path), oldest first.
+fn list_snapshots(dir: &Path) -> io::Result<Vec<(u64, String, PathBuf)>> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let parsed = name
+            .strip_prefix("snapshot-")
+            .and_then(|rest| rest.strip_suffix(".json.gz"))
+            .and_then(|rest| rest.split_once('-'))
+            .and_then(|(slot, hash)| Some((slot.parse::<u64>().ok()?, hash.to_string())));
+        if let Some((slot, hash)) = parsed {
+            snapshots.push((slot, hash, path));
+        }
+    }
+    snapshots.sort_by_key(|(slot, _, _)| *slot);
+    Ok(snapshots)
+}
@@ -0,0 +1,415 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::genesis::GenesisConfig;
+use crate::keypair::{self, Keypair};
+
+/// Epochs a deactivated stake must wait before it can be withdrawn.
+pub const DEACTIVATION_COOLDOWN_EPOCHS: u64 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StakeAccount {
+    pub staker: String,
+    pub validator_id: String,
+    pub amount: u64,
+    pub activation_epoch: u64,
+    pub deactivation_epoch: Option<u64>,
+}
+
+impl StakeAccount {
+    /// Stake counts towards the leader schedule from its activation epoch
+    /// until the epoch it was deactivated at.
+    pub fn is_active(&self, epoch: u64) -> bool {
+        epoch >= self.activation_epoch && self.deactivation_epoch.is_none_or(|d| epoch < d)
+    }
+
+    pub fn is_withdrawable(&self, epoch: u64) -> bool {
+        match self.deactivation_epoch {
+            Some(d) => epoch >= d + DEACTIVATION_COOLDOWN_EPOCHS,
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StakeError {
+    ZeroAmount,
+    InsufficientFunds { balance: u64, amount: u64 },
+    AccountNotFound(String),
+    AccountExists(String),
+    WrongStaker(String),
+    AlreadyDeactivated(String),
+    NotDeactivated(String),
+    CoolingDown { stake_account: String, withdrawable_epoch: u64 },
+}
+
+impl fmt::Display for StakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StakeError::ZeroAmount => write!(f, "stake amount must be greater than zero"),
+            StakeError::InsufficientFunds { balance, amount } => {
+                write!(f, "insufficient funds: balance {} < amount {}", balance, amount)
+            }
+            StakeError::AccountNotFound(id) => write!(f, "stake account {} not found", id),
+            StakeError::AccountExists(id) => write!(f, "stake account {} already exists", id),
+            StakeError::WrongStaker(id) => write!(f, "stake account {} belongs to another staker", id),
+            StakeError::AlreadyDeactivated(id) => write!(f, "stake account {} is already deactivated", id),
+            StakeError::NotDeactivated(id) => write!(f, "stake account {} must be deactivated first", id),
+            StakeError::CoolingDown { stake_account, withdrawable_epoch } => write!(
+                f,
+                "stake account {} is cooling down until epoch {}",
+                stake_account, withdrawable_epoch
+            ),
+        }
+    }
+}
+
+/// Delegates `amount` of the staker's balance to a validator. Signed by the
+/// staker over a recent blockhash, like a transfer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stake {
+    pub staker: String,
+    pub validator_id: String,
+    pub amount: u64,
+    pub recent_blockhash: String,
+    pub signature: Vec<u8>,
+}
+
+impl Stake {
+    pub fn new(staker: &Keypair, validator_id: String, amount: u64, recent_blockhash: String) -> Self {
+        let mut stake = Stake {
+            staker: staker.pubkey(),
+            validator_id,
+            amount,
+            recent_blockhash,
+            signature: Vec::new(),
+        };
+        stake.signature = staker.sign(&stake.signing_message());
+        stake
+    }
+
+    fn signing_message(&self) -> Vec<u8> {
+        keypair::signing_message(
+            b"delegate",
+            &[
+                self.staker.as_bytes(),
+                self.validator_id.as_bytes(),
+                &self.amount.to_be_bytes(),
+                self.recent_blockhash.as_bytes(),
+            ],
+        )
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        keypair::verify(&self.staker, &self.signing_message(), &self.signature)
+    }
+
+    /// The address of the stake account this delegation creates. It comes
+    /// from the signature, so the staker knows it before the delegation
+    /// lands in a block and every node replaying the block agrees on it.
+    pub fn stake_account(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"stake account");
+        hasher.update(&self.signature);
+        bs58::encode(hasher.finalize()).into_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StakeAction {
+    Deactivate,
+    Withdraw,
+}
+
+impl StakeAction {
+    fn tag(self) -> &'static [u8] {
+        match self {
+            StakeAction::Deactivate => b"deactivate",
+            StakeAction::Withdraw => b"withdraw",
+        }
+    }
+}
+
+/// Deactivates or withdraws one of the staker's stake accounts. The action
+/// is part of what is signed, so a signed deactivation can't be replayed as
+/// a withdrawal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StakeAccountAction {
+    pub staker: String,
+    pub stake_account: String,
+    pub recent_blockhash: String,
+    pub signature: Vec<u8>,
+}
+
+impl StakeAccountAction {
+    pub fn new(staker: &Keypair, stake_account: String, action: StakeAction, recent_blockhash: String) -> Self {
+        let mut instruction = StakeAccountAction {
+            staker: staker.pubkey(),
+            stake_account,
+            recent_blockhash,
+            signature: Vec::new(),
+        };
+        instruction.signature = staker.sign(&instruction.signing_message(action));
+        instruction
+    }
+
+    fn signing_message(&self, action: StakeAction) -> Vec<u8> {
+        keypair::signing_message(
+            action.tag(),
+            &[self.staker.as_bytes(), self.stake_account.as_bytes(), self.recent_blockhash.as_bytes()],
+        )
+    }
+
+    pub fn verify_signature(&self, action: StakeAction) -> bool {
+        keypair::verify(&self.staker, &self.signing_message(action), &self.signature)
+    }
+}
+
+/// A signed stake operation as it is carried in a block.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StakeInstruction {
+    Delegate(Stake),
+    Deactivate(StakeAccountAction),
+    Withdraw(StakeAccountAction),
+}
+
+impl StakeInstruction {
+    pub fn verify_signature(&self) -> bool {
+        match self {
+            StakeInstruction::Delegate(stake) => stake.verify_signature(),
+            StakeInstruction::Deactivate(action) => action.verify_signature(StakeAction::Deactivate),
+            StakeInstruction::Withdraw(action) => action.verify_signature(StakeAction::Withdraw),
+        }
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        match self {
+            StakeInstruction::Delegate(stake) => &stake.signature,
+            StakeInstruction::Deactivate(action) | StakeInstruction::Withdraw(action) => &action.signature,
+        }
+    }
+
+    pub fn recent_blockhash(&self) -> &str {
+        match self {
+            StakeInstruction::Delegate(stake) => &stake.recent_blockhash,
+            StakeInstruction::Deactivate(action) | StakeInstruction::Withdraw(action) => &action.recent_blockhash,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StakeAccounts {
+    accounts: HashMap<String, StakeAccount>,
+    next_seed: u64,
+    #[serde(default)]
+    slashed: HashSet<String>,
+}
+
+fn stake_account_address(staker: &str, validator_id: &str, seed: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(staker.as_bytes());
+    hasher.update(validator_id.as_bytes());
+    hasher.update(seed.to_be_bytes());
+    bs58::encode(hasher.finalize()).into_string()
+}
+
+impl StakeAccounts {
+    /// Genesis stake is self-delegated by each validator and active from epoch 0.
+    pub fn from_genesis(genesis: &GenesisConfig) -> Self {
+        let mut stakes = StakeAccounts::default();
+        for validator in &genesis.validators {
+            stakes.insert(StakeAccount {
+                staker: validator.identity.clone(),
+                validator_id: validator.identity.clone(),
+                amount: validator.stake,
+                activation_epoch: 0,
+                deactivation_epoch: None,
+            });
+        }
+        stakes
+    }
+
+    fn insert(&mut self, account: StakeAccount) -> String {
+        let address = stake_account_address(&account.staker, &account.validator_id, self.next_seed);
+        self.next_seed += 1;
+        self.accounts.insert(address.clone(), account);
+        address
+    }
+
+    /// Moves `amount` from the staker's balance into a new stake account at
+    /// `stake_account` that activates at the next epoch boundary.
+    pub fn delegate(
+        &mut self,
+        balances: &mut HashMap<String, u64>,
+        stake_account: &str,
+        staker: &str,
+        validator_id: &str,
+        amount: u64,
+        current_epoch: u64,
+    ) -> Result<(), StakeError> {
+        self.check_delegate(balances, stake_account, staker, amount)?;
+        *balances.get_mut(staker).expect("checked above") -= amount;
+        self.accounts.insert(
+            stake_account.to_string(),
+            StakeAccount {
+                staker: staker.to_string(),
+                validator_id: validator_id.to_string(),
+                amount,
+                activation_epoch: current_epoch + 1,
+                deactivation_epoch: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn check_delegate(&self, balances: &HashMap<String, u64>, stake_account: &str, staker: &str, amount: u64) -> Result<(), StakeError> {
+        if amount == 0 {
+            return Err(StakeError::ZeroAmount);
+        }
+        let balance = balances.get(staker).copied().unwrap_or(0);
+        if balance < amount {
+            return Err(StakeError::InsufficientFunds { balance, amount });
+        }
+        if self.accounts.contains_key(stake_account) {
+            return Err(StakeError::AccountExists(stake_account.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Stops the stake counting from the next epoch boundary on.
+    pub fn deactivate(&mut self, stake_account: &str, staker: &str, current_epoch: u64) -> Result<(), StakeError> {
+        self.check_deactivate(stake_account, staker)?;
+        self.accounts.get_mut(stake_account).expect("checked above").deactivation_epoch = Some(current_epoch + 1);
+        Ok(())
+    }
+
+    fn check_deactivate(&self, stake_account: &str, staker: &str) -> Result<(), StakeError> {
+        if self.owned_account(stake_account, staker)?.deactivation_epoch.is_some() {
+            return Err(StakeError::AlreadyDeactivated(stake_account.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns a deactivated stake to the staker's balance once its cooldown has passed.
+    pub fn withdraw(
+        &mut self,
+        balances: &mut HashMap<String, u64>,
+        stake_account: &str,
+        staker: &str,
+        current_epoch: u64,
+    ) -> Result<u64, StakeError> {
+        let amount = self.check_withdraw(stake_account, staker, current_epoch)?;
+        self.accounts.remove(stake_account);
+        *balances.entry(staker.to_string()).or_insert(0) += amount;
+        Ok(amount)
+    }
+
+    /// The amount a withdrawal would return now.
+    fn check_withdraw(&self, stake_account: &str, staker: &str, current_epoch: u64) -> Result<u64, StakeError> {
+        let account = self.owned_account(stake_account, staker)?;
+        let deactivation_epoch = match account.deactivation_epoch {
+            Some(epoch) => epoch,
+            None => return Err(StakeError::NotDeactivated(stake_account.to_string())),
+        };
+        if !account.is_withdrawable(current_epoch) {
+            return Err(StakeError::CoolingDown {
+                stake_account: stake_account.to_string(),
+                withdrawable_epoch: deactivation_epoch + DEACTIVATION_COOLDOWN_EPOCHS,
+            });
+        }
+        Ok(account.amount)
+    }
+
+    /// Fails the way `apply` would, without changing anything. Returns the
+    /// amount a withdrawal would move, or zero.
+    pub fn check(&self, balances: &HashMap<String, u64>, instruction: &StakeInstruction, current_epoch: u64) -> Result<u64, StakeError> {
+        match instruction {
+            StakeInstruction::Delegate(stake) => {
+                self.check_delegate(balances, &stake.stake_account(), &stake.staker, stake.amount).map(|()| 0)
+            }
+            StakeInstruction::Deactivate(action) => self.check_deactivate(&action.stake_account, &action.staker).map(|()| 0),
+            StakeInstruction::Withdraw(action) => self.check_withdraw(&action.stake_account, &action.staker, current_epoch),
+        }
+    }
+
+    /// Carries out an instruction from a block. Signatures are the caller's
+    /// to check.
+    pub fn apply(&mut self, balances: &mut HashMap<String, u64>, instruction: &StakeInstruction, current_epoch: u64) -> Result<(), StakeError> {
+        match instruction {
+            StakeInstruction::Delegate(stake) => {
+                self.delegate(balances, &stake.stake_account(), &stake.staker, &stake.validator_id, stake.amount, current_epoch)
+            }
+            StakeInstruction::Deactivate(action) => self.deactivate(&action.stake_account, &action.staker, current_epoch),
+            StakeInstruction::Withdraw(action) => {
+                self.withdraw(balances, &action.stake_account, &action.staker, current_epoch).map(drop)
+            }
+        }
+    }
+
+    fn owned_account(&self, stake_account: &str, staker: &str) -> Result<&StakeAccount, StakeError> {
+        let account = self
+            .accounts
+            .get(stake_account)
+            .ok_or_else(|| StakeError::AccountNotFound(stake_account.to_string()))?;
+        if account.staker != staker {
+            return Err(StakeError::WrongStaker(stake_account.to_string()));
+        }
+        Ok(account)
+    }
+
+    pub fn get(&self, stake_account: &str) -> Option<&StakeAccount> {
+        self.accounts.get(stake_account)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&String, &StakeAccount)> {
+        self.accounts.iter()
+    }
+
+    /// Burns `fraction` of every stake delegated to `validator_id` and drops the
+    /// validator from the leader schedule. Returns the amount burned.
+    pub fn slash(&mut self, validator_id: &str, fraction: f64) -> u64 {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let mut burned = 0;
+        for account in self.accounts.values_mut().filter(|account| account.validator_id == validator_id) {
+            let penalty = (account.amount as f64 * fraction) as u64;
+            account.amount -= penalty;
+            burned += penalty;
+        }
+        self.slashed.insert(validator_id.to_string());
+        burned
+    }
+
+    pub fn is_slashed(&self, validator_id: &str) -> bool {
+        self.slashed.contains(validator_id)
+    }
+
+    /// Stake that counts at `epoch`: active and not delegated to a slashed
+    /// validator. The leader schedule and rewards both use this.
+    pub fn active_accounts(&self, epoch: u64) -> impl Iterator<Item = &StakeAccount> {
+        let slashed = &self.slashed;
+        self.accounts
+            .values()
+            .filter(move |account| account.is_active(epoch) && !slashed.contains(&account.validator_id))
+    }
+
+    pub fn active_accounts_mut(&mut self, epoch: u64) -> impl Iterator<Item = &mut StakeAccount> {
+        let slashed = &self.slashed;
+        self.accounts
+            .values_mut()
+            .filter(move |account| account.is_active(epoch) && !slashed.contains(&account.validator_id))
+    }
+
+    /// Active stake per validator at `epoch`; this is what the leader schedule is weighted by.
+    pub fn active_stakes(&self, epoch: u64) -> HashMap<String, u64> {
+        let mut stakes = HashMap::new();
+        for account in self.active_accounts(epoch) {
+            *stakes.entry(account.validator_id.clone()).or_insert(0) += account.amount;
+        }
+        stakes
+    }
+
+    pub fn total_staked(&self) -> u64 {
+        self.accounts.values().map(|account| account.amount).sum()
+    }
+}
@@ -1,8 +1,43 @@
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use crate::keypair::{self, Keypair};
+use crate::metrics::metrics;
+
+/// The most transactions a node holds while they wait for a block.
+pub const MAX_PENDING_TRANSACTIONS: usize = 10_000;
 
 impl Transaction {
+    /// A transfer signed by the sender. `recent_blockhash` must be one of the
+    /// leader's recent block hashes (see `getLatestBlockhash`), which makes
+    /// each signature unique and lets the leader reject replays.
+    pub fn new(sender: &Keypair, receiver: String, amount: u64, recent_blockhash: String) -> Self {
+        let mut transaction = Transaction {
+            sender: sender.pubkey(),
+            receiver,
+            amount,
+            signature: Vec::new(),
+            recent_blockhash,
+        };
+        transaction.signature = sender.sign(&transaction.signing_message());
+        transaction
+    }
+
+    fn signing_message(&self) -> Vec<u8> {
+        keypair::signing_message(
+            b"transfer",
+            &[
+                self.sender.as_bytes(),
+                self.receiver.as_bytes(),
+                &self.amount.to_be_bytes(),
+                self.recent_blockhash.as_bytes(),
+            ],
+        )
+    }
+
+    /// True if the sender signed it.
     pub fn validate(&self) -> bool {
-        !self.signature.is_empty()
+        keypair::verify(&self.sender, &self.signing_message(), &self.signature)
     }
 }
 
@@ -12,16 +47,39 @@ pub struct Transaction {
     pub receiver: String,
     pub amount: u64,
     pub signature: Vec<u8>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub recent_blockhash: String,
 }
 
-pub fn create_transaction(sender: String, receiver: String, amount: u64, signature: Vec<u8>) -> Transaction {
-    Transaction {
-        sender,
-        receiver,
-        amount,
-        signature,
-    }
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    InvalidSignature,
+    InsufficientFunds { balance: u64, amount: u64 },
 }
 
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionError::InvalidSignature => write!(f, "transaction is not signed by its sender"),
+            TransactionError::InsufficientFunds { balance, amount } => {
+                write!(f, "insufficient funds: balance {} < amount {}", balance, amount)
+            }
+        }
+    }
+}
 
-
+/// Moves `amount` from sender to receiver. Balances are left untouched if
+/// the transaction fails.
+pub fn execute_transaction(balances: &mut HashMap<String, u64>, transaction: &Transaction) -> Result<(), TransactionError> {
+    let _timer = metrics().transaction_execution_seconds.start_timer();
+    if !transaction.validate() {
+        return Err(TransactionError::InvalidSignature);
+    }
+    let balance = balances.get(&transaction.sender).copied().unwrap_or(0);
+    if balance < transaction.amount {
+        return Err(TransactionError::InsufficientFunds { balance, amount: transaction.amount });
+    }
+    balances.insert(transaction.sender.clone(), balance - transaction.amount);
+    *balances.entry(transaction.receiver.clone()).or_insert(0) += transaction.amount;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use validator::block::Block;
+use validator::blockstore::Blockstore;
+use validator::consensus::build_block;
+use validator::keypair::Keypair;
+use validator::sim::SimClock;
+use validator::transaction::Transaction;
+
+fn temp_dir(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{}", name, std::process::id()))
+}
+
+/// A block on `parent` carrying `transaction`, which its sender can pay for.
+fn block(leader: &Keypair, parent: Option<&Block>, transaction: Transaction) -> Block {
+    let (parent_hash, parent_height) = match parent {
+        Some(parent) => (hex::decode(&parent.block_hash).unwrap().try_into().unwrap(), parent.block_height),
+        None => ([0; 32], 0),
+    };
+    let mut balances = HashMap::from([(transaction.sender.clone(), 100)]);
+    let clock = SimClock { now_ms: (parent_height + 1) * 1000 };
+    build_block(&clock, leader, parent_hash, parent_height, vec![transaction], &mut balances, Vec::new(), Vec::new())
+}
+
+#[test]
+fn finds_transactions_by_signature_across_reopen_and_base() {
+    let (leader, alice) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let first = Transaction::new(&alice, "bob".to_string(), 1, "blockhash".to_string());
+    let second = Transaction::new(&alice, "bob".to_string(), 2, "blockhash".to_string());
+    let dir = temp_dir("blockstore");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut blockstore = Blockstore::open(&dir).unwrap();
+    let one = block(&leader, None, first.clone());
+    let two = block(&leader, Some(&one), second.clone());
+    blockstore.insert(one.clone()).unwrap();
+    blockstore.insert(two.clone()).unwrap();
+    assert_eq!(blockstore.transaction_slot(&first.signature), Some(1));
+    assert_eq!(blockstore.find_transaction(&second.signature).map(|(block, _)| block.block_height), Some(2));
+    assert_eq!(blockstore.transaction_slot(b"unknown"), None);
+
+    // The index is rebuilt from the ledger and forgets what the base drops
+    let mut reopened = Blockstore::open(&dir).unwrap();
+    assert_eq!(reopened.transaction_slot(&second.signature), Some(2));
+    reopened.set_base(1, one.block_hash);
+    assert_eq!(reopened.transaction_slot(&first.signature), None);
+    assert_eq!(reopened.transaction_slot(&second.signature), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
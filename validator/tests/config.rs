@@ -0,0 +1,90 @@
+use clap::Parser;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use validator::config::{check_distinct_addrs, to_toml, ValidatorArgs, ValidatorConfig, DEFAULT_LEADER_ADDR};
+use validator::logging::LogFormat;
+
+fn temp_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{}", name, std::process::id()))
+}
+
+fn write_config(name: &str, toml: &str) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::write(&path, toml).unwrap();
+    path
+}
+
+fn resolve(flags: &[&str]) -> std::io::Result<ValidatorConfig> {
+    ValidatorArgs::try_parse_from(std::iter::once("validator").chain(flags.iter().copied())).unwrap().resolve()
+}
+
+#[test]
+fn flags_override_the_file_which_overrides_defaults() {
+    let path = write_config(
+        "precedence.toml",
+        "gossip_addr = \"127.0.0.1:9001\"\ngossip_fanout = 3\nlog_format = \"json\"\n",
+    );
+    let config = resolve(&["--config", path.to_str().unwrap(), "--gossip-fanout", "5", "--ledger", "ledger"]).unwrap();
+
+    // Flag beats file
+    assert_eq!(config.gossip_fanout, 5);
+    // File beats default
+    assert_eq!(config.gossip_addr, "127.0.0.1:9001".parse().unwrap());
+    assert_eq!(config.log_format, LogFormat::Json);
+    // Flag beats default
+    assert_eq!(config.ledger.as_deref(), Some(Path::new("ledger")));
+    // Default stays when neither sets it
+    assert_eq!(config.leader_addr, DEFAULT_LEADER_ADDR.parse().unwrap());
+    assert_eq!(config.metrics_addr, None);
+}
+
+#[test]
+fn no_file_gives_the_defaults() {
+    let config = resolve(&[]).unwrap();
+    assert_eq!(to_toml(&config), to_toml(&ValidatorConfig::default()));
+}
+
+#[test]
+fn printed_config_loads_back() {
+    let config = resolve(&["--metrics-addr", "127.0.0.1:9100", "--log", "debug"]).unwrap();
+    let path = write_config("printed.toml", &to_toml(&config));
+    let reloaded = resolve(&["--config", path.to_str().unwrap()]).unwrap();
+    assert_eq!(to_toml(&reloaded), to_toml(&config));
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let path = write_config("typo.toml", "gossip_fanot = 3\n");
+    let error = resolve(&["--config", path.to_str().unwrap()]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("gossip_fanot"), "{}", error);
+}
+
+#[test]
+fn missing_file_keeps_its_error_kind() {
+    let error = resolve(&["--config", temp_path("missing.toml").to_str().unwrap()]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn validates_after_merging() {
+    let path = write_config("zero-fanout.toml", "gossip_fanout = 0\n");
+    assert!(resolve(&["--config", path.to_str().unwrap()]).is_err());
+    // A flag can fix what the file got wrong
+    assert!(resolve(&["--config", path.to_str().unwrap(), "--gossip-fanout", "2"]).is_ok());
+
+    let error = resolve(&["--gossip-addr", "127.0.0.1:9100", "--metrics-addr", "127.0.0.1:9100"]).unwrap_err();
+    assert!(error.to_string().contains("gossip_addr and metrics_addr"), "{}", error);
+}
+
+#[test]
+fn addrs_must_be_distinct() {
+    let a = Some("127.0.0.1:1".parse().unwrap());
+    let b = Some("127.0.0.1:2".parse().unwrap());
+    assert!(check_distinct_addrs(&[]).is_ok());
+    assert!(check_distinct_addrs(&[("a", a), ("b", b), ("c", None), ("d", None)]).is_ok());
+
+    let error = check_distinct_addrs(&[("a", a), ("b", b), ("c", a)]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "a and c are both 127.0.0.1:1");
+}
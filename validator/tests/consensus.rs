@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use validator::block::{Block, Vote};
+use validator::blockstore::Blockstore;
+use validator::consensus::{build_block, ConfirmationTracker, MAX_TRACKED_SLOTS};
+use validator::keypair::Keypair;
+use validator::sim::SimClock;
+
+/// A blockstore holding an empty chain of `length` blocks.
+fn ledger(length: u64) -> Blockstore {
+    let leader = Keypair::from_seed([1; 32]);
+    let mut blockstore = Blockstore::in_memory();
+    let mut parent_hash = [0; 32];
+    for parent_height in 0..length {
+        let clock = SimClock { now_ms: (parent_height + 1) * 1000 };
+        let block = build_block(&clock, &leader, parent_hash, parent_height, Vec::new(), &mut HashMap::new(), Vec::new(), Vec::new());
+        parent_hash.copy_from_slice(&hex::decode(&block.block_hash).unwrap());
+        blockstore.insert(block).unwrap();
+    }
+    blockstore
+}
+
+fn vote(blockstore: &Blockstore, slot: u64, voter: &Keypair) -> Vote {
+    Vote::new(blockstore.get(slot).unwrap(), voter)
+}
+
+fn voters() -> (Vec<Keypair>, HashMap<String, u64>) {
+    let voters: Vec<Keypair> = (1..=4).map(|seed| Keypair::from_seed([seed; 32])).collect();
+    let stakes = voters.iter().map(|voter| (voter.pubkey(), 10)).collect();
+    (voters, stakes)
+}
+
+#[test]
+fn confirms_past_two_thirds_of_stake() {
+    let blockstore = ledger(3);
+    let (voters, stakes) = voters();
+    let mut tracker = ConfirmationTracker::default();
+
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 2, &voters[0]), &blockstore, &stakes), Some(false));
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 2, &voters[1]), &blockstore, &stakes), Some(false));
+    // A repeated vote adds no stake
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 2, &voters[1]), &blockstore, &stakes), Some(false));
+    assert!(!tracker.is_confirmed(2));
+
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 2, &voters[2]), &blockstore, &stakes), Some(true));
+    assert!(tracker.is_confirmed(2));
+    assert_eq!(tracker.latest_confirmed(), Some(2));
+    // Later votes don't confirm it again
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 2, &voters[3]), &blockstore, &stakes), Some(false));
+    assert_eq!(tracker.confirmed_count, 1);
+}
+
+#[test]
+fn exactly_two_thirds_is_not_enough() {
+    let blockstore = ledger(1);
+    let voters: Vec<Keypair> = (1..=3).map(|seed| Keypair::from_seed([seed; 32])).collect();
+    let stakes: HashMap<String, u64> = voters.iter().map(|voter| (voter.pubkey(), 10)).collect();
+    let mut tracker = ConfirmationTracker::default();
+
+    tracker.record_vote(&vote(&blockstore, 1, &voters[0]), &blockstore, &stakes);
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 1, &voters[1]), &blockstore, &stakes), Some(false));
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 1, &voters[2]), &blockstore, &stakes), Some(true));
+}
+
+#[test]
+fn unstaked_votes_count_for_nothing() {
+    let blockstore = ledger(1);
+    let (voters, _) = voters();
+    let mut tracker = ConfirmationTracker::default();
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 1, &voters[0]), &blockstore, &HashMap::new()), Some(false));
+
+    let mut tracker = ConfirmationTracker::default();
+    let stakes = HashMap::from([(voters[0].pubkey(), 10)]);
+    let outsider = Keypair::from_seed([9; 32]);
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 1, &outsider), &blockstore, &stakes), Some(false));
+}
+
+#[test]
+fn rejects_votes_for_blocks_not_in_the_ledger() {
+    let blockstore = ledger(3);
+    let (voters, stakes) = voters();
+    let mut tracker = ConfirmationTracker::default();
+    for voter in &voters[..3] {
+        tracker.record_vote(&vote(&blockstore, 1, voter), &blockstore, &stakes);
+    }
+    assert!(tracker.is_confirmed(1));
+
+    let mut future = vote(&blockstore, 3, &voters[0]);
+    future.block_height = u64::MAX;
+    assert_eq!(tracker.record_vote(&future, &blockstore, &stakes), None);
+    let other_fork = Vote::new(&Block { block_hash: "ff".repeat(32), ..blockstore.get(2).unwrap().clone() }, &voters[0]);
+    assert_eq!(tracker.record_vote(&other_fork, &blockstore, &stakes), None);
+
+    // Neither pruned what was already confirmed
+    assert!(tracker.is_confirmed(1));
+}
+
+#[test]
+fn forgets_slots_far_behind_the_newest_vote() {
+    let newest = MAX_TRACKED_SLOTS + 2;
+    let blockstore = ledger(newest);
+    let (voters, stakes) = voters();
+    let mut tracker = ConfirmationTracker::default();
+    for slot in [1, 2] {
+        for voter in &voters[..3] {
+            tracker.record_vote(&vote(&blockstore, slot, voter), &blockstore, &stakes);
+        }
+    }
+    assert!(tracker.is_confirmed(1) && tracker.is_confirmed(2));
+
+    tracker.record_vote(&vote(&blockstore, newest, &voters[0]), &blockstore, &stakes);
+    assert!(!tracker.is_confirmed(1));
+    assert!(tracker.is_confirmed(2));
+    assert_eq!(tracker.latest_confirmed(), Some(2));
+
+    // A forgotten slot can't be voted for afresh
+    assert!(!tracker.has_voted(1, &voters[0].pubkey()));
+    assert_eq!(tracker.record_vote(&vote(&blockstore, 1, &voters[0]), &blockstore, &stakes), None);
+    assert!(tracker.has_voted(2, &voters[0].pubkey()));
+}
@@ -0,0 +1,95 @@
+use validator::crds::{BloomFilter, ContactInfo, Crds, CrdsValue};
+use validator::keypair::Keypair;
+
+fn value(seed: u32, wallclock: u64) -> CrdsValue {
+    let mut key = [0u8; 32];
+    key[..4].copy_from_slice(&seed.to_le_bytes());
+    let keypair = Keypair::from_seed(key);
+    let contact_info = ContactInfo {
+        identity: keypair.pubkey(),
+        gossip_addr: String::new(),
+        tpu_addr: String::new(),
+        tvu_addr: String::new(),
+        shred_version: 1,
+        wallclock,
+    };
+    CrdsValue::new_signed(contact_info, &keypair)
+}
+
+fn hash(seed: u8) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = seed.wrapping_mul(31).wrapping_add(i as u8 * 7);
+    }
+    hash
+}
+
+#[test]
+fn bloom_filter_contains_what_was_added() {
+    let mut filter = BloomFilter::new(10);
+    for seed in 0..10 {
+        filter.add(&hash(seed));
+    }
+    assert!((0..10).all(|seed| filter.contains(&hash(seed))));
+    assert!(!BloomFilter::new(10).contains(&hash(0)));
+
+    // Survives the trip through a pull request
+    let filter: BloomFilter = serde_json::from_str(&serde_json::to_string(&filter).unwrap()).unwrap();
+    assert!((0..10).all(|seed| filter.contains(&hash(seed))));
+}
+
+#[test]
+fn malformed_bloom_filters_are_rejected() {
+    for json in [
+        r#"{"bits": [], "num_hashes": 3}"#,
+        r#"{"bits": [0], "num_hashes": 0}"#,
+        r#"{"bits": [0], "num_hashes": 5}"#,
+    ] {
+        assert!(serde_json::from_str::<BloomFilter>(json).is_err(), "{}", json);
+    }
+    let oversized = format!(r#"{{"bits": {:?}, "num_hashes": 3}}"#, vec![0u64; (1 << 16) + 1]);
+    assert!(serde_json::from_str::<BloomFilter>(&oversized).is_err());
+
+    assert!(serde_json::from_str::<BloomFilter>(r#"{"bits": [0], "num_hashes": 4}"#).is_ok());
+}
+
+#[test]
+fn empty_store_misses_nothing() {
+    let crds = Crds::default();
+    assert!(crds.missing_from(&crds.build_filter()).is_empty());
+}
+
+#[test]
+fn full_store_only_replaces_stale_identities() {
+    let mut crds = Crds::with_capacity(8);
+    for seed in 0..8 {
+        assert!(crds.insert(value(seed, 100 + seed as u64)));
+    }
+    // Staler than everything we hold, so it doesn't get in
+    let newcomer = value(u32::MAX, 50);
+    assert!(!crds.insert(newcomer.clone()));
+    assert!(crds.get(&newcomer.contact_info.identity).is_none());
+
+    let newcomer = value(u32::MAX, 1_000_000);
+    assert!(crds.insert(newcomer.clone()));
+    assert_eq!(crds.len(), 8);
+    assert!(crds.get(&newcomer.contact_info.identity).is_some());
+    assert!(crds.get(&value(0, 100).contact_info.identity).is_none());
+    // Known identities still update in place
+    assert!(crds.insert(value(1, 2_000_000)));
+    assert_eq!(crds.len(), 8);
+}
+
+#[test]
+fn stale_values_are_purged_except_our_own() {
+    let mut crds = Crds::default();
+    let (me, gone, alive) = (value(0, 10), value(1, 10), value(2, 500));
+    for value in [&me, &gone, &alive] {
+        assert!(crds.insert(value.clone()));
+    }
+    crds.purge_older_than(100, &me.contact_info.identity);
+    assert_eq!(crds.len(), 2);
+    assert!(crds.get(&me.contact_info.identity).is_some());
+    assert!(crds.get(&gone.contact_info.identity).is_none());
+    assert!(crds.get(&alive.contact_info.identity).is_some());
+}
@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+use validator::faucet::{AirdropRequest, AirdropWindow, Faucet, FaucetError, FaucetLimits};
+use validator::keypair::Keypair;
+use validator::rpc_client::RpcClient;
+
+fn limits() -> FaucetLimits {
+    FaucetLimits {
+        per_request: 100,
+        per_address: 150,
+        per_window: 250,
+        window: Duration::from_secs(60),
+    }
+}
+
+fn request(seed: u8, amount: u64) -> AirdropRequest {
+    AirdropRequest { pubkey: Keypair::from_seed([seed; 32]).pubkey(), amount }
+}
+
+/// Checks then records, the way the faucet does once the transfer is sent.
+fn airdrop(window: &mut AirdropWindow, limits: &FaucetLimits, request: &AirdropRequest) -> Result<(), FaucetError> {
+    window.check(limits, request)?;
+    window.record(request);
+    Ok(())
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    let window = AirdropWindow::new(Instant::now());
+    let limits = limits();
+    let not_a_pubkey = AirdropRequest { pubkey: "not a pubkey".to_string(), amount: 1 };
+    assert_eq!(window.check(&limits, &not_a_pubkey), Err(FaucetError::InvalidPubkey("not a pubkey".to_string())));
+    let short = AirdropRequest { pubkey: bs58::encode([1; 31]).into_string(), amount: 1 };
+    assert!(matches!(window.check(&limits, &short), Err(FaucetError::InvalidPubkey(_))));
+    assert_eq!(window.check(&limits, &request(1, 0)), Err(FaucetError::ZeroAmount));
+    assert_eq!(window.check(&limits, &request(1, 101)), Err(FaucetError::RequestTooLarge { amount: 101, cap: 100 }));
+    assert_eq!(window.check(&limits, &request(1, 100)), Ok(()));
+}
+
+#[test]
+fn caps_each_address_and_the_window() {
+    let mut window = AirdropWindow::new(Instant::now());
+    let limits = limits();
+
+    airdrop(&mut window, &limits, &request(1, 100)).unwrap();
+    let pubkey = request(1, 0).pubkey;
+    assert_eq!(
+        airdrop(&mut window, &limits, &request(1, 51)),
+        Err(FaucetError::AddressLimit { pubkey: pubkey.clone(), remaining: 50 })
+    );
+    airdrop(&mut window, &limits, &request(1, 50)).unwrap();
+    assert_eq!(airdrop(&mut window, &limits, &request(1, 1)), Err(FaucetError::AddressLimit { pubkey, remaining: 0 }));
+
+    // Other addresses share what is left of the window
+    airdrop(&mut window, &limits, &request(2, 90)).unwrap();
+    assert_eq!(airdrop(&mut window, &limits, &request(3, 20)), Err(FaucetError::WindowLimit { remaining: 10 }));
+    airdrop(&mut window, &limits, &request(3, 10)).unwrap();
+}
+
+#[test]
+fn limits_reset_with_the_window() {
+    let start = Instant::now();
+    let mut window = AirdropWindow::new(start);
+    let limits = limits();
+    airdrop(&mut window, &limits, &request(1, 100)).unwrap();
+    airdrop(&mut window, &limits, &request(1, 50)).unwrap();
+
+    window.roll(start + Duration::from_secs(59), &limits);
+    assert!(window.check(&limits, &request(1, 1)).is_err());
+    window.roll(start + Duration::from_secs(60), &limits);
+    assert_eq!(window.check(&limits, &request(1, 100)), Ok(()));
+    // A clock that goes backwards doesn't end the window
+    window.roll(start, &limits);
+    airdrop(&mut window, &limits, &request(1, 100)).unwrap();
+}
+
+#[tokio::test]
+async fn refused_airdrops_never_reach_the_leader() {
+    // Nothing listens here, so any request that got as far as the RPC would fail to transfer
+    let rpc = RpcClient::new("127.0.0.1:1".parse().unwrap());
+    let faucet = Faucet::new(Keypair::from_seed([9; 32]), rpc, limits());
+    assert_eq!(faucet.airdrop(&request(1, 0)).await, Err(FaucetError::ZeroAmount));
+    assert!(matches!(faucet.airdrop(&request(1, 1)).await, Err(FaucetError::Transfer(_))));
+
+    // A failed transfer isn't counted against the address
+    for _ in 0..2 {
+        assert!(matches!(faucet.airdrop(&request(1, 100)).await, Err(FaucetError::Transfer(_))));
+    }
+}
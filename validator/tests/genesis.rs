@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use validator::genesis::{EpochSchedule, GenesisAccount, GenesisConfig, GenesisValidator, PohConfig};
+use validator::stake::StakeAccounts;
+
+fn genesis() -> GenesisConfig {
+    GenesisConfig {
+        creation_time: 1_700_000_000,
+        accounts: vec![
+            GenesisAccount { pubkey: "mint".to_string(), balance: 1_000 },
+            GenesisAccount { pubkey: "alice".to_string(), balance: 250 },
+        ],
+        validators: vec![
+            GenesisValidator { identity: "validator_a".to_string(), stake: 5_000, commission: 10 },
+            GenesisValidator { identity: "validator_b".to_string(), stake: 3_000, commission: 0 },
+        ],
+        poh: PohConfig::default(),
+        epoch_schedule: EpochSchedule::default(),
+        inflation: Default::default(),
+        slashing: Default::default(),
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn write_then_load_keeps_the_hash() {
+    let genesis = genesis();
+    let path = temp_path("genesis.json");
+    genesis.write(&path).unwrap();
+    let loaded = GenesisConfig::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded.hash(), genesis.hash());
+    assert_eq!(loaded.validators[0].commission, 10);
+}
+
+#[test]
+fn hash_changes_with_any_field() {
+    let genesis = genesis();
+    let mut other = genesis.clone();
+    other.accounts[1].balance += 1;
+    assert_ne!(other.hash(), genesis.hash());
+
+    let mut other = genesis.clone();
+    other.creation_time += 1;
+    assert_ne!(other.hash(), genesis.hash());
+}
+
+#[test]
+fn load_rejects_invalid_files() {
+    let path = temp_path("invalid-genesis.json");
+    std::fs::write(&path, b"{\"accounts\": []}").unwrap();
+    let error = GenesisConfig::load(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    assert_eq!(GenesisConfig::load(&temp_path("missing-genesis.json")).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn supply_counts_balances_and_stake() {
+    let genesis = genesis();
+    assert_eq!(genesis.total_supply(), 1_000 + 250 + 5_000 + 3_000);
+
+    // Genesis stake is self-delegated, active from epoch 0 and not taken
+    // out of any balance
+    let stakes = StakeAccounts::from_genesis(&genesis);
+    assert_eq!(stakes.total_staked(), 8_000);
+    let active = stakes.active_stakes(0);
+    assert_eq!(active.len(), 2);
+    assert_eq!(active["validator_a"], 5_000);
+    assert_eq!(active["validator_b"], 3_000);
+    assert!(stakes.accounts().all(|(_, account)| account.staker == account.validator_id));
+}
@@ -0,0 +1,18 @@
+use validator::gossip::PruneMessage;
+use validator::keypair::Keypair;
+
+#[test]
+fn prunes_are_signed_by_the_sender() {
+    let keypair = Keypair::from_seed([7; 32]);
+    let prune = PruneMessage::new_signed(&keypair, vec!["origin".to_string()]);
+    assert_eq!(prune.from, keypair.pubkey());
+    assert!(prune.verify());
+
+    // Neither the origins nor the sender can be swapped after signing
+    let mut forged = prune.clone();
+    forged.origins.push("other".to_string());
+    assert!(!forged.verify());
+    let mut forged = prune;
+    forged.from = Keypair::from_seed([8; 32]).pubkey();
+    assert!(!forged.verify());
+}
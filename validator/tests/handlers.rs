@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use validator::block::{Block, Message, Vote};
+use validator::blockstore::Blockstore;
+use validator::consensus::{build_block, ConfirmationTracker, Replayed};
+use validator::genesis::{EpochSchedule, GenesisAccount, GenesisConfig, GenesisValidator, PohConfig};
+use validator::handlers::{apply_block_stake, ValidatorChain, VoteCounter, VoteOutcome};
+use validator::keypair::Keypair;
+use validator::network::Outbox;
+use validator::repair::RepairRequest;
+use validator::sim::SimClock;
+use validator::slashing::{ConflictDetector, Evidence, EvidencePool};
+use validator::stake::{Stake, StakeAccounts, StakeInstruction};
+
+const GENESIS_HASH: [u8; 32] = [7; 32];
+
+/// A signed, empty block on `parent_hash`; the timestamp makes it unique.
+fn block(leader: &Keypair, parent_hash: &str, parent_height: u64, timestamp_secs: u64) -> Block {
+    let clock = SimClock { now_ms: timestamp_secs * 1000 };
+    let parent_hash = hex::decode(parent_hash).unwrap().try_into().unwrap();
+    build_block(&clock, leader, parent_hash, parent_height, Vec::new(), &mut HashMap::new(), Vec::new(), Vec::new())
+}
+
+#[test]
+fn validator_chain_votes_repairs_and_reports() {
+    let (leader, identity) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let clock = SimClock { now_ms: 10_000 };
+    let mut chain = ValidatorChain::new(Blockstore::in_memory(), GENESIS_HASH);
+    let mut outbox = Outbox::default();
+
+    let first = block(&leader, &hex::encode(GENESIS_HASH), 0, 1);
+    assert!(matches!(chain.handle_proposal(&clock, &mut outbox, &identity, &leader.pubkey(), &first), Replayed::Stored));
+    let sent: Vec<_> = outbox.drain().collect();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].0, leader.pubkey());
+    assert!(matches!(&sent[0].1, Message::ConsensusVote(vote) if vote.block_height == 1 && vote.voter == identity.pubkey()));
+
+    // A block past our tip leaves a gap we ask the leader to repair
+    let second = block(&leader, &first.block_hash, 1, 2);
+    let third = block(&leader, &second.block_hash, 2, 3);
+    assert!(matches!(chain.handle_proposal(&clock, &mut outbox, &identity, &leader.pubkey(), &third), Replayed::Gap { .. }));
+    let sent: Vec<_> = outbox.drain().collect();
+    assert!(matches!(
+        &sent[..],
+        [(_, Message::Repair(repair))] if repair.request == RepairRequest::Blocks { start: 2, end: 3 } && repair.verify(clock.now_ms)
+    ));
+
+    // Repaired blocks catch us up, after which there's nothing left to ask for
+    assert_eq!(chain.handle_repaired_blocks(&clock, &mut outbox, &identity, &leader.pubkey(), vec![third, second]).unwrap(), 2);
+    assert_eq!(chain.blockstore.height(), 3);
+    assert_eq!(outbox.drain().count(), 0);
+    assert!(!chain.repair(&clock, &mut outbox, &identity, &leader.pubkey()));
+
+    // A second block for a slot is reported instead of voted on
+    let conflicting = block(&leader, &hex::encode(GENESIS_HASH), 0, 4);
+    assert!(matches!(chain.handle_proposal(&clock, &mut outbox, &identity, &leader.pubkey(), &conflicting), Replayed::Equivocation(_)));
+    let sent: Vec<_> = outbox.drain().collect();
+    assert!(matches!(&sent[..], [(_, Message::Evidence(evidence))] if evidence.verify() && evidence.offense.offender() == leader.pubkey()));
+}
+
+#[test]
+fn vote_counter_confirms_and_reports_double_votes() {
+    let (leader, voter) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let first = block(&leader, &hex::encode(GENESIS_HASH), 0, 1);
+    let mut blockstore = Blockstore::in_memory();
+    blockstore.insert(first.clone()).unwrap();
+    let (mut conflict_detector, mut evidence_pool, mut confirmations) =
+        (ConflictDetector::default(), EvidencePool::default(), ConfirmationTracker::default());
+    let mut vote_credits = HashMap::new();
+    let mut counter = VoteCounter {
+        identity: &leader,
+        blockstore: &blockstore,
+        conflict_detector: &mut conflict_detector,
+        evidence_pool: &mut evidence_pool,
+        confirmations: &mut confirmations,
+        vote_credits: &mut vote_credits,
+    };
+    let late = Keypair::from_seed([4; 32]);
+    let active_stakes = HashMap::from([(voter.pubkey(), 10), (late.pubkey(), 1)]);
+
+    assert_eq!(counter.handle_vote(&Vote::new(&first, &voter), &active_stakes), VoteOutcome::Counted { confirmed: true });
+    // Resending the vote earns nothing more, but a voter that missed the
+    // confirmation still gets its one credit
+    assert_eq!(counter.handle_vote(&Vote::new(&first, &voter), &active_stakes), VoteOutcome::Repeated);
+    assert_eq!(counter.handle_vote(&Vote::new(&first, &late), &active_stakes), VoteOutcome::Counted { confirmed: false });
+    assert_eq!(counter.handle_vote(&Vote::new(&first, &late), &active_stakes), VoteOutcome::Repeated);
+    let unknown = block(&leader, &first.block_hash, 1, 2);
+    assert_eq!(counter.handle_vote(&Vote::new(&unknown, &voter), &active_stakes), VoteOutcome::Unknown);
+
+    let conflicting = block(&leader, &hex::encode(GENESIS_HASH), 0, 3);
+    assert_eq!(counter.handle_vote(&Vote::new(&conflicting, &voter), &active_stakes), VoteOutcome::DoubleVote);
+    let evidence = evidence_pool.take_pending();
+    assert_eq!(evidence.len(), 1);
+    assert_eq!(evidence[0].offense.offender(), voter.pubkey());
+    assert_eq!(evidence[0].reporter, leader.pubkey());
+    assert_eq!(vote_credits, HashMap::from([(voter.pubkey(), 1), (late.pubkey(), 1)]));
+}
+
+#[test]
+fn block_stake_slashes_then_applies_what_still_fits() {
+    let (leader, voter, alice) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]), Keypair::from_seed([3; 32]));
+    let genesis = GenesisConfig {
+        creation_time: 0,
+        accounts: vec![GenesisAccount { pubkey: alice.pubkey(), balance: 100 }],
+        validators: vec![GenesisValidator { identity: voter.pubkey(), stake: 1_000, commission: 0 }],
+        poh: PohConfig::default(),
+        epoch_schedule: EpochSchedule::default(),
+        inflation: Default::default(),
+        slashing: Default::default(),
+    };
+    let mut stakes = StakeAccounts::from_genesis(&genesis);
+    let mut balances = HashMap::from([(alice.pubkey(), 100)]);
+
+    let mut detector = ConflictDetector::default();
+    detector.observe_vote(Vote::new(&block(&leader, &hex::encode(GENESIS_HASH), 0, 1), &voter));
+    let offense = detector.observe_vote(Vote::new(&block(&leader, &hex::encode(GENESIS_HASH), 0, 2), &voter)).unwrap();
+    let evidence = vec![Evidence::new(offense, &leader)];
+
+    let blockhash = hex::encode(genesis.hash());
+    let stake = Stake::new(&alice, voter.pubkey(), 30, blockhash.clone());
+    let overdraft = Stake::new(&alice, voter.pubkey(), 80, blockhash);
+    let instructions = vec![StakeInstruction::Delegate(stake.clone()), StakeInstruction::Delegate(overdraft)];
+
+    let (burned, applied) = apply_block_stake(&mut balances, &mut stakes, &evidence, instructions, 0, genesis.slashing.slash_fraction);
+    assert_eq!(burned, 50);
+    assert!(stakes.is_slashed(&voter.pubkey()));
+    assert!(matches!(&applied[..], [StakeInstruction::Delegate(applied)] if applied.stake_account() == stake.stake_account()));
+    assert_eq!(balances[&alice.pubkey()], 70);
+}
+
+#[test]
+fn repaired_blocks_must_come_from_the_leader() {
+    let (leader, identity, impostor) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]), Keypair::from_seed([3; 32]));
+    let clock = SimClock { now_ms: 10_000 };
+    let mut chain = ValidatorChain::new(Blockstore::in_memory(), GENESIS_HASH);
+    let mut outbox = Outbox::default();
+
+    // Chained and hashed correctly, but signed by someone else
+    let forged = block(&impostor, &hex::encode(GENESIS_HASH), 0, 1);
+    assert_eq!(chain.handle_repaired_blocks(&clock, &mut outbox, &identity, &leader.pubkey(), vec![forged]).unwrap(), 0);
+    assert_eq!(chain.blockstore.height(), 0);
+
+    let genuine = block(&leader, &hex::encode(GENESIS_HASH), 0, 1);
+    assert_eq!(chain.handle_repaired_blocks(&clock, &mut outbox, &identity, &leader.pubkey(), vec![genuine]).unwrap(), 1);
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use validator::genesis::{EpochSchedule, GenesisAccount, GenesisConfig, GenesisValidator, PohConfig};
+use validator::inflation::{distribute_rewards, epoch_duration_years, epoch_issuance, Inflation};
+use validator::stake::StakeAccounts;
+
+fn staked(validators: &[(&str, u64)]) -> StakeAccounts {
+    StakeAccounts::from_genesis(&GenesisConfig {
+        creation_time: 0,
+        accounts: Vec::<GenesisAccount>::new(),
+        validators: validators
+            .iter()
+            .map(|(identity, stake)| GenesisValidator { identity: identity.to_string(), stake: *stake, commission: 0 })
+            .collect(),
+        poh: PohConfig::default(),
+        epoch_schedule: EpochSchedule::default(),
+        inflation: Inflation::default(),
+        slashing: Default::default(),
+    })
+}
+
+fn stake_of(stakes: &StakeAccounts, validator_id: &str) -> u64 {
+    stakes.accounts().filter(|(_, account)| account.validator_id == validator_id).map(|(_, account)| account.amount).sum()
+}
+
+fn credits(entries: &[(&str, u64)]) -> HashMap<String, u64> {
+    entries.iter().map(|(identity, credits)| (identity.to_string(), *credits)).collect()
+}
+
+#[test]
+fn rate_tapers_down_to_terminal() {
+    let inflation = Inflation::default();
+    assert_eq!(inflation.rate(0.0), 0.08);
+    assert!((inflation.rate(1.0) - 0.068).abs() < 1e-12);
+    assert!(inflation.rate(2.0) < inflation.rate(1.0));
+    assert_eq!(inflation.rate(100.0), inflation.terminal);
+}
+
+#[test]
+fn issuance_scales_with_supply_and_epoch_length() {
+    let inflation = Inflation { initial: 0.1, taper: 0.0, terminal: 0.1 };
+    let poh = PohConfig { tick_duration_ms: 1000, ticks_per_slot: 1 };
+    // 315_576 one-second slots is a hundredth of a 365.25 day year
+    let epoch_schedule = EpochSchedule { slots_per_epoch: 315_576 };
+    let years = epoch_duration_years(&poh, &epoch_schedule);
+    assert!((years - 0.01).abs() < 1e-9);
+
+    let (rate, issuance) = epoch_issuance(&inflation, &poh, &epoch_schedule, 0, 1_000_000_000);
+    assert_eq!(rate, 0.1);
+    assert!((999_999..=1_000_000).contains(&issuance), "{}", issuance);
+}
+
+#[test]
+fn rewards_are_weighted_by_stake_and_credits() {
+    let mut stakes = staked(&[("validator_a", 100), ("validator_b", 300)]);
+    let mut balances = HashMap::new();
+    let commissions = HashMap::new();
+
+    // Points are 100 * 3 and 300 * 1, so the issuance splits evenly
+    let vote_credits = credits(&[("validator_a", 3), ("validator_b", 1)]);
+    let (total, commission) = distribute_rewards(&mut stakes, &mut balances, &vote_credits, &commissions, 1_000, 0);
+    assert_eq!((total, commission), (1_000, 0));
+    assert_eq!(stake_of(&stakes, "validator_a"), 600);
+    assert_eq!(stake_of(&stakes, "validator_b"), 800);
+    assert!(balances.is_empty());
+}
+
+#[test]
+fn commission_goes_to_the_validator_identity() {
+    let mut stakes = staked(&[("validator_a", 100)]);
+    let mut balances = HashMap::new();
+    let commissions: HashMap<String, u8> = [("validator_a".to_string(), 10)].into_iter().collect();
+
+    let vote_credits = credits(&[("validator_a", 5)]);
+    let (total, commission) = distribute_rewards(&mut stakes, &mut balances, &vote_credits, &commissions, 500, 0);
+    assert_eq!((total, commission), (500, 50));
+    assert_eq!(balances["validator_a"], 50);
+    assert_eq!(stake_of(&stakes, "validator_a"), 550);
+}
+
+#[test]
+fn nothing_is_paid_without_credits_or_active_stake() {
+    let mut stakes = staked(&[("validator_a", 100), ("validator_b", 100)]);
+    let mut balances = HashMap::new();
+    let commissions = HashMap::new();
+
+    assert_eq!(distribute_rewards(&mut stakes, &mut balances, &HashMap::new(), &commissions, 1_000, 0), (0, 0));
+    let vote_credits = credits(&[("validator_a", 0), ("validator_b", 0)]);
+    assert_eq!(distribute_rewards(&mut stakes, &mut balances, &vote_credits, &commissions, 1_000, 0), (0, 0));
+    assert_eq!(stakes.total_staked(), 200);
+
+    // Stake delegated in epoch 0 only activates at epoch 1, so it earns nothing yet
+    let mut balances: HashMap<String, u64> = [("carol".to_string(), 100)].into_iter().collect();
+    let mut stakes = StakeAccounts::default();
+    stakes.delegate(&mut balances, "carol-stake", "carol", "validator_a", 100, 0).unwrap();
+    let vote_credits = credits(&[("validator_a", 10)]);
+    assert_eq!(distribute_rewards(&mut stakes, &mut balances, &vote_credits, &commissions, 1_000, 0), (0, 0));
+    assert_eq!(distribute_rewards(&mut stakes, &mut balances, &vote_credits, &commissions, 1_000, 1), (1_000, 0));
+}
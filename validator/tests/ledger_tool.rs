@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use validator::block::Block;
+use validator::blockstore::Blockstore;
+use validator::consensus::build_block;
+use validator::genesis::{EpochSchedule, GenesisAccount, GenesisConfig, PohConfig};
+use validator::keypair::Keypair;
+use validator::sim::SimClock;
+use validator::stake::{Stake, StakeInstruction};
+use validator::transaction::Transaction;
+
+fn genesis(alice: &Keypair) -> GenesisConfig {
+    GenesisConfig {
+        creation_time: 0,
+        accounts: vec![GenesisAccount { pubkey: alice.pubkey(), balance: 100 }],
+        validators: Vec::new(),
+        poh: PohConfig::default(),
+        epoch_schedule: EpochSchedule::default(),
+        inflation: Default::default(),
+        slashing: Default::default(),
+    }
+}
+
+/// Three blocks on top of `genesis`, each moving `amount` from alice to bob.
+/// `balances` is the bank the leader built them against.
+fn chain(genesis: &GenesisConfig, alice: &Keypair, amount: u64, mut balances: HashMap<String, u64>) -> Vec<Block> {
+    let leader = Keypair::from_seed([1; 32]);
+    let mut parent_hash = genesis.hash();
+    (0..3)
+        .map(|parent_height| {
+            let transfer = Transaction::new(alice, "bob".to_string(), amount, hex::encode(parent_hash));
+            let clock = SimClock { now_ms: (parent_height + 1) * 1_000 };
+            let block = build_block(&clock, &leader, parent_hash, parent_height, vec![transfer], &mut balances, Vec::new(), Vec::new());
+            assert_eq!(block.transactions.len(), 1);
+            parent_hash.copy_from_slice(&hex::decode(&block.block_hash).unwrap());
+            block
+        })
+        .collect()
+}
+
+/// Writes the genesis and blocks into a fresh ledger and runs `ledger-tool`
+/// on it, returning whether it succeeded and what it printed.
+fn ledger_tool(name: &str, genesis: &GenesisConfig, blocks: Vec<Block>, args: &[&str]) -> (bool, String) {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("ledger-tool-{}-{}", name, std::process::id()));
+    let genesis_path = dir.join("genesis.json");
+    let mut blockstore = Blockstore::open(&dir).unwrap();
+    for block in blocks {
+        blockstore.insert(block).unwrap();
+    }
+    genesis.write(&genesis_path).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ledger-tool"))
+        .args(args)
+        .args(["--ledger", path_str(&dir), "--genesis", path_str(&genesis_path)])
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    (output.status.success(), String::from_utf8(output.stdout).unwrap())
+}
+
+fn path_str(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+fn genesis_balances(genesis: &GenesisConfig) -> HashMap<String, u64> {
+    genesis.accounts.iter().map(|account| (account.pubkey.clone(), account.balance)).collect()
+}
+
+#[test]
+fn verifies_a_good_ledger_and_replays_balances() {
+    let alice = Keypair::from_seed([2; 32]);
+    let genesis = genesis(&alice);
+    let blocks = chain(&genesis, &alice, 10, genesis_balances(&genesis));
+
+    let (ok, output) = ledger_tool("good", &genesis, blocks.clone(), &["verify"]);
+    assert!(ok, "{}", output);
+    assert!(output.contains("Replayed 3 blocks to height 3 (3 transactions, 0 stake instructions)"), "{}", output);
+
+    let (ok, output) = ledger_tool("balances", &genesis, blocks, &["balances", "--end", "2", "--account", "bob"]);
+    assert!(ok, "{}", output);
+    assert_eq!(output.trim(), "bob 20");
+}
+
+#[test]
+fn reports_a_tampered_block_hash() {
+    let alice = Keypair::from_seed([2; 32]);
+    let genesis = genesis(&alice);
+    let mut blocks = chain(&genesis, &alice, 10, genesis_balances(&genesis));
+    blocks[1].timestamp += 1;
+
+    let (ok, output) = ledger_tool("tampered", &genesis, blocks, &["verify"]);
+    assert!(!ok);
+    assert!(output.contains("divergence at height 2: block hash"), "{}", output);
+}
+
+#[test]
+fn reports_a_broken_parent_link() {
+    let alice = Keypair::from_seed([2; 32]);
+    let genesis = genesis(&alice);
+    let mut blocks = chain(&genesis, &alice, 10, genesis_balances(&genesis));
+    blocks.remove(1);
+    let other = chain(&genesis, &alice, 11, genesis_balances(&genesis));
+    blocks.insert(1, other[1].clone());
+
+    let (ok, output) = ledger_tool("parent", &genesis, blocks, &["verify"]);
+    assert!(!ok);
+    assert!(output.contains("divergence at height 2: parent hash"), "{}", output);
+}
+
+#[test]
+fn reports_a_transfer_the_bank_cannot_execute() {
+    let alice = Keypair::from_seed([2; 32]);
+    let genesis = genesis(&alice);
+    // Built against a bank where alice held more than genesis gave her
+    let richer = HashMap::from([(alice.pubkey(), 1_000)]);
+    let blocks = chain(&genesis, &alice, 40, richer);
+
+    let (ok, output) = ledger_tool("overdraft", &genesis, blocks, &["verify"]);
+    assert!(!ok);
+    assert!(output.contains("divergence at height 3: transaction"), "{}", output);
+    assert!(output.contains("insufficient funds"), "{}", output);
+}
+
+/// One block on top of `genesis` carrying `stake_instructions`.
+fn stake_block(genesis: &GenesisConfig, stake_instructions: Vec<StakeInstruction>) -> Block {
+    let leader = Keypair::from_seed([1; 32]);
+    let clock = SimClock { now_ms: 1_000 };
+    build_block(&clock, &leader, genesis.hash(), 0, Vec::new(), &mut HashMap::new(), Vec::new(), stake_instructions)
+}
+
+#[test]
+fn replays_stake_instructions() {
+    let alice = Keypair::from_seed([2; 32]);
+    let genesis = genesis(&alice);
+    let stake = Stake::new(&alice, "validator_a".to_string(), 30, hex::encode(genesis.hash()));
+    let blocks = vec![stake_block(&genesis, vec![StakeInstruction::Delegate(stake)])];
+
+    let (ok, output) = ledger_tool("stake", &genesis, blocks.clone(), &["verify"]);
+    assert!(ok, "{}", output);
+    assert!(output.contains("(0 transactions, 1 stake instructions)"), "{}", output);
+
+    let (ok, output) = ledger_tool("stake-balances", &genesis, blocks, &["balances", "--account", &alice.pubkey()]);
+    assert!(ok, "{}", output);
+    assert_eq!(output.trim(), format!("{} 70", alice.pubkey()));
+}
+
+#[test]
+fn reports_a_stake_instruction_the_bank_cannot_apply() {
+    let alice = Keypair::from_seed([2; 32]);
+    let genesis = genesis(&alice);
+    let stake = Stake::new(&alice, "validator_a".to_string(), 300, hex::encode(genesis.hash()));
+    let blocks = vec![stake_block(&genesis, vec![StakeInstruction::Delegate(stake)])];
+
+    let (ok, output) = ledger_tool("stake-overdraft", &genesis, blocks, &["verify"]);
+    assert!(!ok);
+    assert!(output.contains("divergence at height 1: stake instruction"), "{}", output);
+
+    let mut forged = Stake::new(&alice, "validator_a".to_string(), 30, hex::encode(genesis.hash()));
+    forged.amount = 40;
+    let blocks = vec![stake_block(&genesis, vec![StakeInstruction::Delegate(forged)])];
+    let (ok, output) = ledger_tool("stake-forged", &genesis, blocks, &["verify"]);
+    assert!(!ok);
+    assert!(output.contains("divergence at height 1: stake instruction signature"), "{}", output);
+}
@@ -0,0 +1,42 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use validator::metrics::{serve_metrics, MAX_REQUEST_HEAD_BYTES};
+
+/// Sends `parts` as separate writes and returns the whole response.
+async fn request(parts: &[&[u8]]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_metrics(listener));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    for part in parts {
+        stream.write_all(part).await.unwrap();
+        stream.flush().await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn serves_metrics_once_the_headers_arrive() {
+    let response = request(&[b"GET /metrics HTTP/1.1\r\n", b"Host: localhost\r\n", b"\r\n"]).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("poh_hashes"));
+}
+
+#[tokio::test]
+async fn other_paths_are_not_found() {
+    let response = request(&[b"GET /status HTTP/1.1\r\n\r\n"]).await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", response);
+}
+
+#[tokio::test]
+async fn refuses_oversized_headers() {
+    // One byte over, all of which the server reads, so closing doesn't reset the connection
+    let request_line = b"GET /metrics HTTP/1.1\r\n";
+    let padding = "a".repeat(MAX_REQUEST_HEAD_BYTES + 1 - request_line.len());
+    let response = request(&[request_line, padding.as_bytes()]).await;
+    assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+}
@@ -0,0 +1,22 @@
+use std::io::ErrorKind;
+use tokio::io::AsyncWriteExt;
+use validator::block::Message;
+use validator::network::{read_message, write_message, MAX_FRAME_BYTES};
+
+#[tokio::test]
+async fn bad_frames_are_skipped_and_oversized_ones_refused() {
+    let (mut client, mut server) = tokio::io::duplex(1024);
+
+    // Garbage is reported as such and the next frame still reads
+    client.write_all(&3u32.to_be_bytes()).await.unwrap();
+    client.write_all(b"{{{").await.unwrap();
+    write_message(&mut client, &Message::SnapshotRequest).await.unwrap();
+    let error = read_message::<_, Message>(&mut server).await.unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(matches!(read_message(&mut server).await.unwrap(), Message::SnapshotRequest));
+
+    // Only the length is read before a frame that is too big is refused
+    client.write_all(&(MAX_FRAME_BYTES as u32 + 1).to_be_bytes()).await.unwrap();
+    let error = read_message::<_, Message>(&mut server).await.unwrap_err();
+    assert_ne!(error.kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+use validator::catchup::CatchUp;
+use validator::poh_handler::{poh_hash, PohCheckpoint, PohEntry, PohHistory, POH_CHECKPOINT_INTERVAL};
+use validator::repair::RepairRequest;
+use validator::sim::SimClock;
+
+const GENESIS: [u8; 32] = [9; 32];
+
+/// `count` entries chaining on from `prev_hash`.
+fn chain(prev_hash: &[u8], count: usize) -> Vec<PohEntry> {
+    let mut hash = prev_hash.to_vec();
+    (0..count as u64)
+        .map(|timestamp| {
+            hash = poh_hash(&hash, timestamp);
+            PohEntry { timestamp, hash: hash.clone() }
+        })
+        .collect()
+}
+
+fn history_of(count: usize) -> PohHistory {
+    let mut history = PohHistory::new(GENESIS).with_max_entries(POH_CHECKPOINT_INTERVAL);
+    assert_eq!(history.append_verified(0, chain(&GENESIS, count)).unwrap(), Ok(count));
+    history
+}
+
+#[test]
+fn evicts_up_to_a_checkpoint_past_max_entries() {
+    let history = history_of(1_499);
+    assert_eq!(history.anchor().height, 0);
+    assert_eq!(history.entries().len(), 1_499);
+
+    // Half the budget is kept, cut at the checkpoint at 1000
+    let history = history_of(1_500);
+    assert_eq!(history.len(), 1_500);
+    assert_eq!(history.anchor().height, 1_000);
+    assert_eq!(history.entries().len(), 500);
+    assert_eq!(history.hash_at(999), None);
+    assert_eq!(history.hash_at(1_000), Some(history.anchor().hash.clone()));
+    assert!(history.range(0, 10).is_empty());
+    assert_eq!(history.range(1_000, 1_010).len(), 10);
+}
+
+#[test]
+fn checkpoints_every_interval() {
+    let history = history_of(2_000);
+    let checkpoint = history.latest_checkpoint().unwrap();
+    assert_eq!(checkpoint.height, 2_000);
+    assert_eq!(checkpoint.hash, history.last_hash());
+
+    let history = history_of(2_999);
+    assert_eq!(history.latest_checkpoint().unwrap().height, 2_000);
+    assert_eq!(history.hash_at(2_000), Some(history.latest_checkpoint().unwrap().hash.clone()));
+}
+
+#[test]
+fn append_verified_only_extends_the_tip() {
+    let mut history = history_of(10);
+    let next = chain(&history.last_hash(), 5);
+
+    // Entries that don't start at our tip are skipped
+    assert_eq!(history.append_verified(9, next.clone()).unwrap(), Ok(0));
+    assert_eq!(history.append_verified(10, Vec::new()).unwrap(), Ok(0));
+
+    // A broken link reports its absolute index and appends nothing
+    let mut broken = next.clone();
+    broken[2].hash = vec![0; 32];
+    assert_eq!(history.append_verified(10, broken).unwrap(), Err(12));
+    assert_eq!(history.len(), 10);
+
+    assert_eq!(history.append_verified(10, next).unwrap(), Ok(5));
+    assert_eq!(history.len(), 15);
+}
+
+#[test]
+fn reset_to_restarts_from_a_checkpoint() {
+    let source = history_of(2_000);
+    let checkpoint = source.latest_checkpoint().unwrap().clone();
+
+    let mut history = history_of(10);
+    history.reset_to(Some(checkpoint.clone())).unwrap();
+    assert_eq!(history.len(), 2_000);
+    assert_eq!(history.anchor(), &checkpoint);
+    assert_eq!(history.last_hash(), checkpoint.hash);
+
+    // Entries verify from the checkpoint alone
+    assert_eq!(history.append_verified(2_000, chain(&checkpoint.hash, 3)).unwrap(), Ok(3));
+
+    history.reset_to(None).unwrap();
+    assert_eq!(history.len(), 0);
+    assert_eq!(history.anchor(), &PohCheckpoint { height: 0, hash: GENESIS.to_vec() });
+}
+
+#[test]
+fn append_verified_reports_write_failures() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("poh-history-{}", std::process::id()));
+    let mut history = PohHistory::open(&dir, GENESIS).unwrap();
+    assert_eq!(history.append_verified(0, chain(&GENESIS, 3)).unwrap(), Ok(3));
+    assert_eq!(PohHistory::open(&dir, GENESIS).unwrap().len(), 3);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(history.append_verified(3, chain(&history.last_hash(), 3)).is_err());
+}
+
+#[test]
+fn repaired_poh_must_lead_to_the_leaders_target() {
+    let mut history = history_of(10);
+    let leaders = chain(&GENESIS, 30);
+    let mut catch_up = CatchUp::new(GENESIS);
+    catch_up.set_poh_target(30, leaders[29].hash.clone());
+
+    // Entries anyone could compute from our tip chain fine, but miss the target
+    let mut hash = history.last_hash();
+    let forged: Vec<PohEntry> = (0..20)
+        .map(|timestamp| {
+            hash = poh_hash(&hash, 1_000 + timestamp);
+            PohEntry { timestamp: 1_000 + timestamp, hash: hash.clone() }
+        })
+        .collect();
+    assert!(catch_up.take_poh_repair(&history, 10, forged).is_none());
+    assert!(catch_up.take_poh_repair(&history, 11, leaders[11..20].to_vec()).is_none());
+
+    // The leader's entries are held until they reach the target
+    assert_eq!(catch_up.take_poh_repair(&history, 10, leaders[10..20].to_vec()).unwrap().len(), 0);
+    assert_eq!(catch_up.next_poh_repair(&history), Some(RepairRequest::Poh { start: 20, end: 30 }));
+    let entries = catch_up.take_poh_repair(&history, 20, leaders[20..].to_vec()).unwrap();
+    assert_eq!(history.append_verified(10, entries).unwrap(), Ok(20));
+    assert_eq!(history.last_hash(), leaders[29].hash);
+    assert_eq!(catch_up.next_poh_repair(&history), None);
+}
+
+#[test]
+fn ticks_chain_from_the_last_entry_at_the_clocks_time() {
+    let mut history = PohHistory::new([3; 32]);
+    history.tick(&SimClock { now_ms: 5_000 }).unwrap();
+    history.tick(&SimClock { now_ms: 6_400 }).unwrap();
+    let entries = history.entries();
+    assert_eq!((entries[0].timestamp, entries[1].timestamp), (5, 6));
+    assert_eq!(entries[0].hash, poh_hash(&[3; 32], 5));
+    assert_eq!(entries[1].hash, poh_hash(&entries[0].hash, 6));
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use validator::block::Block;
+use validator::blockstore::Blockstore;
+use validator::consensus::build_block;
+use validator::keypair::Keypair;
+use validator::poh_handler::PohHistory;
+use validator::repair::{
+    shred_block, RepairRequest, RepairService, Shred, ShredCollector, SignedRepairRequest, MAX_SHREDS_PER_BLOCK,
+};
+use validator::sim::SimClock;
+
+/// A block that takes `size` bytes of leader name to serialize, so it
+/// spans several shreds.
+fn large_block(height: u64, size: usize) -> Block {
+    let clock = SimClock { now_ms: 1_000 };
+    let mut block =
+        build_block(&clock, &Keypair::from_seed([1; 32]), [0; 32], height - 1, Vec::new(), &mut HashMap::new(), Vec::new(), Vec::new());
+    block.leader = "x".repeat(size);
+    block
+}
+
+#[test]
+fn shreds_rebuild_the_block_in_any_order() {
+    let block = large_block(3, 200_000);
+    let mut shreds = shred_block(&block);
+    assert_eq!(shreds.len(), 4);
+    shreds.reverse();
+
+    let mut collector = ShredCollector::default();
+    let last = shreds.pop().unwrap();
+    for (received, shred) in shreds.into_iter().enumerate() {
+        assert_eq!(collector.insert(shred.clone()).unwrap_err().len(), 3 - received);
+        // A repeated shred changes nothing
+        assert_eq!(collector.insert(shred).unwrap_err().len(), 3 - received);
+    }
+    let rebuilt = collector.insert(last).unwrap();
+    assert_eq!(rebuilt.block_hash, block.block_hash);
+    assert_eq!(rebuilt.leader, block.leader);
+    assert_eq!(collector.pending_blocks(), 0);
+}
+
+#[test]
+fn malformed_shreds_are_dropped() {
+    let shred = |index, num_shreds, payload_size| Shred { block_height: 1, index, num_shreds, payload: vec![0; payload_size] };
+    let mut collector = ShredCollector::default();
+    for bad in [shred(0, 0, 1), shred(0, MAX_SHREDS_PER_BLOCK + 1, 1), shred(2, 2, 1), shred(0, 2, 64 * 1024 + 1)] {
+        assert_eq!(collector.insert(bad).unwrap_err(), Vec::<usize>::new());
+    }
+    assert_eq!(collector.pending_blocks(), 0);
+
+    // Every shred of a block must agree on the count
+    assert_eq!(collector.insert(shred(0, 3, 1)).unwrap_err(), vec![1, 2]);
+    assert_eq!(collector.insert(shred(1, 200, 1)).unwrap_err(), Vec::<usize>::new());
+    assert_eq!(collector.insert(shred(1, 3, 1)).unwrap_err(), vec![2]);
+}
+
+#[test]
+fn pending_blocks_are_bounded() {
+    let shred = |block_height| Shred { block_height, index: 0, num_shreds: 2, payload: vec![0] };
+    let mut collector = ShredCollector::default();
+    for height in 10..30 {
+        collector.insert(shred(height)).unwrap_err();
+    }
+    assert_eq!(collector.pending_blocks(), 8);
+
+    // Lower heights are needed sooner, so they push out the highest
+    assert_eq!(collector.insert(shred(5)).unwrap_err(), vec![1]);
+    assert_eq!(collector.insert(shred(40)).unwrap_err(), Vec::<usize>::new());
+    assert_eq!(collector.pending_blocks(), 8);
+
+    collector.purge_below(12);
+    assert_eq!(collector.pending_blocks(), 5);
+}
+
+#[test]
+fn repairs_are_rate_limited_per_requester() {
+    let (blockstore, poh) = (Blockstore::in_memory(), PohHistory::new([0; 32]));
+    let request = RepairRequest::Poh { start: 0, end: 10 };
+    let mut service = RepairService::new(2);
+    let at = |now_ms| SimClock { now_ms };
+
+    assert!(service.serve(&at(1_000), "alice", &request, &blockstore, &poh).is_some());
+    assert!(service.serve(&at(1_500), "alice", &request, &blockstore, &poh).is_some());
+    assert!(service.serve(&at(1_900), "alice", &request, &blockstore, &poh).is_none());
+    assert!(service.serve(&at(1_900), "bob", &request, &blockstore, &poh).is_some());
+    // A new window starts a second after the last one did
+    assert!(service.serve(&at(2_000), "alice", &request, &blockstore, &poh).is_some());
+}
+
+#[test]
+fn repair_requests_are_signed_and_recent() {
+    let keypair = Keypair::from_seed([2; 32]);
+    let signed = SignedRepairRequest::new_signed(&keypair, RepairRequest::Blocks { start: 1, end: 4 }, 50_000);
+    assert_eq!(signed.requester, keypair.pubkey());
+    assert!(signed.verify(50_000));
+    assert!(signed.verify(59_000));
+    assert!(!signed.verify(70_000));
+
+    // Naming someone else as the requester breaks the signature
+    let mut forged = signed.clone();
+    forged.requester = Keypair::from_seed([3; 32]).pubkey();
+    assert!(!forged.verify(50_000));
+    let mut forged = signed;
+    forged.request = RepairRequest::Blocks { start: 1, end: 100 };
+    assert!(!forged.verify(50_000));
+}
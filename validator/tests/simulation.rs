@@ -0,0 +1,46 @@
+use validator::genesis::EpochSchedule;
+use validator::sim::{Faults, SimConfig, SimReport, Simulation};
+
+fn run(seed: u64, faults: Faults) -> SimReport {
+    Simulation::new(SimConfig { seed, validators: 3, slots: 20, faults, ..SimConfig::default() }).run()
+}
+
+fn lossy() -> Faults {
+    Faults { max_delay_ms: 600, drop_rate: 0.2, reorder: true, ..Faults::default() }
+}
+
+#[test]
+fn same_seed_replays_the_same_run() {
+    let first = run(7, lossy());
+    assert_eq!(first, run(7, lossy()));
+    assert_ne!(first.fingerprint, run(8, lossy()).fingerprint);
+}
+
+#[test]
+fn reliable_network_confirms_every_slot() {
+    let report = run(1, Faults::default());
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert_eq!(report.leader_height, 20);
+    assert_eq!(report.confirmed_slots, 20);
+    assert_eq!(report.validator_heights, vec![20, 20, 20]);
+    assert!(report.transactions > 0);
+}
+
+#[test]
+fn lossy_network_keeps_ledgers_consistent() {
+    for seed in 0..3 {
+        let report = run(seed, lossy());
+        assert!(report.violations.is_empty(), "{}: {:?}", report, report.violations);
+    }
+}
+
+#[test]
+fn blocks_past_an_epoch_carry_stake_and_pay_rewards() {
+    let slots = EpochSchedule::default().slots_per_epoch + 5;
+    // An epoch is a sliver of a year, so it takes a large supply to issue anything
+    let stake = 10_000_000_000_000;
+    let report = Simulation::new(SimConfig { seed: 3, validators: 3, slots, stake, ..SimConfig::default() }).run();
+    assert!(report.violations.is_empty(), "{:?}", report.violations);
+    assert!(report.stake_instructions > 0);
+    assert!(report.rewards > 0);
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use validator::block::{Block, Vote};
+use validator::consensus::build_block;
+use validator::genesis::{EpochSchedule, GenesisConfig, GenesisValidator, PohConfig};
+use validator::inflation::distribute_rewards;
+use validator::keypair::Keypair;
+use validator::sim::SimClock;
+use validator::slashing::{ConflictDetector, Evidence, EvidencePool, Offense};
+use validator::stake::StakeAccounts;
+
+/// A signed, empty block at `parent_height + 1`; the timestamp makes it unique.
+fn block(leader: &Keypair, parent_height: u64, timestamp_secs: u64) -> Block {
+    let clock = SimClock { now_ms: timestamp_secs * 1000 };
+    build_block(&clock, leader, [0; 32], parent_height, Vec::new(), &mut HashMap::new(), Vec::new(), Vec::new())
+}
+
+fn double_vote(voter: &Keypair, leader: &Keypair, slot: u64) -> Offense {
+    let mut detector = ConflictDetector::default();
+    assert_eq!(detector.observe_vote(Vote::new(&block(leader, slot - 1, 1), voter)), None);
+    detector.observe_vote(Vote::new(&block(leader, slot - 1, 2), voter)).unwrap()
+}
+
+#[test]
+fn detects_double_votes() {
+    let (leader, voter) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let first = block(&leader, 4, 1);
+    let mut detector = ConflictDetector::default();
+
+    assert_eq!(detector.observe_vote(Vote::new(&first, &voter)), None);
+    // Voting for the same block again is not an offense, nor is another voter
+    assert_eq!(detector.observe_vote(Vote::new(&first, &voter)), None);
+    assert_eq!(detector.observe_vote(Vote::new(&block(&leader, 4, 2), &Keypair::from_seed([3; 32]))), None);
+
+    let offense = detector.observe_vote(Vote::new(&block(&leader, 4, 2), &voter)).unwrap();
+    assert!(matches!(offense, Offense::DoubleVote { .. }));
+    assert_eq!(offense.offender(), voter.pubkey());
+    assert_eq!(offense.slot(), 5);
+    assert!(offense.is_valid());
+}
+
+#[test]
+fn detects_duplicate_blocks() {
+    let leader = Keypair::from_seed([1; 32]);
+    let first = block(&leader, 9, 1);
+    let mut detector = ConflictDetector::default();
+
+    assert_eq!(detector.observe_block(first.header()), None);
+    assert_eq!(detector.observe_block(first.header()), None);
+    assert_eq!(detector.observe_block(block(&leader, 10, 2).header()), None);
+
+    let offense = detector.observe_block(block(&leader, 9, 2).header()).unwrap();
+    assert!(matches!(offense, Offense::DuplicateBlock { .. }));
+    assert_eq!(offense.offender(), leader.pubkey());
+    assert_eq!(offense.slot(), 10);
+    assert!(offense.is_valid());
+
+    // Purged slots are forgotten
+    detector.purge_below(11);
+    assert_eq!(detector.observe_block(block(&leader, 9, 3).header()), None);
+}
+
+#[test]
+fn forged_offenses_are_invalid() {
+    let (leader, voter) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let offense = double_vote(&voter, &leader, 3);
+    let Offense::DoubleVote { first, mut second } = offense else { unreachable!() };
+
+    // The same vote twice is no conflict
+    assert!(!Offense::DoubleVote { first: first.clone(), second: first.clone() }.is_valid());
+    // A vote the offender never signed
+    second.block_hash = "00".repeat(32);
+    assert!(!Offense::DoubleVote { first, second }.is_valid());
+}
+
+#[test]
+fn evidence_pool_dedups_by_offense() {
+    let (leader, voter) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let offense = double_vote(&voter, &leader, 3);
+    let mut pool = EvidencePool::default();
+
+    assert!(pool.add(Evidence::new(offense.clone(), &leader)));
+    // Another reporter of the same offense, or the same report again, is ignored
+    assert!(!pool.add(Evidence::new(offense.clone(), &Keypair::from_seed([4; 32]))));
+    assert!(!pool.add(Evidence::new(offense.clone(), &leader)));
+    assert_eq!(pool.take_pending().len(), 1);
+    assert!(pool.take_pending().is_empty());
+    assert!(!pool.add(Evidence::new(offense, &leader)));
+
+    // A report whose signature doesn't match its reporter is refused
+    let mut forged = Evidence::new(double_vote(&voter, &leader, 4), &leader);
+    forged.reporter = voter.pubkey();
+    assert!(!pool.add(forged));
+}
+
+#[test]
+fn evidence_pool_refuses_purged_slots() {
+    let (leader, voter) = (Keypair::from_seed([1; 32]), Keypair::from_seed([2; 32]));
+    let mut pool = EvidencePool::default();
+    assert!(pool.add(Evidence::new(double_vote(&voter, &leader, 3), &leader)));
+
+    // Once slot 3 is purged its offense can't be reported again, though
+    // later slots still can
+    pool.purge_below(5);
+    assert!(!pool.add(Evidence::new(double_vote(&voter, &leader, 3), &leader)));
+    assert!(!pool.add(Evidence::new(double_vote(&voter, &leader, 4), &leader)));
+    assert!(pool.add(Evidence::new(double_vote(&voter, &leader, 5), &leader)));
+}
+
+#[test]
+fn slashing_burns_stake_and_stops_rewards() {
+    let genesis = GenesisConfig {
+        creation_time: 0,
+        accounts: Vec::new(),
+        validators: vec![
+            GenesisValidator { identity: "validator_a".to_string(), stake: 1_000, commission: 0 },
+            GenesisValidator { identity: "validator_b".to_string(), stake: 1_000, commission: 0 },
+        ],
+        poh: PohConfig::default(),
+        epoch_schedule: EpochSchedule::default(),
+        inflation: Default::default(),
+        slashing: Default::default(),
+    };
+    let mut stakes = StakeAccounts::from_genesis(&genesis);
+    let mut balances = HashMap::new();
+
+    assert_eq!(stakes.slash("validator_a", genesis.slashing.slash_fraction), 50);
+    assert!(stakes.is_slashed("validator_a"));
+    assert_eq!(stakes.total_staked(), 1_950);
+    assert_eq!(stakes.active_stakes(0).keys().collect::<Vec<_>>(), vec!["validator_b"]);
+
+    // Slashed stake keeps no share of the rewards
+    let vote_credits: HashMap<String, u64> =
+        [("validator_a".to_string(), 10), ("validator_b".to_string(), 10)].into_iter().collect();
+    assert_eq!(distribute_rewards(&mut stakes, &mut balances, &vote_credits, &HashMap::new(), 100, 0), (100, 0));
+    assert_eq!(stakes.total_staked(), 2_050);
+    assert_eq!(stakes.active_stakes(0)["validator_b"], 1_100);
+
+    // The fraction is clamped, so slashing never burns more than the stake
+    assert_eq!(stakes.slash("validator_b", 2.0), 1_100);
+    assert_eq!(stakes.total_staked(), 950);
+}
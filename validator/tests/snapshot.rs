@@ -0,0 +1,96 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use validator::blockstore::Blockstore;
+use validator::consensus::build_block;
+use validator::keypair::Keypair;
+use validator::poh_handler::PohCheckpoint;
+use validator::sim::SimClock;
+use validator::snapshot::{Snapshot, SnapshotArchive, MAX_SNAPSHOTS_RETAINED};
+use validator::stake::StakeAccounts;
+
+fn snapshot(slot: u64) -> Snapshot {
+    Snapshot {
+        genesis_hash: "genesis".to_string(),
+        slot,
+        block_hash: format!("{:064x}", slot),
+        poh: PohCheckpoint { height: slot as usize * 10, hash: vec![1; 32] },
+        balances: BTreeMap::from([("alice".to_string(), 100), ("bob".to_string(), 5)]),
+        stakes: StakeAccounts::default(),
+        vote_credits: BTreeMap::from([("validator_a".to_string(), 3)]),
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn archive_then_unpack_round_trips() {
+    let archive = snapshot(7).archive().unwrap();
+    assert_eq!(archive.slot, 7);
+    let unpacked = archive.unpack().unwrap();
+    assert_eq!(unpacked.slot, 7);
+    assert_eq!(unpacked.block_hash, snapshot(7).block_hash);
+    assert_eq!(unpacked.poh, snapshot(7).poh);
+    assert_eq!(unpacked.balances, snapshot(7).balances);
+    assert_eq!(unpacked.vote_credits, snapshot(7).vote_credits);
+}
+
+#[test]
+fn unpack_rejects_corrupt_archives() {
+    let archive = snapshot(7).archive().unwrap();
+
+    let mut corrupt = archive.clone();
+    let last = corrupt.data.len() - 1;
+    corrupt.data[last] ^= 1;
+    assert_eq!(corrupt.unpack().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    // The slot outside the archive must match the one inside
+    let mut relabeled = archive;
+    relabeled.slot = 8;
+    assert!(relabeled.unpack().is_err());
+}
+
+#[test]
+fn save_keeps_only_the_newest_archives() {
+    let dir = temp_dir("snapshots");
+    assert!(SnapshotArchive::latest(&dir).unwrap().is_none());
+    for slot in [100, 300, 200] {
+        snapshot(slot).archive().unwrap().save(&dir).unwrap();
+    }
+
+    let latest = SnapshotArchive::latest(&dir).unwrap().unwrap();
+    assert_eq!(latest.slot, 300);
+    assert_eq!(latest.unpack().unwrap().slot, 300);
+    let remaining = std::fs::read_dir(dir.join("snapshots")).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(remaining, MAX_SNAPSHOTS_RETAINED);
+}
+
+#[test]
+fn set_base_starts_the_chain_after_a_snapshot() {
+    let leader = Keypair::from_seed([1; 32]);
+    let block = |parent_height| {
+        build_block(&SimClock { now_ms: 1_000 }, &leader, [0; 32], parent_height, Vec::new(), &mut HashMap::new(), Vec::new(), Vec::new())
+    };
+    let mut blockstore = Blockstore::in_memory();
+    for parent_height in 0..6 {
+        blockstore.insert(block(parent_height)).unwrap();
+    }
+
+    // Blocks at or below the base are dropped and can't be added back
+    blockstore.set_base(4, "base".to_string());
+    assert_eq!(blockstore.base(), Some((4, "base")));
+    assert_eq!(blockstore.len(), 2);
+    assert!(blockstore.get(4).is_none());
+    assert!(!blockstore.insert(block(3)).unwrap());
+    assert_eq!(blockstore.height(), 6);
+
+    let mut fresh = Blockstore::in_memory();
+    fresh.set_base(10, "base".to_string());
+    assert!(fresh.is_empty());
+    assert_eq!(fresh.height(), 10);
+    assert_eq!(fresh.tip_hash().as_deref(), Some("base"));
+    assert!(fresh.insert(block(10)).unwrap());
+    assert_eq!(fresh.height(), 11);
+}
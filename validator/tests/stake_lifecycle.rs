@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use validator::keypair::Keypair;
+use validator::stake::{Stake, StakeAccountAction, StakeAccounts, StakeAction, StakeError, StakeInstruction};
+
+fn funded(staker: &str, balance: u64) -> HashMap<String, u64> {
+    let mut balances = HashMap::new();
+    balances.insert(staker.to_string(), balance);
+    balances
+}
+
+#[test]
+fn delegate_activate_deactivate_withdraw() {
+    let mut balances = funded("alice", 1000);
+    let mut stakes = StakeAccounts::default();
+
+    // Delegating in epoch 3 debits the balance but only activates at epoch 4
+    let account = "stake-1".to_string();
+    stakes.delegate(&mut balances, &account, "alice", "validator_a", 600, 3).unwrap();
+    assert_eq!(balances["alice"], 400);
+    assert!(stakes.active_stakes(3).is_empty());
+    assert_eq!(stakes.active_stakes(4).get("validator_a"), Some(&600));
+
+    // Deactivating in epoch 5 keeps it in the schedule until the boundary
+    stakes.deactivate(&account, "alice", 5).unwrap();
+    assert_eq!(stakes.active_stakes(5).get("validator_a"), Some(&600));
+    assert!(stakes.active_stakes(6).is_empty());
+
+    // Withdrawal waits for the cooldown epoch
+    assert_eq!(
+        stakes.withdraw(&mut balances, &account, "alice", 6),
+        Err(StakeError::CoolingDown { stake_account: account.clone(), withdrawable_epoch: 7 })
+    );
+    assert_eq!(stakes.withdraw(&mut balances, &account, "alice", 7), Ok(600));
+    assert_eq!(balances["alice"], 1000);
+    assert!(stakes.get(&account).is_none());
+    assert_eq!(stakes.total_staked(), 0);
+}
+
+#[test]
+fn rejects_invalid_stake_instructions() {
+    let mut balances = funded("alice", 100);
+    let mut stakes = StakeAccounts::default();
+
+    assert_eq!(
+        stakes.delegate(&mut balances, "stake-1", "alice", "validator_a", 101, 0),
+        Err(StakeError::InsufficientFunds { balance: 100, amount: 101 })
+    );
+    assert_eq!(stakes.delegate(&mut balances, "stake-1", "alice", "validator_a", 0, 0), Err(StakeError::ZeroAmount));
+    assert!(matches!(
+        stakes.delegate(&mut balances, "stake-1", "mallory", "validator_a", 1, 0),
+        Err(StakeError::InsufficientFunds { balance: 0, .. })
+    ));
+
+    let account = "stake-1".to_string();
+    stakes.delegate(&mut balances, &account, "alice", "validator_a", 60, 0).unwrap();
+    assert_eq!(
+        stakes.delegate(&mut balances, &account, "alice", "validator_a", 40, 0),
+        Err(StakeError::AccountExists(account.clone()))
+    );
+    assert_eq!(balances["alice"], 40);
+    assert_eq!(stakes.withdraw(&mut balances, &account, "alice", 5), Err(StakeError::NotDeactivated(account.clone())));
+    assert_eq!(stakes.deactivate(&account, "mallory", 1), Err(StakeError::WrongStaker(account.clone())));
+
+    stakes.deactivate(&account, "alice", 1).unwrap();
+    assert_eq!(stakes.deactivate(&account, "alice", 1), Err(StakeError::AlreadyDeactivated(account.clone())));
+    assert_eq!(
+        stakes.withdraw(&mut balances, "missing", "alice", 5),
+        Err(StakeError::AccountNotFound("missing".to_string()))
+    );
+}
+
+#[test]
+fn delegations_are_addressed_by_signature() {
+    let alice = Keypair::from_seed([1; 32]);
+    let first = Stake::new(&alice, "validator_a".to_string(), 10, "blockhash-1".to_string());
+    let again = Stake::new(&alice, "validator_a".to_string(), 10, "blockhash-2".to_string());
+    assert_eq!(first.stake_account(), first.clone().stake_account());
+    assert_ne!(first.stake_account(), again.stake_account());
+}
+
+#[test]
+fn instructions_check_then_apply() {
+    let alice = Keypair::from_seed([1; 32]);
+    let mut balances = funded(&alice.pubkey(), 100);
+    let mut stakes = StakeAccounts::default();
+    let stake = Stake::new(&alice, "validator_a".to_string(), 70, "blockhash".to_string());
+    let account = stake.stake_account();
+    let delegate = StakeInstruction::Delegate(stake);
+    let deactivate =
+        StakeInstruction::Deactivate(StakeAccountAction::new(&alice, account.clone(), StakeAction::Deactivate, "blockhash".to_string()));
+    let withdraw =
+        StakeInstruction::Withdraw(StakeAccountAction::new(&alice, account.clone(), StakeAction::Withdraw, "blockhash".to_string()));
+    for instruction in [&delegate, &deactivate, &withdraw] {
+        assert!(instruction.verify_signature());
+    }
+
+    // Checking changes nothing
+    assert_eq!(stakes.check(&balances, &delegate, 0), Ok(0));
+    assert_eq!(stakes.check(&balances, &deactivate, 0), Err(StakeError::AccountNotFound(account.clone())));
+    assert_eq!(balances[&alice.pubkey()], 100);
+
+    stakes.apply(&mut balances, &delegate, 0).unwrap();
+    assert_eq!(stakes.get(&account).unwrap().amount, 70);
+    assert_eq!(balances[&alice.pubkey()], 30);
+    // The same delegation can't land twice
+    assert_eq!(stakes.check(&balances, &delegate, 0), Err(StakeError::InsufficientFunds { balance: 30, amount: 70 }));
+
+    stakes.apply(&mut balances, &deactivate, 0).unwrap();
+    assert_eq!(stakes.check(&balances, &withdraw, 1), Err(StakeError::CoolingDown { stake_account: account.clone(), withdrawable_epoch: 2 }));
+    assert_eq!(stakes.check(&balances, &withdraw, 2), Ok(70));
+    stakes.apply(&mut balances, &withdraw, 2).unwrap();
+    assert_eq!(balances[&alice.pubkey()], 100);
+    assert!(stakes.get(&account).is_none());
+}